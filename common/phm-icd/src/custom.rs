@@ -0,0 +1,56 @@
+//! Board-specific commands
+//!
+//! Workers can handle commands of their own, without changes to this crate. These are
+//! sent as [ToMcu::Custom](crate::ToMcu::Custom) with an id chosen by the board, and a
+//! payload in any format.
+//!
+//! Commands shared between firmware and host can be defined as serde types with
+//! [CustomCommand], which are sent as postcard-encoded payloads:
+//!
+//! ```
+//! use phm_icd::custom::CustomCommand;
+//! use serde::{Deserialize, Serialize};
+//!
+//! /// Switch the relays of a test fixture
+//! #[derive(Serialize, Deserialize)]
+//! pub struct SetRelays {
+//!     pub mask: u8,
+//!     pub on: bool,
+//! }
+//!
+//! impl CustomCommand for SetRelays {
+//!     const ID: u16 = 1;
+//!     /// The state of all relays
+//!     type Response = u8;
+//! }
+//! ```
+
+use heapless::Vec;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The most bytes in the payload of a custom command or response.
+pub const MAX_PAYLOAD: usize = 64;
+
+/// The payload of a custom command or response.
+pub type CustomPayload = Vec<u8, MAX_PAYLOAD>;
+
+/// A custom command with a typed request and response
+///
+/// Implemented by the request type, the response is its associated type. Both are
+/// encoded with postcard, and must fit in [MAX_PAYLOAD] bytes.
+pub trait CustomCommand: Serialize + DeserializeOwned {
+    /// The id of the command, unique among the custom commands of a worker.
+    const ID: u16;
+
+    type Response: Serialize + DeserializeOwned;
+}
+
+/// Encode a request or response as a payload.
+pub fn to_payload<T: Serialize>(value: &T) -> Result<CustomPayload, postcard::Error> {
+    postcard::to_vec(value)
+}
+
+/// Decode a request or response from a payload.
+pub fn from_payload<T: DeserializeOwned>(payload: &[u8]) -> Result<T, postcard::Error> {
+    postcard::from_bytes(payload)
+}
@@ -1,43 +1,256 @@
 #![no_std]
 
+pub mod custom;
+
+pub use custom::CustomPayload;
 use heapless::Vec;
 use serde::{Deserialize, Serialize};
 
-// TODO: Something better than this
-pub type Error = ();
+/// Why the worker could not execute a command.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Error {
+    /// The command failed, e.g. because a device did not acknowledge its address.
+    Failed,
+    /// The command did not complete before the worker's deadline, and was aborted.
+    Timeout,
+    /// The worker does not have the interface, bus or feature used by the command,
+    /// see [Capabilities].
+    Unsupported,
+    /// The command queue of the worker was full, and the command was dropped without
+    /// being executed. See [Response::credits].
+    Busy,
+}
+
+/// A message from the PC to the worker.
+///
+/// Generic over the command, so that the PC can send a borrowed `&ToMcu`.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request<C = ToMcu> {
+    /// Chosen by the PC, and copied into the [Response] to this command.
+    pub seq: u16,
+    pub cmd: C,
+}
+
+/// A message from the worker to the PC, answering the [Request] with the same `seq`.
+///
+/// Responses to executed commands are sent in order, but a command rejected with
+/// [Error::Busy] is answered right away, ahead of earlier commands.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub seq: u16,
+    /// The free slots in the command queue of the worker when this was sent. The PC
+    /// should not have more commands in flight than this, or they may be answered
+    /// with [Error::Busy].
+    pub credits: u8,
+    pub result: Result<ToPc, Error>,
+}
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToMcu {
-    I2c(ToMcuI2c),
-    Spi(ToMcuSpi),
-    Uart(ToMcuUart),
+    /// A command for the I2C bus with index `bus`.
+    I2c {
+        bus: u8,
+        cmd: ToMcuI2c,
+    },
+    /// A command for the I2C bus with index `bus`.
+    Smbus {
+        bus: u8,
+        cmd: ToMcuSmbus,
+    },
+    /// A command for the SPI bus with index `bus`.
+    Spi {
+        bus: u8,
+        cmd: ToMcuSpi,
+    },
+    /// A command for the UART with index `bus`.
+    Uart {
+        bus: u8,
+        cmd: ToMcuUart,
+    },
+    Adc(ToMcuAdc),
+    Pwm(ToMcuPwm),
     Ping,
+    GetCapabilities,
+    /// Wait for at least `us` microseconds, timed by the worker. Delays must end
+    /// a little before the worker's deadline, or they fail with [Error::Timeout].
+    Delay {
+        us: u32,
+    },
+    /// Measure the pulses on the pulse input `input` for `gate_us` microseconds.
+    MeasurePulse {
+        input: u8,
+        gate_us: u32,
+    },
+    /// A board-specific command, see [custom].
+    Custom {
+        id: u16,
+        payload: CustomPayload,
+    },
+    GetStats,
+    /// Reset the counters of the worker, answered with their values before the reset.
+    ResetStats,
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToMcuI2c {
     Write {
-        addr: u8,
+        addr: u16,
+        mode: AddressMode,
         output: Vec<u8, 64>,
     },
     Read {
-        addr: u8,
+        addr: u16,
+        mode: AddressMode,
         to_read: u32,
     },
     WriteThenRead {
-        addr: u8,
+        addr: u16,
+        mode: AddressMode,
         output: Vec<u8, 64>,
         to_read: u32,
     },
+    /// Read the levels of the SDA and SCL lines.
+    BusStatus,
+    /// Free a bus held by a device, by clocking SCL until the device releases SDA,
+    /// then sending a STOP.
+    Recover,
+}
+
+/// The kind of address of an I2C device.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddressMode {
+    SevenBit,
+    TenBit,
+}
+
+/// SMBus commands, executed over the I2C bus.
+///
+/// With `pec` set, the worker appends a packet error code to the bytes written,
+/// and checks the packet error code sent by the device after the bytes read.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToMcuSmbus {
+    /// Send only the address, with the R/W bit set for a read if `read` is true.
+    ///
+    /// Answered with [Error::Unsupported] by workers whose I2C driver can't send the
+    /// address alone. The blackpill worker only supports writes, the others neither.
+    Quick {
+        addr: u8,
+        read: bool,
+    },
+    SendByte {
+        addr: u8,
+        data: u8,
+        pec: bool,
+    },
+    ReceiveByte {
+        addr: u8,
+        pec: bool,
+    },
+    WriteByte {
+        addr: u8,
+        command: u8,
+        data: u8,
+        pec: bool,
+    },
+    ReadByte {
+        addr: u8,
+        command: u8,
+        pec: bool,
+    },
+    WriteWord {
+        addr: u8,
+        command: u8,
+        data: u16,
+        pec: bool,
+    },
+    ReadWord {
+        addr: u8,
+        command: u8,
+        pec: bool,
+    },
+    /// Write a word, then read a word in the same transaction.
+    ProcessCall {
+        addr: u8,
+        command: u8,
+        data: u16,
+        pec: bool,
+    },
+    /// Write a block of up to 32 bytes, prefixed with its length.
+    BlockWrite {
+        addr: u8,
+        command: u8,
+        data: Vec<u8, 32>,
+        pec: bool,
+    },
+    /// Read a block of up to 32 bytes, whose length is sent by the device.
+    BlockRead {
+        addr: u8,
+        command: u8,
+        pec: bool,
+    },
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToMcuSpi {
-    Write { output: Vec<u8, 64> },
-    Transfer { output: Vec<u8, 64> },
+    Write {
+        output: Vec<u8, 64>,
+    },
+    Transfer {
+        output: Vec<u8, 64>,
+    },
+    /// Write words of `bits` bits, from 9 to 16, if the size is one of
+    /// [Capabilities::spi_word_sizes].
+    WriteU16 {
+        bits: u8,
+        output: Vec<u16, 32>,
+    },
+    /// Transfer words of `bits` bits, from 9 to 16, if the size is one of
+    /// [Capabilities::spi_word_sizes].
+    TransferU16 {
+        bits: u8,
+        output: Vec<u16, 32>,
+    },
+    /// Assert the chip select line `cs`, and keep it asserted until `Release`. The
+    /// chip select lines aren't tied to a bus, and can be used with any SPI bus.
+    Select {
+        cs: u8,
+    },
+    /// Deassert all chip select lines.
+    Release,
+    /// Execute a sequence of operations, without releasing the chip select in between.
+    ///
+    /// If `cs` is given, that chip select line is asserted for the duration of the
+    /// transaction. The bytes written by each operation are taken in order from `output`.
+    Transaction {
+        cs: Option<u8>,
+        ops: Vec<SpiOp, 16>,
+        output: Vec<u8, 64>,
+    },
+}
+
+/// One operation of an SPI transaction, like embedded-hal's `spi::Operation`.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpiOp {
+    /// Write `len` bytes, discarding the bytes read.
+    Write { len: u8 },
+    /// Read `len` bytes, writing zeroes.
+    Read { len: u8 },
+    /// Write `write_len` bytes while reading `read_len` bytes. The shorter side is
+    /// padded with zeroes or discarded.
+    Transfer { read_len: u8, write_len: u8 },
+    /// Write `len` bytes, and read the same number of bytes.
+    TransferInPlace { len: u8 },
+    /// Wait for at least `ns` nanoseconds.
+    DelayNs { ns: u32 },
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
@@ -48,28 +261,176 @@ pub enum ToMcuUart {
     Read,
 }
 
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToMcuAdc {
+    /// List the analog input channels of the worker.
+    ListChannels,
+    /// Take `count` samples of `channel`, `interval_us` apart. At most 32 samples
+    /// can be taken by one command.
+    Sample {
+        channel: u8,
+        count: u8,
+        interval_us: u32,
+    },
+}
+
+/// Commands for the PWM outputs, which all answer with the state of the channel.
+///
+/// Duty cycles are given as a fraction of `u16::MAX`.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToMcuPwm {
+    /// Set the frequency of `channel`, as closely as the worker can. Channels
+    /// sharing a timer with `channel` change their frequency too.
+    SetFrequency {
+        channel: u8,
+        hz: u32,
+    },
+    SetDuty {
+        channel: u8,
+        duty: u16,
+    },
+    SetEnabled {
+        channel: u8,
+        enabled: bool,
+    },
+    GetState {
+        channel: u8,
+    },
+}
+
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToPc {
     I2c(ToPcI2c),
+    Smbus(ToPcSmbus),
     Spi(ToPcSpi),
     Uart(ToPcUart),
+    Adc(ToPcAdc),
+    Pwm(ToPcPwm),
     Pong,
+    Capabilities(Capabilities),
+    DelayComplete,
+    Pulse(PulseStats),
+    /// The response to [ToMcu::Custom].
+    Custom {
+        id: u16,
+        payload: CustomPayload,
+    },
+    Stats(Stats),
+}
+
+/// Counters of a worker, since it started or since the last [ToMcu::ResetStats].
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stats {
+    /// The commands executed by the worker.
+    pub commands: u32,
+    /// Commands rejected with [Error::Busy], because the queue of the worker was full.
+    pub dropped_commands: u32,
+    /// Responses dropped on the way to the PC, because a queue or the link was full.
+    pub dropped_responses: u32,
+    /// Frames from the PC that could not be decoded, or did not fit in the buffer.
+    pub decode_errors: u32,
+    /// Bytes lost by the UARTs, because a receive buffer was full or the UART
+    /// reported an error.
+    pub uart_overruns: u32,
+    /// Failed I2C and SMBus commands.
+    pub i2c_errors: u32,
+    /// Failed SPI commands.
+    pub spi_errors: u32,
+    /// Failed UART commands.
+    pub uart_errors: u32,
+    /// Commands aborted at their deadline.
+    pub timeouts: u32,
+}
+
+/// The features supported by a worker.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Whether I2C devices with 10-bit addresses are supported.
+    pub i2c_ten_bit: bool,
+    /// The number of SPI chip select lines.
+    pub spi_cs_count: u8,
+    /// The supported SPI word sizes of the first SPI bus, in bits. Words of 8 bits
+    /// are written with [ToMcuSpi::Write], larger ones with [ToMcuSpi::WriteU16].
+    pub spi_word_sizes: Vec<u8, 16>,
+    /// The number of I2C buses, addressed by indices from 0.
+    pub i2c_buses: u8,
+    /// The number of SPI buses, addressed by indices from 0.
+    pub spi_buses: u8,
+    /// The number of UARTs, addressed by indices from 0.
+    pub uart_buses: u8,
+    /// The number of analog input channels.
+    pub adc_channels: u8,
+    /// The number of PWM channels.
+    pub pwm_channels: u8,
+    /// The number of pulse inputs.
+    pub pulse_inputs: u8,
+    /// Whether driver calls still blocked at the deadline of a command are aborted,
+    /// e.g. on an I2C device stretching the clock forever. Otherwise only the waits
+    /// of the worker itself end at the deadline, and such a command hangs the worker.
+    pub deadline_abort: bool,
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToPcI2c {
-    WriteComplete { addr: u8 },
-    Read { addr: u8, data_read: Vec<u8, 64> },
-    WriteThenRead { addr: u8, data_read: Vec<u8, 64> },
+    WriteComplete {
+        addr: u16,
+    },
+    Read {
+        addr: u16,
+        data_read: Vec<u8, 64>,
+    },
+    WriteThenRead {
+        addr: u16,
+        data_read: Vec<u8, 64>,
+    },
+    BusStatus(I2cBusStatus),
+    /// The bus status after recovering the bus.
+    Recovered(I2cBusStatus),
+}
+
+/// The levels of the I2C bus lines, `true` when high.
+///
+/// An idle bus has both lines high.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct I2cBusStatus {
+    pub sda: bool,
+    pub scl: bool,
+}
+
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToPcSmbus {
+    Complete { addr: u8 },
+    Byte { addr: u8, data: u8 },
+    Word { addr: u8, data: u16 },
+    Block { addr: u8, data: Vec<u8, 32> },
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToPcSpi {
     WriteComplete,
-    Transfer { data_read: Vec<u8, 64> },
+    Transfer {
+        data_read: Vec<u8, 64>,
+    },
+    TransferU16 {
+        data_read: Vec<u16, 32>,
+    },
+    Selected {
+        cs: u8,
+    },
+    Released,
+    /// The bytes read by all operations of a transaction, in order.
+    Transaction {
+        data_read: Vec<u8, 64>,
+    },
 }
 
 #[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
@@ -78,3 +439,73 @@ pub enum ToPcUart {
     WriteComplete,
     Read { data_read: Vec<u8, 64> },
 }
+
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToPcAdc {
+    /// The analog input channels, in the order of their channel numbers.
+    Channels(Vec<AdcChannel, 8>),
+    Samples {
+        channel: u8,
+        info: AdcChannel,
+        /// The raw samples, where `0` is 0V and the largest value for the resolution
+        /// is the full-scale voltage.
+        samples: Vec<u16, 32>,
+    },
+}
+
+/// The properties of an analog input channel.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdcChannel {
+    /// The resolution of the samples, in bits.
+    pub resolution_bits: u8,
+    /// The input voltage of the largest sample, in millivolts.
+    pub full_scale_mv: u16,
+}
+
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ToPcPwm {
+    State { channel: u8, state: PwmState },
+}
+
+/// The pulses seen on a pulse input during a measurement.
+///
+/// Edges are timed by the worker's clock, so times have a resolution of
+/// about a microsecond.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PulseStats {
+    pub input: u8,
+    pub gate_us: u32,
+    pub rising_edges: u32,
+    pub falling_edges: u32,
+    /// The level of the input at the end of the measurement, `true` when high.
+    pub level: bool,
+    /// The times from one rising edge to the next.
+    pub period: PulseTimes,
+    /// The times from a rising edge to the next falling edge.
+    pub high: PulseTimes,
+}
+
+/// Statistics of a set of durations, all zero when `count` is zero.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PulseTimes {
+    pub count: u32,
+    pub total_us: u32,
+    pub min_us: u32,
+    pub max_us: u32,
+}
+
+/// The state of a PWM output.
+#[cfg_attr(feature = "use-defmt", derive(defmt::Format))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PwmState {
+    /// The frequency, in Hz.
+    pub hz: u32,
+    /// The duty cycle, as a fraction of `u16::MAX`.
+    pub duty: u16,
+    pub enabled: bool,
+}
@@ -39,7 +39,7 @@ fn main() -> Result<(), ()> {
     loop {
         if last_send.elapsed() >= Duration::from_secs(1) {
             println!("Sending I2C command!");
-            embedded_hal::blocking::i2c::Write::write(&mut ehal, 0x42, &[1, 2, 3, 4]).unwrap();
+            embedded_hal::blocking::i2c::Write::write(&mut ehal, 0x42u8, &[1, 2, 3, 4]).unwrap();
 
             last_send = Instant::now();
         }
@@ -0,0 +1,53 @@
+use phm_icd::AdcChannel;
+use phm_worker::{Adc as WorkerAdc, Error};
+use stm32f4xx_hal::{
+    adc::{
+        config::{AdcConfig, SampleTime},
+        Adc,
+    },
+    gpio::{
+        gpioa::{PA0, PA1},
+        Analog,
+    },
+    pac::ADC1,
+};
+
+/// 12-bit samples, with the 3.3V supply as the reference
+const CHANNEL: AdcChannel = AdcChannel {
+    resolution_bits: 12,
+    full_scale_mv: 3300,
+};
+static CHANNELS: [AdcChannel; 2] = [CHANNEL; 2];
+
+/// ADC1, on PA0 (channel 0) and PA1 (channel 1)
+///
+/// PA0 is also connected to the KEY button, and reads 0V while it is pressed.
+pub struct PhmAdc {
+    adc: Adc<ADC1>,
+    pa0: PA0<Analog>,
+    pa1: PA1<Analog>,
+}
+
+impl PhmAdc {
+    pub fn new(adc: ADC1, pa0: PA0<Analog>, pa1: PA1<Analog>) -> Self {
+        Self {
+            adc: Adc::adc1(adc, true, AdcConfig::default()),
+            pa0,
+            pa1,
+        }
+    }
+}
+
+impl WorkerAdc for PhmAdc {
+    fn channels(&self) -> &[AdcChannel] {
+        &CHANNELS
+    }
+
+    fn read(&mut self, channel: u8) -> Result<u16, Error> {
+        match channel {
+            0 => Ok(self.adc.convert(&self.pa0, SampleTime::Cycles_480)),
+            1 => Ok(self.adc.convert(&self.pa1, SampleTime::Cycles_480)),
+            _ => Err(Error::Adc),
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use embedded_hal::blocking::{
+    delay::DelayUs,
+    i2c::{Read, Write, WriteRead},
+};
+use phm_icd::I2cBusStatus;
+use phm_worker::{recover_i2c_bus, Error, I2cRecovery, SmbusBlockRead, SmbusQuick};
+use stm32f4xx_hal::{
+    gpio::{
+        gpiob::{PB8, PB9},
+        Alternate, OpenDrain,
+    },
+    i2c::{Error as I2cError, I2c},
+    pac::{GPIOB, I2C1},
+    prelude::*,
+    rcc::Clocks,
+};
+
+const SCL_PIN: u32 = 8;
+const SDA_PIN: u32 = 9;
+
+pub type I2c1 = I2c<I2C1, (PB8<Alternate<OpenDrain, 4>>, PB9<Alternate<OpenDrain, 4>>)>;
+
+/// The I2C1 peripheral, on PB9 (SDA) and PB8 (SCL)
+pub struct PhmI2c {
+    // Only `None` while recovering the bus
+    i2c: Option<I2c1>,
+    clocks: Clocks,
+}
+
+impl PhmI2c {
+    pub fn new(
+        i2c: I2C1,
+        pins: (PB8<Alternate<OpenDrain, 4>>, PB9<Alternate<OpenDrain, 4>>),
+        clocks: Clocks,
+    ) -> Self {
+        PhmI2c {
+            i2c: Some(I2c::new(i2c, pins, 400.khz(), &clocks)),
+            clocks,
+        }
+    }
+
+    fn i2c(&mut self) -> &mut I2c1 {
+        match self.i2c.as_mut() {
+            Some(i2c) => i2c,
+            None => defmt::unreachable!(),
+        }
+    }
+}
+
+impl Write for PhmI2c {
+    type Error = I2cError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.i2c().write(addr, bytes)
+    }
+}
+
+impl Read for PhmI2c {
+    type Error = I2cError;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c().read(addr, buffer)
+    }
+}
+
+impl WriteRead for PhmI2c {
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c().write_read(addr, bytes, buffer)
+    }
+}
+
+impl I2cRecovery for PhmI2c {
+    fn bus_status(&mut self) -> Result<I2cBusStatus, Error> {
+        // SAFETY: read-only access to the input levels, which are read in any pin mode
+        let idr = unsafe { &*GPIOB::ptr() }.idr.read().bits();
+        Ok(I2cBusStatus {
+            sda: idr & (1 << SDA_PIN) != 0,
+            scl: idr & (1 << SCL_PIN) != 0,
+        })
+    }
+
+    fn recover(&mut self, delay: &mut impl DelayUs<u32>) -> Result<I2cBusStatus, Error> {
+        let (i2c, (scl, sda)) = match self.i2c.take() {
+            Some(i2c) => i2c.release(),
+            None => defmt::unreachable!(),
+        };
+
+        let mut scl = scl.into_open_drain_output();
+        let mut sda = sda.into_open_drain_output();
+        let status = recover_i2c_bus(&mut sda, &mut scl, delay);
+
+        // Start over with a fresh driver, in case the peripheral saw part of the recovery
+        let pins = (
+            scl.into_alternate_open_drain(),
+            sda.into_alternate_open_drain(),
+        );
+        self.i2c = Some(I2c::new(i2c, pins, 400.khz(), &self.clocks));
+        status
+    }
+}
+
+// The driver reads a length given up front, and the peripheral has usually
+// acknowledged the byte after the count before it can be read, so block reads are
+// unsupported
+impl SmbusBlockRead for PhmI2c {}
+
+impl SmbusQuick for PhmI2c {
+    fn quick(&mut self, addr: u8, read: bool) -> Result<(), Error> {
+        // An empty write sends the address and a STOP. The driver has no way to stop
+        // a read before its first byte, so quick reads are unsupported.
+        match read {
+            true => Err(Error::Unsupported),
+            false => self.i2c().write(addr, &[]).map_err(|_| Error::I2c),
+        }
+    }
+}
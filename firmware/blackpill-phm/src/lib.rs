@@ -1,5 +1,10 @@
 #![no_std]
 
+pub mod adc;
+pub mod i2c;
+pub mod pwm;
+pub mod spi;
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use defmt_rtt as _; // global logger
@@ -5,47 +5,48 @@ use blackpill_phm as _; // global logger + panicking-behavior + memory layout
 
 #[rtic::app(device = stm32f4xx_hal::pac, dispatchers = [USART1])]
 mod app {
+    use blackpill_phm::{adc::PhmAdc, i2c::PhmI2c, pwm::PhmPwm, spi::PhmSpi};
+    use cortex_m::delay::Delay;
     use defmt::unwrap;
     use heapless::spsc::Queue;
-    use phm_icd::{ToMcu, ToPc};
+    use phm_icd::Request;
+    use phm_usb::{Descriptor, UsbInterface};
     use phm_worker::{
-        comms::{CommsLink, InterfaceComms, WorkerComms},
+        comms::{CommsLink, InterfaceStats, Tagged, WorkerComms},
         Worker,
     };
-    use postcard::{to_vec_cobs, CobsAccumulator, FeedResult};
     use stm32f4xx_hal::{
         gpio::{
-            gpioa::{PA2, PA3, PA5, PA6, PA7},
-            gpiob::{PB8, PB9},
-            Alternate, OpenDrain, PushPull,
+            gpioa::{PA2, PA3},
+            Alternate, ErasedPin, Floating, Input, Output, PushPull,
         },
-        i2c::I2c,
         otg_fs::{UsbBus, UsbBusType, USB},
-        pac::{I2C1, SPI1, USART2},
+        pac::USART2,
         prelude::*,
         serial::{config::Config as UartConfig, Serial},
-        spi::{Mode, Phase, Polarity, Spi, TransferModeNormal},
+        spi::{Mode, Phase, Polarity, Spi},
         timer::{
             monotonic::{ExtU32, MonoTimer},
             Timer,
         },
     };
-    use usb_device::{
-        class_prelude::UsbBusAllocator,
-        device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
-    };
-    use usbd_serial::{SerialPort, USB_CLASS_CDC};
-    type PhmI2c = I2c<I2C1, (PB8<Alternate<OpenDrain, 4>>, PB9<Alternate<OpenDrain, 4>>)>;
+    use usb_device::class_prelude::UsbBusAllocator;
     type PhmUart = Serial<USART2, (PA2<Alternate<PushPull, 7>>, PA3<Alternate<PushPull, 7>>), u8>;
-    type PhmSpi = Spi<
-        SPI1,
-        (
-            PA5<Alternate<PushPull, 5>>,
-            PA6<Alternate<PushPull, 5>>,
-            PA7<Alternate<PushPull, 5>>,
-        ),
-        TransferModeNormal,
-    >;
+    type PhmSpiCs = [ErasedPin<Output<PushPull>>; 3];
+    type PhmPulseInputs = [ErasedPin<Input<Floating>>; 2];
+
+    /// Command deadlines for the worker, from the monotonic timer
+    ///
+    /// The I2C driver only waits on status flags that can't be raised from another
+    /// context, so a stuck driver call can't be aborted at the deadline, and only the
+    /// waits in the worker itself are bounded. The capabilities of the worker say so.
+    pub struct PhmClock;
+
+    impl phm_worker::Clock for PhmClock {
+        fn now_us(&mut self) -> u32 {
+            monotonics::now().duration_since_epoch().to_micros()
+        }
+    }
 
     #[monotonic(binds = TIM2, default = true)]
     type Monotonic = MonoTimer<stm32f4xx_hal::pac::TIM2, 1_000_000>;
@@ -55,17 +56,27 @@ mod app {
 
     #[local]
     struct Local {
-        interface_comms: InterfaceComms<8>,
-        worker: Worker<WorkerComms<8>, PhmI2c, PhmSpi, PhmUart>,
-        usb_serial: SerialPort<'static, UsbBus<USB>>,
-        usb_dev: UsbDevice<'static, UsbBus<USB>>,
+        worker: Worker<
+            WorkerComms<8>,
+            Delay,
+            PhmClock,
+            [PhmI2c; 1],
+            [PhmSpi; 1],
+            [PhmUart; 1],
+            PhmSpiCs,
+            PhmAdc,
+            PhmPwm,
+            PhmPulseInputs,
+        >,
+        usb: UsbInterface<UsbBus<USB>, 8>,
     }
 
     #[init(local = [
         ep_memory: [u32; 1024] = [0; 1024],
         usb_bus: Option<UsbBusAllocator<UsbBusType>> = None,
-        incoming: Queue<ToMcu, 8> = Queue::new(),
-        outgoing: Queue<Result<ToPc, ()>, 8> = Queue::new(),
+        incoming: Queue<Request, 8> = Queue::new(),
+        outgoing: Queue<Tagged, 8> = Queue::new(),
+        interface_stats: InterfaceStats = InterfaceStats::new(),
     ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let device = cx.device;
@@ -74,9 +85,12 @@ mod app {
         let rcc = device.RCC.constrain();
         let clocks = rcc.cfgr.sysclk(48.mhz()).require_pll48clk().freeze();
 
-        // Configure the monotonic timer, currently using TIMER0, a 32-bit, 1MHz timer
+        // Configure the monotonic timer, currently using TIM2, a 32-bit timer at 1MHz
         let mono = Timer::new(device.TIM2, &clocks).monotonic();
 
+        // SysTick is used for delays requested by the host
+        let delay = Delay::new(cx.core.SYST, clocks.sysclk().0);
+
         // Create GPIO ports for pin-mapping
         let gpioa = device.GPIOA.split();
         let gpiob = device.GPIOB.split();
@@ -84,22 +98,31 @@ mod app {
         // Set up I2C
         let scl = gpiob.pb8.into_alternate_open_drain();
         let sda = gpiob.pb9.into_alternate_open_drain();
-        let i2c = I2c::new(device.I2C1, (scl, sda), 400.khz(), &clocks);
+        let i2c = PhmI2c::new(device.I2C1, (scl, sda), clocks);
 
         // Set up SPI
         let sck = gpioa.pa5.into_alternate();
         let miso = gpioa.pa6.into_alternate();
         let mosi = gpioa.pa7.into_alternate();
-        let spi = Spi::new(
-            device.SPI1,
-            (sck, miso, mosi),
-            Mode {
-                polarity: Polarity::IdleLow,
-                phase: Phase::CaptureOnFirstTransition,
-            },
-            2_000.khz(),
-            &clocks,
-        );
+        let spi = PhmSpi {
+            spi: Spi::new(
+                device.SPI1,
+                (sck, miso, mosi),
+                Mode {
+                    polarity: Polarity::IdleLow,
+                    phase: Phase::CaptureOnFirstTransition,
+                },
+                2_000.khz(),
+                &clocks,
+            ),
+        };
+
+        // Set up the SPI chip select pins, which are active low
+        let spi_cs: PhmSpiCs = [
+            gpioa.pa4.into_push_pull_output().erase(),
+            gpiob.pb0.into_push_pull_output().erase(),
+            gpiob.pb1.into_push_pull_output().erase(),
+        ];
 
         // define RX/TX pins
         let tx_pin = gpioa.pa2.into_alternate();
@@ -113,8 +136,28 @@ mod app {
         )
         .unwrap();
 
+        // Set up the ADC inputs
+        let adc = PhmAdc::new(
+            device.ADC1,
+            gpioa.pa0.into_analog(),
+            gpioa.pa1.into_analog(),
+        );
+
+        // Set up the PWM outputs
+        let pwm = PhmPwm::new(
+            device.TIM3,
+            (gpiob.pb4.into_alternate(), gpiob.pb5.into_alternate()),
+            &clocks,
+        );
+
+        // Set up the pulse inputs
+        let pulse_inputs: PhmPulseInputs = [
+            gpiob.pb6.into_floating_input().erase(),
+            gpiob.pb7.into_floating_input().erase(),
+        ];
+
         // Set up USB
-        let usb = USB {
+        let usb_periph = USB {
             usb_global: device.OTG_FS_GLOBAL,
             usb_device: device.OTG_FS_DEVICE,
             usb_pwrclk: device.OTG_FS_PWRCLK,
@@ -123,81 +166,41 @@ mod app {
             hclk: clocks.hclk(),
         };
         let usb_bus = cx.local.usb_bus;
-        usb_bus.replace(UsbBus::new(usb, cx.local.ep_memory));
-
-        // Set up USB Serial Port
-        let usb_serial = SerialPort::new(usb_bus.as_ref().unwrap());
-        let usb_dev = UsbDeviceBuilder::new(usb_bus.as_ref().unwrap(), UsbVidPid(0x16c0, 0x27dd))
-            .manufacturer("OVAR Labs")
-            .product("PHM Worker")
-            // TODO: Use some kind of unique ID. This will probably require another singleton,
-            // as the storage must be static. Probably heapless::String -> singleton!()
-            .serial_number("ajm123")
-            .device_class(USB_CLASS_CDC)
-            .max_packet_size_0(64) // (makes control transfers 8x faster)
-            .build();
+        usb_bus.replace(UsbBus::new(usb_periph, cx.local.ep_memory));
 
         let comms = CommsLink {
             to_pc: cx.local.outgoing,
             to_mcu: cx.local.incoming,
+            stats: cx.local.interface_stats,
         };
 
         let (worker_comms, interface_comms) = comms.split();
+        let usb = UsbInterface::new(
+            usb_bus.as_ref().unwrap(),
+            interface_comms,
+            &Descriptor::DEFAULT,
+        );
 
-        let worker = Worker::new(worker_comms, i2c, spi, uart);
+        // The I2C driver accepts the reserved addresses used for 10-bit devices.
+        //
+        // Only one bus of each kind is wired up: the I2C recovery reads the GPIOB
+        // pins of I2C1, the 16-bit SPI frames address SPI1 directly, and USART1 is
+        // taken as the RTIC dispatcher.
+        let worker = Worker::new(worker_comms, delay, PhmClock)
+            .with_i2c([i2c])
+            .with_spi([spi], spi_cs)
+            .with_uart([uart])
+            .with_ten_bit_i2c()
+            .with_adc(adc)
+            .with_pwm(pwm)
+            .with_pulse_inputs(pulse_inputs);
         usb_tick::spawn().ok();
-        (
-            Shared {},
-            Local {
-                worker,
-                interface_comms,
-                usb_serial,
-                usb_dev,
-            },
-            init::Monotonics(mono),
-        )
+        (Shared {}, Local { worker, usb }, init::Monotonics(mono))
     }
 
-    #[task(local = [usb_serial, interface_comms, usb_dev, cobs_buf: CobsAccumulator<512> = CobsAccumulator::new()])]
+    #[task(local = [usb])]
     fn usb_tick(cx: usb_tick::Context) {
-        let usb_serial = cx.local.usb_serial;
-        let usb_dev = cx.local.usb_dev;
-        let cobs_buf = cx.local.cobs_buf;
-        let interface_comms = cx.local.interface_comms;
-
-        let mut buf = [0u8; 128];
-
-        usb_dev.poll(&mut [usb_serial]);
-
-        if let Some(out) = interface_comms.to_pc.dequeue() {
-            if let Ok(ser_msg) = to_vec_cobs::<_, 128>(&out) {
-                usb_serial.write(&ser_msg).ok();
-            } else {
-                defmt::panic!("Serialization error!");
-            }
-        }
-
-        match usb_serial.read(&mut buf) {
-            Ok(sz) if sz > 0 => {
-                let buf = &buf[..sz];
-                let mut window = &buf[..];
-
-                'cobs: while !window.is_empty() {
-                    window = match cobs_buf.feed::<phm_icd::ToMcu>(&window) {
-                        FeedResult::Consumed => break 'cobs,
-                        FeedResult::OverFull(new_wind) => new_wind,
-                        FeedResult::DeserError(new_wind) => new_wind,
-                        FeedResult::Success { data, remaining } => {
-                            defmt::println!("got: {:?}", data);
-                            interface_comms.to_mcu.enqueue(data).ok();
-                            remaining
-                        }
-                    };
-                }
-            }
-            Ok(_) | Err(usb_device::UsbError::WouldBlock) => {}
-            Err(_e) => defmt::panic!("Usb Error!"),
-        }
+        cx.local.usb.poll();
         usb_tick::spawn_after(1.millis()).ok();
     }
 
@@ -0,0 +1,149 @@
+use phm_icd::PwmState;
+use phm_worker::{Error, Pwm as WorkerPwm};
+use stm32f4xx_hal::{
+    gpio::{
+        gpiob::{PB4, PB5},
+        Alternate, PushPull,
+    },
+    pac::{RCC, TIM3},
+    rcc::Clocks,
+};
+
+/// The default frequency of the channels, in Hz
+const DEFAULT_HZ: u32 = 1_000;
+
+// CCMR1: PWM mode 1 with preloaded compare registers, for both channels
+const CCMR1_PWM1: u32 = (0b110 << 4) | (1 << 3) | (0b110 << 12) | (1 << 11);
+const CR1_CEN: u32 = 1 << 0;
+const CR1_ARPE: u32 = 1 << 7;
+const EGR_UG: u32 = 1 << 0;
+
+/// The PWM outputs of TIM3, on PB4 (channel 0) and PB5 (channel 1)
+///
+/// The HAL's PWM driver fixes the frequency on creation, so the timer is driven
+/// through its registers. Both channels share the timer, and with it their frequency.
+pub struct PhmPwm {
+    tim: TIM3,
+    _pins: (PB4<Alternate<PushPull, 2>>, PB5<Alternate<PushPull, 2>>),
+    timer_hz: u32,
+    hz: u32,
+    duties: [u16; 2],
+    enabled: [bool; 2],
+}
+
+impl PhmPwm {
+    pub fn new(
+        tim: TIM3,
+        pins: (PB4<Alternate<PushPull, 2>>, PB5<Alternate<PushPull, 2>>),
+        clocks: &Clocks,
+    ) -> Self {
+        // SAFETY: atomic enable of the TIM3 clock, which no other driver touches
+        unsafe { &*RCC::ptr() }
+            .apb1enr
+            .modify(|_, w| w.tim3en().set_bit());
+
+        // The APB1 timers run at twice the APB1 clock when APB1 is divided
+        let timer_hz = match clocks.ppre1() {
+            1 => clocks.pclk1().0,
+            _ => clocks.pclk1().0 * 2,
+        };
+
+        // SAFETY: valid mode bits for CCMR1 and CR1, see the constants
+        tim.ccmr1_output().write(|w| unsafe { w.bits(CCMR1_PWM1) });
+        tim.cr1.write(|w| unsafe { w.bits(CR1_ARPE) });
+
+        let mut pwm = Self {
+            tim,
+            _pins: pins,
+            timer_hz,
+            hz: 0,
+            duties: [0; 2],
+            enabled: [false; 2],
+        };
+        pwm.set_frequency(0, DEFAULT_HZ).ok();
+        pwm.tim
+            .cr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | CR1_CEN) });
+        pwm
+    }
+
+    fn period(&self) -> u32 {
+        self.tim.arr.read().bits() + 1
+    }
+
+    /// Write the compare register of `channel`, scaled to the current period
+    fn apply_duty(&self, channel: u8) {
+        let ccr =
+            u32::from(self.duties[usize::from(channel)]) * self.period() / u32::from(u16::MAX);
+        // SAFETY: any value is valid for the compare registers
+        match channel {
+            0 => self.tim.ccr1.write(|w| unsafe { w.bits(ccr) }),
+            _ => self.tim.ccr2.write(|w| unsafe { w.bits(ccr) }),
+        }
+    }
+}
+
+impl WorkerPwm for PhmPwm {
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn set_frequency(&mut self, channel: u8, hz: u32) -> Result<(), Error> {
+        if channel >= self.channels() || hz == 0 || hz > self.timer_hz / 2 {
+            return Err(Error::Pwm);
+        }
+        let total = self.timer_hz / hz;
+        let psc = ((total + 0xFFFF) / 0x1_0000).clamp(1, 0x1_0000);
+        let arr = (total / psc).clamp(2, 0x1_0000);
+
+        // SAFETY: any value is valid for the prescaler and the auto-reload register
+        self.tim.psc.write(|w| unsafe { w.bits(psc - 1) });
+        self.tim.arr.write(|w| unsafe { w.bits(arr - 1) });
+        self.hz = self.timer_hz / (psc * arr);
+
+        // The period changed, so both duty cycles need rescaling
+        self.apply_duty(0);
+        self.apply_duty(1);
+        // SAFETY: UG only reloads the preloaded registers
+        self.tim.egr.write(|w| unsafe { w.bits(EGR_UG) });
+        Ok(())
+    }
+
+    fn set_duty(&mut self, channel: u8, duty: u16) -> Result<(), Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        self.duties[usize::from(channel)] = duty;
+        self.apply_duty(channel);
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, channel: u8, enabled: bool) -> Result<(), Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        // CC1E and CC2E
+        let bit = 1 << (4 * u32::from(channel));
+        // SAFETY: only the output enable bit of the channel changes
+        self.tim.ccer.modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | bit
+            } else {
+                r.bits() & !bit
+            })
+        });
+        self.enabled[usize::from(channel)] = enabled;
+        Ok(())
+    }
+
+    fn state(&self, channel: u8) -> Result<PwmState, Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        Ok(PwmState {
+            hz: self.hz,
+            duty: self.duties[usize::from(channel)],
+            enabled: self.enabled[usize::from(channel)],
+        })
+    }
+}
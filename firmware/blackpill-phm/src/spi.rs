@@ -0,0 +1,105 @@
+use phm_worker::{Error, SpiWords};
+use stm32f4xx_hal::{
+    gpio::{
+        gpioa::{PA5, PA6, PA7},
+        Alternate, PushPull,
+    },
+    pac::{spi1::RegisterBlock, SPI1},
+    spi::{self, Spi, TransferModeNormal},
+};
+
+pub type Spi1 = Spi<
+    SPI1,
+    (
+        PA5<Alternate<PushPull, 5>>,
+        PA6<Alternate<PushPull, 5>>,
+        PA7<Alternate<PushPull, 5>>,
+    ),
+    TransferModeNormal,
+>;
+
+/// The SPI1 peripheral, switched to 16-bit frames for 16-bit words
+///
+/// The HAL only drives 8-bit frames, so 16-bit words are sent through the
+/// registers directly.
+pub struct PhmSpi {
+    pub spi: Spi1,
+}
+
+impl PhmSpi {
+    /// Run `f` with the peripheral in 16-bit frame mode, returning to 8-bit
+    /// frames afterwards.
+    fn with_16_bit_frames<T>(&mut self, f: impl FnOnce(&RegisterBlock) -> T) -> T {
+        // SAFETY: we own the only handle to SPI1, and the HAL driver is not
+        // used until the frame size is restored.
+        let regs = unsafe { &*SPI1::ptr() };
+
+        // The frame format may only be changed while the peripheral is disabled
+        set_16_bit_frames(regs, true);
+        let result = f(regs);
+        set_16_bit_frames(regs, false);
+        result
+    }
+}
+
+fn set_16_bit_frames(regs: &RegisterBlock, enabled: bool) {
+    while regs.sr.read().bsy().bit_is_set() {}
+    regs.cr1.modify(|_, w| w.spe().clear_bit());
+    regs.cr1.modify(|_, w| w.dff().bit(enabled));
+    regs.cr1.modify(|_, w| w.spe().set_bit());
+}
+
+fn transfer_word(regs: &RegisterBlock, word: u16) -> Result<u16, Error> {
+    while regs.sr.read().txe().bit_is_clear() {}
+    regs.dr.write(|w| unsafe { w.bits(word.into()) });
+    loop {
+        let sr = regs.sr.read();
+        if sr.ovr().bit_is_set() || sr.modf().bit_is_set() {
+            return Err(Error::Spi);
+        }
+        if sr.rxne().bit_is_set() {
+            return Ok(regs.dr.read().bits() as u16);
+        }
+    }
+}
+
+impl embedded_hal::blocking::spi::Write<u8> for PhmSpi {
+    type Error = spi::Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        embedded_hal::blocking::spi::Write::write(&mut self.spi, words)
+    }
+}
+
+impl embedded_hal::blocking::spi::Transfer<u8> for PhmSpi {
+    type Error = spi::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        embedded_hal::blocking::spi::Transfer::transfer(&mut self.spi, words)
+    }
+}
+
+impl SpiWords for PhmSpi {
+    fn word_sizes(&self) -> &'static [u8] {
+        &[8, 16]
+    }
+
+    // 16 is the only word size above 8, so `_bits` is always 16
+    fn write_u16(&mut self, _bits: u8, words: &[u16]) -> Result<(), Error> {
+        self.with_16_bit_frames(|regs| {
+            for &word in words {
+                transfer_word(regs, word)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn transfer_u16(&mut self, _bits: u8, words: &mut [u16]) -> Result<(), Error> {
+        self.with_16_bit_frames(|regs| {
+            for word in words.iter_mut() {
+                *word = transfer_word(regs, *word)?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,72 @@
+use embedded_hal::adc::OneShot;
+use nrf52840_hal::{
+    gpio::{
+        p0::{P0_02, P0_03, P0_30, P0_31},
+        Floating, Input,
+    },
+    pac::SAADC,
+    saadc::{Gain, Reference, Resolution, Saadc, SaadcConfig},
+};
+use phm_icd::AdcChannel;
+use phm_worker::{Adc as WorkerAdc, Error};
+
+/// 12-bit samples, with the internal 0.6V reference and a gain of 1/6
+const CHANNEL: AdcChannel = AdcChannel {
+    resolution_bits: 12,
+    full_scale_mv: 3600,
+};
+static CHANNELS: [AdcChannel; 4] = [CHANNEL; 4];
+
+/// The SAADC, on P0.02 (channel 0), P0.03 (channel 1), P0.30 (channel 2)
+/// and P0.31 (channel 3)
+pub struct PhmAdc {
+    saadc: Saadc,
+    p0_02: P0_02<Input<Floating>>,
+    p0_03: P0_03<Input<Floating>>,
+    p0_30: P0_30<Input<Floating>>,
+    p0_31: P0_31<Input<Floating>>,
+}
+
+impl PhmAdc {
+    pub fn new(
+        saadc: SAADC,
+        p0_02: P0_02<Input<Floating>>,
+        p0_03: P0_03<Input<Floating>>,
+        p0_30: P0_30<Input<Floating>>,
+        p0_31: P0_31<Input<Floating>>,
+    ) -> Self {
+        let config = SaadcConfig {
+            resolution: Resolution::_12BIT,
+            reference: Reference::INTERNAL,
+            gain: Gain::GAIN1_6,
+            ..SaadcConfig::default()
+        };
+        Self {
+            saadc: Saadc::new(saadc, config),
+            p0_02,
+            p0_03,
+            p0_30,
+            p0_31,
+        }
+    }
+}
+
+impl WorkerAdc for PhmAdc {
+    fn channels(&self) -> &[AdcChannel] {
+        &CHANNELS
+    }
+
+    fn read(&mut self, channel: u8) -> Result<u16, Error> {
+        let saadc = &mut self.saadc;
+        let sample: Result<i16, ()> = match channel {
+            0 => nb::block!(saadc.read(&mut self.p0_02)),
+            1 => nb::block!(saadc.read(&mut self.p0_03)),
+            2 => nb::block!(saadc.read(&mut self.p0_30)),
+            3 => nb::block!(saadc.read(&mut self.p0_31)),
+            _ => return Err(Error::Adc),
+        };
+
+        // Inputs slightly below ground read as small negative values
+        sample.map(|s| s.max(0) as u16).map_err(|_| Error::Adc)
+    }
+}
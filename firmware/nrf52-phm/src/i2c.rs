@@ -0,0 +1,162 @@
+use core::convert::Infallible;
+use embedded_hal::{
+    blocking::{
+        delay::DelayUs,
+        i2c::{Read, Write, WriteRead},
+    },
+    digital::v2::{InputPin, OutputPin},
+};
+use nrf52840_hal::{
+    pac::{P1, TWIM0},
+    twim::{Error as TwimError, Twim},
+};
+use phm_icd::I2cBusStatus;
+use phm_worker::{recover_i2c_bus, Error, I2cRecovery, SmbusBlockRead, SmbusQuick};
+
+const SCL_PIN: usize = 1;
+const SDA_PIN: usize = 2;
+
+/// The TWIM0 peripheral, on P1.02 (SDA) and P1.01 (SCL)
+pub struct PhmI2c {
+    pub twim: Twim<TWIM0>,
+}
+
+impl PhmI2c {
+    /// Abort the current transfer from another context, such as a timer interrupt.
+    ///
+    /// TWIM sends a STOP and raises the STOPPED event, which makes a blocked driver
+    /// call return.
+    pub fn abort() {
+        // Safety: triggering a task is a single atomic write
+        let twim0 = unsafe { &*TWIM0::ptr() };
+        twim0.tasks_stop.write(|w| unsafe { w.bits(1) });
+    }
+}
+
+impl Write for PhmI2c {
+    type Error = TwimError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.twim.write(addr, bytes)
+    }
+}
+
+impl Read for PhmI2c {
+    type Error = TwimError;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.twim.read(addr, buffer)
+    }
+}
+
+impl WriteRead for PhmI2c {
+    type Error = TwimError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.twim.write_read(addr, bytes, buffer)
+    }
+}
+
+impl I2cRecovery for PhmI2c {
+    fn bus_status(&mut self) -> Result<I2cBusStatus, Error> {
+        // TWIM leaves the input buffers of its pins connected
+        Ok(I2cBusStatus {
+            sda: level(SDA_PIN),
+            scl: level(SCL_PIN),
+        })
+    }
+
+    fn recover(&mut self, delay: &mut impl DelayUs<u32>) -> Result<I2cBusStatus, Error> {
+        // While TWIM is disabled, its pins are controlled by the GPIO registers
+        // SAFETY: we own TWIM0, and re-enable it before it is used again.
+        let twim = unsafe { &*TWIM0::ptr() };
+        twim.enable.write(|w| w.enable().disabled());
+
+        let mut sda = GpioLine::take(SDA_PIN);
+        let mut scl = GpioLine::take(SCL_PIN);
+        let status = recover_i2c_bus(&mut sda, &mut scl, delay);
+        sda.release();
+        scl.release();
+
+        twim.enable.write(|w| w.enable().enabled());
+        status
+    }
+}
+
+// The TWIM peripheral reads a length given up front with EasyDMA, and can't
+// continue a read after the count byte, so block reads are unsupported
+impl SmbusBlockRead for PhmI2c {}
+
+// The driver rejects empty buffers, as their address is not in RAM for EasyDMA, so
+// quick commands are unsupported
+impl SmbusQuick for PhmI2c {}
+
+fn level(pin: usize) -> bool {
+    // SAFETY: read-only access to the input levels
+    let p1 = unsafe { &*P1::ptr() };
+    p1.in_.read().bits() & (1 << pin) != 0
+}
+
+/// A bus line driven as a standard-0, disconnected-1 output of port 1.
+struct GpioLine {
+    pin: usize,
+}
+
+impl GpioLine {
+    fn take(pin: usize) -> Self {
+        // SAFETY: the pin belongs to TWIM, which is disabled until the pin is
+        // released again. The set/clear registers don't affect other pins.
+        let p1 = unsafe { &*P1::ptr() };
+        p1.outset.write(|w| unsafe { w.bits(1 << pin) });
+        p1.pin_cnf[pin].write(|w| {
+            w.dir().output();
+            w.input().connect();
+            w.pull().pullup();
+            w.drive().s0d1();
+            w.sense().disabled();
+            w
+        });
+        GpioLine { pin }
+    }
+
+    /// Return the pin to the configuration TWIM expects.
+    fn release(self) {
+        let p1 = unsafe { &*P1::ptr() };
+        p1.pin_cnf[self.pin].write(|w| {
+            w.dir().input();
+            w.input().connect();
+            w.pull().pullup();
+            w.drive().s0d1();
+            w.sense().disabled();
+            w
+        });
+    }
+}
+
+impl OutputPin for GpioLine {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let p1 = unsafe { &*P1::ptr() };
+        p1.outclr.write(|w| unsafe { w.bits(1 << self.pin) });
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let p1 = unsafe { &*P1::ptr() };
+        p1.outset.write(|w| unsafe { w.bits(1 << self.pin) });
+        Ok(())
+    }
+}
+
+impl InputPin for GpioLine {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(level(self.pin))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!level(self.pin))
+    }
+}
@@ -1,7 +1,11 @@
 #![no_main]
 #![no_std]
 
+pub mod adc;
+pub mod i2c;
 pub mod monotonic;
+pub mod pwm;
+pub mod spi;
 pub mod uart;
 
 use defmt_rtt as _; // global logger
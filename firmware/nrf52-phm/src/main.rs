@@ -10,8 +10,12 @@ mod app {
     use heapless::spsc::Queue;
     use nrf52840_hal::{
         clocks::{ExternalOscillator, Internal, LfOscStopped},
-        gpio::{p0::Parts as P0Parts, p1::Parts as P1Parts, Level},
-        pac::{SPIM2, TIMER0, TWIM0},
+        delay::Delay,
+        gpio::{
+            p0::Parts as P0Parts, p1::Parts as P1Parts, Floating, Input, Level, Output, Pin,
+            PushPull,
+        },
+        pac::TIMER0,
         spim::{Frequency as SpimFreq, Pins as SpimPins, Spim, MODE_0},
         twim::{Frequency as TwimFreq, Pins as TwimPins, Twim},
         uarte::{Baudrate, Parity, Pins as UartPins, Uarte},
@@ -19,18 +23,42 @@ mod app {
         Clocks,
     };
     use nrf52_phm::monotonic::{ExtU32, MonoTimer};
-    use nrf52_phm::uart::PhmUart;
-    use phm_icd::{ToMcu, ToPc};
+    use nrf52_phm::{adc::PhmAdc, i2c::PhmI2c, pwm::PhmPwm, spi::PhmSpi, uart::PhmUart};
+    use phm_icd::Request;
+    use phm_usb::{Descriptor, UsbInterface};
     use phm_worker::{
-        comms::{CommsLink, InterfaceComms, WorkerComms},
+        comms::{CommsLink, InterfaceStats, Tagged, WorkerComms},
         Worker,
     };
-    use postcard::{to_vec_cobs, CobsAccumulator, FeedResult};
-    use usb_device::{
-        class_prelude::UsbBusAllocator,
-        device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
-    };
-    use usbd_serial::{SerialPort, USB_CLASS_CDC};
+    use usb_device::class_prelude::UsbBusAllocator;
+
+    type PhmSpiCs = [Pin<Output<PushPull>>; 3];
+    type PhmPulseInputs = [Pin<Input<Floating>>; 2];
+
+    /// Command deadlines for the worker, from the monotonic timer
+    pub struct PhmClock {
+        abort: Option<abort_command::SpawnHandle>,
+    }
+
+    impl phm_worker::Clock for PhmClock {
+        fn now_us(&mut self) -> u32 {
+            monotonics::now().duration_since_epoch().to_micros() as u32
+        }
+
+        fn arm(&mut self, timeout_us: u32) {
+            self.abort = abort_command::spawn_after(timeout_us.micros()).ok();
+        }
+
+        fn disarm(&mut self) {
+            if let Some(handle) = self.abort.take() {
+                handle.cancel().ok();
+            }
+        }
+
+        fn can_abort(&self) -> bool {
+            true
+        }
+    }
 
     #[monotonic(binds = TIMER0, default = true)]
     type Monotonic = MonoTimer<TIMER0>;
@@ -40,16 +68,26 @@ mod app {
 
     #[local]
     struct Local {
-        interface_comms: InterfaceComms<8>,
-        worker: Worker<WorkerComms<8>, Twim<TWIM0>, Spim<SPIM2>, PhmUart>,
-        usb_serial: SerialPort<'static, Usbd<UsbPeripheral<'static>>>,
-        usb_dev: UsbDevice<'static, Usbd<UsbPeripheral<'static>>>,
+        worker: Worker<
+            WorkerComms<8>,
+            Delay,
+            PhmClock,
+            [PhmI2c; 1],
+            [PhmSpi; 1],
+            [PhmUart; 1],
+            PhmSpiCs,
+            PhmAdc,
+            PhmPwm,
+            PhmPulseInputs,
+        >,
+        usb: UsbInterface<Usbd<UsbPeripheral<'static>>, 8>,
     }
 
     #[init(local = [
         usb_bus: Option<UsbBusAllocator<Usbd<UsbPeripheral<'static>>>> = None,
-        incoming: Queue<ToMcu, 8> = Queue::new(),
-        outgoing: Queue<Result<ToPc, ()>, 8> = Queue::new(),
+        incoming: Queue<Request, 8> = Queue::new(),
+        outgoing: Queue<Tagged, 8> = Queue::new(),
+        interface_stats: InterfaceStats = InterfaceStats::new(),
         uart_rx_buf: [u8; 64] = [0; 64],
         uart_tx_buf: [u8; 1] = [0],
     ])]
@@ -65,12 +103,15 @@ mod app {
         // Configure the monotonic timer, currently using TIMER0, a 32-bit, 1MHz timer
         let mono = Monotonic::new(device.TIMER0);
 
+        // SysTick is used for delays requested by the host
+        let delay = Delay::new(cx.core.SYST);
+
         // Create GPIO ports for pin-mapping
         let port0 = P0Parts::new(device.P0);
         let port1 = P1Parts::new(device.P1);
 
         // Set up Twim
-        let i2c = Twim::new(
+        let twim = Twim::new(
             device.TWIM0,
             TwimPins {
                 scl: port1.p1_01.into_floating_input().degrade(),
@@ -78,12 +119,13 @@ mod app {
             },
             TwimFreq::K100,
         );
+        let i2c = PhmI2c { twim };
 
         // Set up Spim
         let sck = port0.p0_08.into_push_pull_output(Level::Low).degrade();
         let mosi = port0.p0_04.into_push_pull_output(Level::Low).degrade();
         let miso = port0.p0_06.into_floating_input().degrade();
-        let spi = Spim::new(
+        let spim = Spim::new(
             device.SPIM2,
             SpimPins {
                 sck,
@@ -94,6 +136,14 @@ mod app {
             MODE_0,
             0,
         );
+        let spi = PhmSpi { spim };
+
+        // Set up the SPI chip select pins, which are active low
+        let spi_cs: PhmSpiCs = [
+            port1.p1_10.into_push_pull_output(Level::High).degrade(),
+            port1.p1_11.into_push_pull_output(Level::High).degrade(),
+            port1.p1_12.into_push_pull_output(Level::High).degrade(),
+        ];
 
         // Set up UART
         let rxd = port0.p0_28.into_floating_input().degrade();
@@ -110,86 +160,75 @@ mod app {
             .unwrap();
         let uart = PhmUart { rx, tx };
 
-        // Set up USB Serial Port
+        // Set up the ADC inputs
+        let adc = PhmAdc::new(
+            device.SAADC,
+            port0.p0_02.into_floating_input(),
+            port0.p0_03.into_floating_input(),
+            port0.p0_30.into_floating_input(),
+            port0.p0_31.into_floating_input(),
+        );
+
+        // Set up the PWM outputs
+        let pwm = PhmPwm::new(
+            device.PWM0,
+            port1.p1_13.into_push_pull_output(Level::Low).degrade(),
+            port1.p1_14.into_push_pull_output(Level::Low).degrade(),
+        );
+
+        // Set up the pulse inputs
+        let pulse_inputs: PhmPulseInputs = [
+            port1.p1_04.into_floating_input().degrade(),
+            port1.p1_05.into_floating_input().degrade(),
+        ];
+
+        // Set up the USB bus, for the serial port of the worker
         let usb_bus = cx.local.usb_bus;
         usb_bus.replace(Usbd::new(UsbPeripheral::new(device.USBD, clocks)));
-        let usb_serial = SerialPort::new(usb_bus.as_ref().unwrap());
-        let usb_dev = UsbDeviceBuilder::new(usb_bus.as_ref().unwrap(), UsbVidPid(0x16c0, 0x27dd))
-            .manufacturer("OVAR Labs")
-            .product("PHM Worker")
-            // TODO: Use some kind of unique ID. This will probably require another singleton,
-            // as the storage must be static. Probably heapless::String -> singleton!()
-            .serial_number("ajm123")
-            .device_class(USB_CLASS_CDC)
-            .max_packet_size_0(64) // (makes control transfers 8x faster)
-            .build();
 
         let comms = CommsLink {
             to_pc: cx.local.outgoing,
             to_mcu: cx.local.incoming,
+            stats: cx.local.interface_stats,
         };
 
         let (worker_comms, interface_comms) = comms.split();
+        let usb = UsbInterface::new(
+            usb_bus.as_ref().unwrap(),
+            interface_comms,
+            &Descriptor::DEFAULT,
+        );
 
-        let worker = Worker::new(worker_comms, i2c, spi, uart);
+        // TWIM accepts the reserved addresses used for 10-bit devices.
+        //
+        // Only one bus of each kind is wired up: the I2C recovery and abort address
+        // TWIM0 directly, and the UART wrapper is written for UARTE0.
+        let worker = Worker::new(worker_comms, delay, PhmClock { abort: None })
+            .with_i2c([i2c])
+            .with_spi([spi], spi_cs)
+            .with_uart([uart])
+            .with_ten_bit_i2c()
+            .with_adc(adc)
+            .with_pwm(pwm)
+            .with_pulse_inputs(pulse_inputs);
 
         usb_tick::spawn().ok();
-        (
-            Shared {},
-            Local {
-                worker,
-                interface_comms,
-                usb_serial,
-                usb_dev,
-            },
-            init::Monotonics(mono),
-        )
+        (Shared {}, Local { worker, usb }, init::Monotonics(mono))
     }
 
-    #[task(local = [usb_serial, interface_comms, usb_dev, cobs_buf: CobsAccumulator<512> = CobsAccumulator::new()])]
+    #[task(local = [usb])]
     fn usb_tick(cx: usb_tick::Context) {
-        let usb_serial = cx.local.usb_serial;
-        let usb_dev = cx.local.usb_dev;
-        let cobs_buf = cx.local.cobs_buf;
-        let interface_comms = cx.local.interface_comms;
-
-        let mut buf = [0u8; 128];
-
-        usb_dev.poll(&mut [usb_serial]);
-
-        if let Some(out) = interface_comms.to_pc.dequeue() {
-            if let Ok(ser_msg) = to_vec_cobs::<_, 128>(&out) {
-                usb_serial.write(&ser_msg).ok();
-            } else {
-                defmt::panic!("Serialization error!");
-            }
-        }
-
-        match usb_serial.read(&mut buf) {
-            Ok(sz) if sz > 0 => {
-                let buf = &buf[..sz];
-                let mut window = &buf[..];
-
-                'cobs: while !window.is_empty() {
-                    window = match cobs_buf.feed::<phm_icd::ToMcu>(&window) {
-                        FeedResult::Consumed => break 'cobs,
-                        FeedResult::OverFull(new_wind) => new_wind,
-                        FeedResult::DeserError(new_wind) => new_wind,
-                        FeedResult::Success { data, remaining } => {
-                            defmt::println!("got: {:?}", data);
-                            interface_comms.to_mcu.enqueue(data).ok();
-                            remaining
-                        }
-                    };
-                }
-            }
-            Ok(_) | Err(usb_device::UsbError::WouldBlock) => {}
-            Err(_e) => defmt::panic!("Usb Error!"),
-        }
-
+        cx.local.usb.poll();
         usb_tick::spawn_after(1.millis()).ok();
     }
 
+    /// Abort a command still running at its deadline, so the worker can report the timeout
+    #[task]
+    fn abort_command(_cx: abort_command::Context) {
+        defmt::warn!("Aborting I2C transfer");
+        PhmI2c::abort();
+    }
+
     #[idle(local = [worker])]
     fn idle(cx: idle::Context) -> ! {
         defmt::println!("Hello, world!");
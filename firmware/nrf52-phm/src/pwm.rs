@@ -0,0 +1,104 @@
+use nrf52840_hal::{
+    gpio::{Output, Pin, PushPull},
+    pac::PWM0,
+    pwm::{Channel, Pwm},
+    time::U32Ext,
+};
+use phm_icd::PwmState;
+use phm_worker::{Error, Pwm as WorkerPwm};
+
+/// The default frequency of the channels, in Hz
+const DEFAULT_HZ: u32 = 1_000;
+
+/// The PWM outputs, on P1.13 (channel 0) and P1.14 (channel 1)
+///
+/// Both channels are driven by PWM0, so they share their frequency.
+pub struct PhmPwm {
+    pwm: Pwm<PWM0>,
+    duties: [u16; 2],
+    enabled: [bool; 2],
+}
+
+impl PhmPwm {
+    pub fn new(pwm0: PWM0, ch0: Pin<Output<PushPull>>, ch1: Pin<Output<PushPull>>) -> Self {
+        let pwm = Pwm::new(pwm0);
+        pwm.set_output_pin(Channel::C0, ch0)
+            .set_output_pin(Channel::C1, ch1)
+            .set_period(DEFAULT_HZ.hz());
+        pwm.disable_channel(Channel::C0);
+        pwm.disable_channel(Channel::C1);
+        pwm.enable();
+        Self {
+            pwm,
+            duties: [0; 2],
+            enabled: [false; 2],
+        }
+    }
+
+    /// Write the duty cycle of `channel`, scaled to the current period
+    fn apply_duty(&self, channel: u8) {
+        let max = u32::from(self.pwm.max_duty());
+        let duty = u32::from(self.duties[usize::from(channel)]) * max / u32::from(u16::MAX);
+        self.pwm.set_duty_on(hal_channel(channel), duty as u16);
+    }
+}
+
+fn hal_channel(channel: u8) -> Channel {
+    if channel == 0 {
+        Channel::C0
+    } else {
+        Channel::C1
+    }
+}
+
+impl WorkerPwm for PhmPwm {
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn set_frequency(&mut self, channel: u8, hz: u32) -> Result<(), Error> {
+        // The slowest frequency the 15-bit counter reaches with the largest prescaler,
+        // and a frequency still leaving a usable duty cycle resolution
+        if channel >= self.channels() || !(4..=1_000_000).contains(&hz) {
+            return Err(Error::Pwm);
+        }
+        self.pwm.set_period(hz.hz());
+        // The counter top changed, so both duty cycles need rescaling
+        self.apply_duty(0);
+        self.apply_duty(1);
+        Ok(())
+    }
+
+    fn set_duty(&mut self, channel: u8, duty: u16) -> Result<(), Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        self.duties[usize::from(channel)] = duty;
+        self.apply_duty(channel);
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, channel: u8, enabled: bool) -> Result<(), Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        if enabled {
+            self.pwm.enable_channel(hal_channel(channel));
+        } else {
+            self.pwm.disable_channel(hal_channel(channel));
+        }
+        self.enabled[usize::from(channel)] = enabled;
+        Ok(())
+    }
+
+    fn state(&self, channel: u8) -> Result<PwmState, Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        Ok(PwmState {
+            hz: self.pwm.period().0,
+            duty: self.duties[usize::from(channel)],
+            enabled: self.enabled[usize::from(channel)],
+        })
+    }
+}
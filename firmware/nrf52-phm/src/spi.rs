@@ -0,0 +1,28 @@
+use nrf52840_hal::{
+    pac::SPIM2,
+    spim::{Error, Spim},
+};
+use phm_worker::SpiWords;
+
+/// The SPIM peripheral, which only supports 8-bit words
+pub struct PhmSpi {
+    pub spim: Spim<SPIM2>,
+}
+
+impl embedded_hal::blocking::spi::Write<u8> for PhmSpi {
+    type Error = Error;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        embedded_hal::blocking::spi::Write::write(&mut self.spim, words)
+    }
+}
+
+impl embedded_hal::blocking::spi::Transfer<u8> for PhmSpi {
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        embedded_hal::blocking::spi::Transfer::transfer(&mut self.spim, words)
+    }
+}
+
+impl SpiWords for PhmSpi {}
@@ -1,6 +1,6 @@
 use nrf52840_hal::{
     pac::UARTE0,
-    uarte::{UarteRx, UarteTx},
+    uarte::{Error as UarteError, UarteRx, UarteTx},
 };
 
 pub struct PhmUart {
@@ -8,23 +8,65 @@ pub struct PhmUart {
     pub tx: UarteTx<UARTE0>,
 }
 
+/// A UART error, either from the driver, or flagged by the peripheral while receiving
+#[derive(Debug)]
+pub enum UartError {
+    /// A byte was received before the previous one was read, and is lost
+    Overrun,
+    /// A byte was received without a valid stop bit
+    Framing,
+    Parity,
+    /// The line was held low for longer than a byte
+    Break,
+    Driver(UarteError),
+}
+
+impl PhmUart {
+    /// Take any receive error flagged since the last read.
+    ///
+    /// The driver only reports transfers that came up short, so the error source is
+    /// read and cleared from the peripheral here.
+    fn take_error(&mut self) -> Option<UartError> {
+        // SAFETY: UARTE0 belongs to the driver halves in `self`, which never touch
+        // ERRORSRC. Writing ones only clears the flags that were read.
+        let uarte = unsafe { &*UARTE0::ptr() };
+        let source = uarte.errorsrc.read();
+        let error = if source.overrun().bit_is_set() {
+            UartError::Overrun
+        } else if source.framing().bit_is_set() {
+            UartError::Framing
+        } else if source.parity().bit_is_set() {
+            UartError::Parity
+        } else if source.break_().bit_is_set() {
+            UartError::Break
+        } else {
+            return None;
+        };
+        uarte.errorsrc.write(|w| unsafe { w.bits(source.bits()) });
+        Some(error)
+    }
+}
+
 impl embedded_hal::serial::Read<u8> for PhmUart {
-    type Error = ();
+    type Error = UartError;
 
     fn read(&mut self) -> Result<u8, nb::Error<Self::Error>> {
-        embedded_hal::serial::Read::<u8>::read(&mut self.rx).map_err(|_| nb::Error::WouldBlock)
+        if let Some(error) = self.take_error() {
+            return Err(nb::Error::Other(error));
+        }
+        embedded_hal::serial::Read::<u8>::read(&mut self.rx).map_err(|e| e.map(UartError::Driver))
     }
 }
 
 impl embedded_hal::serial::Write<u8> for PhmUart {
-    type Error = ();
+    type Error = UartError;
 
     fn write(&mut self, output: u8) -> Result<(), nb::Error<Self::Error>> {
         embedded_hal::serial::Write::<u8>::write(&mut self.tx, output)
-            .map_err(|_| nb::Error::WouldBlock)
+            .map_err(|e| e.map(UartError::Driver))
     }
 
     fn flush(&mut self) -> Result<(), nb::Error<Self::Error>> {
-        embedded_hal::serial::Write::<u8>::flush(&mut self.tx).map_err(|_| nb::Error::WouldBlock)
+        embedded_hal::serial::Write::<u8>::flush(&mut self.tx).map_err(|e| e.map(UartError::Driver))
     }
 }
@@ -0,0 +1,90 @@
+//! # Pretty HAL Machine USB Interface
+//!
+//! This crate connects a Pretty HAL Machine worker to the PC over a USB serial port,
+//! on any MCU with a `usb-device` driver.
+
+#![no_std]
+
+use phm_worker::comms::{InterfaceComms, SerialPump};
+use usb_device::{
+    bus::{UsbBus, UsbBusAllocator},
+    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+    UsbError,
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+/// How a worker identifies itself on the USB bus
+pub struct Descriptor {
+    pub vid: u16,
+    pub pid: u16,
+    pub manufacturer: &'static str,
+    pub product: &'static str,
+    pub serial_number: &'static str,
+}
+
+impl Descriptor {
+    /// The descriptor of the stock workers, which boards can start from, e.g.
+    /// `Descriptor { product: "My Board", ..Descriptor::DEFAULT }`.
+    pub const DEFAULT: Descriptor = Descriptor {
+        vid: 0x16c0,
+        pid: 0x27dd,
+        manufacturer: "OVAR Labs",
+        product: "PHM Worker",
+        // TODO: Use some kind of unique ID. This will probably require another singleton,
+        // as the storage must be static. Probably heapless::String -> singleton!()
+        serial_number: "ajm123",
+    };
+}
+
+impl Default for Descriptor {
+    fn default() -> Self {
+        Descriptor::DEFAULT
+    }
+}
+
+/// The USB serial port of a worker, carrying messages between the PC and the
+/// [InterfaceComms] of the worker
+pub struct UsbInterface<B: UsbBus + 'static, const N: usize> {
+    serial: SerialPort<'static, B>,
+    device: UsbDevice<'static, B>,
+    pump: SerialPump<N>,
+}
+
+impl<B: UsbBus + 'static, const N: usize> UsbInterface<B, N> {
+    pub fn new(
+        bus: &'static UsbBusAllocator<B>,
+        comms: InterfaceComms<N>,
+        descriptor: &Descriptor,
+    ) -> Self {
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, UsbVidPid(descriptor.vid, descriptor.pid))
+            .manufacturer(descriptor.manufacturer)
+            .product(descriptor.product)
+            .serial_number(descriptor.serial_number)
+            .device_class(USB_CLASS_CDC)
+            .max_packet_size_0(64) // (makes control transfers 8x faster)
+            .build();
+
+        UsbInterface {
+            serial,
+            device,
+            pump: SerialPump::new(comms),
+        }
+    }
+
+    /// Service the USB device, send the pending responses to the PC, and queue the
+    /// commands received from it for the worker.
+    ///
+    /// This should be called about every millisecond, or on every USB interrupt.
+    pub fn poll(&mut self) {
+        let serial = &mut self.serial;
+        self.device.poll(&mut [&mut *serial]);
+
+        self.pump.send(|frame| serial.write(frame));
+
+        match self.pump.receive(|buf| serial.read(buf)) {
+            Ok(()) | Err(UsbError::WouldBlock) => {}
+            Err(_e) => defmt::panic!("Usb Error!"),
+        }
+    }
+}
@@ -5,10 +5,15 @@
 
 #![no_std]
 
-use embedded_hal::blocking::{i2c, spi};
+use core::convert::Infallible;
+use embedded_hal::blocking::{delay::DelayUs, i2c, spi};
+use embedded_hal::digital::v2::{InputPin, OutputPin};
 use embedded_hal::serial;
 use phm_icd::{
-    Error as IcdError, ToMcu, ToMcuI2c, ToMcuSpi, ToMcuUart, ToPc, ToPcI2c, ToPcSpi, ToPcUart,
+    custom::{from_payload, to_payload, CustomCommand, CustomPayload},
+    AdcChannel, AddressMode, Capabilities, Error as IcdError, I2cBusStatus, PulseStats, PulseTimes,
+    PwmState, SpiOp, Stats, ToMcu, ToMcuAdc, ToMcuI2c, ToMcuPwm, ToMcuSmbus, ToMcuSpi, ToMcuUart,
+    ToPc, ToPcAdc, ToPcI2c, ToPcPwm, ToPcSmbus, ToPcSpi, ToPcUart,
 };
 
 /// The worker Error type
@@ -18,18 +23,104 @@ pub enum Error {
     I2c,
     Spi,
     Uart,
+    Adc,
+    Pwm,
+    Pulse,
+    /// A custom command failed, or its payload could not be decoded or encoded.
+    Custom,
     Internal,
+    /// The worker does not have the interface, bus or feature used by the command.
+    Unsupported,
+    /// The command did not complete before its deadline.
+    Timeout,
 }
 
+impl From<Error> for IcdError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Timeout => IcdError::Timeout,
+            Error::Unsupported => IcdError::Unsupported,
+            _ => IcdError::Failed,
+        }
+    }
+}
+
+/// The default deadline for a command, in milliseconds.
+///
+/// This is shorter than the host's default timeout, so that the host receives
+/// the timeout error rather than giving up first.
+pub const DEFAULT_COMMAND_TIMEOUT_MS: u32 = 1_000;
+
+/// The time that delays, ADC sampling and pulse measurements leave before the
+/// deadline, for the driver calls around them.
+const DEADLINE_MARGIN_US: u32 = 1_000;
+
 /// Helper types for MCU-to-PC communications
 pub mod comms {
+    use core::sync::atomic::{AtomicU32, Ordering};
     use heapless::spsc::{Consumer, Producer, Queue};
-    use phm_icd::{Error as IcdError, ToMcu, ToPc};
+    use heapless::Deque;
+    use phm_icd::{Error as IcdError, Request, Response, Stats, ToMcu, ToPc};
+    use postcard::{to_slice_cobs, CobsAccumulator, FeedResult};
+
+    /// The largest encoded message to the PC.
+    pub const MAX_FRAME: usize = 256;
+
+    /// A response to the PC, tagged with the `seq` of its [Request]
+    pub type Tagged = (u16, Result<ToPc, IcdError>);
 
     /// A wrapper structure for statically allocated bidirectional queues
     pub struct CommsLink<const N: usize> {
-        pub to_pc: &'static mut Queue<Result<ToPc, IcdError>, N>,
-        pub to_mcu: &'static mut Queue<ToMcu, N>,
+        pub to_pc: &'static mut Queue<Tagged, N>,
+        pub to_mcu: &'static mut Queue<Request, N>,
+        pub stats: &'static InterfaceStats,
+    }
+
+    /// A counter incremented from a single context, and read or reset from any context
+    ///
+    /// Only uses atomic loads and stores, which are available on every target. An
+    /// increment racing with a reset may be lost.
+    #[derive(Default)]
+    pub struct Counter(AtomicU32);
+
+    impl Counter {
+        pub const fn new() -> Self {
+            Counter(AtomicU32::new(0))
+        }
+
+        pub fn increment(&self) {
+            self.0.store(self.get().wrapping_add(1), Ordering::Relaxed);
+        }
+
+        pub fn get(&self) -> u32 {
+            self.0.load(Ordering::Relaxed)
+        }
+
+        pub fn reset(&self) {
+            self.0.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Counters of the interface half, such as the USB serial handler, which are
+    /// reported by the worker
+    #[derive(Default)]
+    pub struct InterfaceStats {
+        /// Commands dropped because the queue to the worker was full.
+        pub dropped_commands: Counter,
+        /// Responses that could not be written to the PC.
+        pub dropped_responses: Counter,
+        /// Frames that could not be decoded, or did not fit in the buffer.
+        pub decode_errors: Counter,
+    }
+
+    impl InterfaceStats {
+        pub const fn new() -> Self {
+            InterfaceStats {
+                dropped_commands: Counter::new(),
+                dropped_responses: Counter::new(),
+                decode_errors: Counter::new(),
+            }
+        }
     }
 
     impl<const N: usize> CommsLink<N> {
@@ -46,10 +137,13 @@ pub mod comms {
                 WorkerComms {
                     to_pc: to_pc_prod,
                     to_mcu: to_mcu_cons,
+                    stats: self.stats,
+                    seq: 0,
                 },
                 InterfaceComms {
                     to_pc: to_pc_cons,
                     to_mcu: to_mcu_prod,
+                    stats: self.stats,
                 },
             )
         }
@@ -57,26 +151,173 @@ pub mod comms {
 
     /// The Worker half of the the CommsLink type.
     pub struct WorkerComms<const N: usize> {
-        pub to_pc: Producer<'static, Result<ToPc, IcdError>, N>,
-        pub to_mcu: Consumer<'static, ToMcu, N>,
+        pub to_pc: Producer<'static, Tagged, N>,
+        pub to_mcu: Consumer<'static, Request, N>,
+        pub stats: &'static InterfaceStats,
+        /// The `seq` of the last received command, which the next response answers.
+        seq: u16,
     }
 
     impl<const N: usize> crate::WorkerIo for WorkerComms<N> {
         type Error = ();
 
         fn send(&mut self, msg: Result<ToPc, IcdError>) -> Result<(), Self::Error> {
-            self.to_pc.enqueue(msg).map_err(drop)
+            self.to_pc.enqueue((self.seq, msg)).map_err(drop)
         }
 
         fn receive(&mut self) -> Option<ToMcu> {
-            self.to_mcu.dequeue()
+            let request = self.to_mcu.dequeue()?;
+            self.seq = request.seq;
+            Some(request.cmd)
+        }
+
+        fn read_stats(&self, stats: &mut Stats) {
+            stats.dropped_commands = stats
+                .dropped_commands
+                .wrapping_add(self.stats.dropped_commands.get());
+            stats.dropped_responses = stats
+                .dropped_responses
+                .wrapping_add(self.stats.dropped_responses.get());
+            stats.decode_errors = stats
+                .decode_errors
+                .wrapping_add(self.stats.decode_errors.get());
+        }
+
+        fn reset_stats(&mut self) {
+            self.stats.dropped_commands.reset();
+            self.stats.dropped_responses.reset();
+            self.stats.decode_errors.reset();
         }
     }
 
     /// Serial Interface half of the CommsLink type.
     pub struct InterfaceComms<const N: usize> {
-        pub to_pc: Consumer<'static, Result<ToPc, IcdError>, N>,
-        pub to_mcu: Producer<'static, ToMcu, N>,
+        pub to_pc: Consumer<'static, Tagged, N>,
+        pub to_mcu: Producer<'static, Request, N>,
+        pub stats: &'static InterfaceStats,
+    }
+
+    impl<const N: usize> InterfaceComms<N> {
+        /// The free slots in the queue to the worker, advertised to the PC as credits.
+        pub fn credits(&self) -> u8 {
+            let free = self.to_mcu.capacity() - self.to_mcu.len();
+            free.min(u8::MAX.into()) as u8
+        }
+    }
+
+    /// Moves messages between [InterfaceComms] and a byte stream to the PC, such as a
+    /// USB serial port, as COBS frames
+    ///
+    /// A frame that the stream doesn't take at once is kept, and the rest of it is
+    /// written by the next call to [SerialPump::send], before any other frame.
+    pub struct SerialPump<const N: usize> {
+        comms: InterfaceComms<N>,
+        cobs_buf: CobsAccumulator<512>,
+        tx_buf: [u8; MAX_FRAME],
+        /// The part of `tx_buf` that is still to be written.
+        tx_start: usize,
+        tx_end: usize,
+        /// The `seq` of commands rejected because the queue to the worker was full,
+        /// still to be answered with [IcdError::Busy].
+        busy: Deque<u16, N>,
+    }
+
+    impl<const N: usize> SerialPump<N> {
+        pub fn new(comms: InterfaceComms<N>) -> Self {
+            SerialPump {
+                comms,
+                cobs_buf: CobsAccumulator::new(),
+                tx_buf: [0; MAX_FRAME],
+                tx_start: 0,
+                tx_end: 0,
+                busy: Deque::new(),
+            }
+        }
+
+        /// Write the pending responses, for as long as `write` takes bytes.
+        ///
+        /// `write` returns how many bytes of the given frame it took, and an error or
+        /// zero when it is full.
+        pub fn send<E>(&mut self, mut write: impl FnMut(&[u8]) -> Result<usize, E>) {
+            loop {
+                if self.tx_start == self.tx_end && !self.next_frame() {
+                    return;
+                }
+                match write(&self.tx_buf[self.tx_start..self.tx_end]) {
+                    Ok(written) if written > 0 => self.tx_start += written,
+                    _ => return,
+                }
+            }
+        }
+
+        /// Encode the next response into `tx_buf`, returning false if there is none.
+        fn next_frame(&mut self) -> bool {
+            loop {
+                let (seq, result) = match self.busy.pop_front() {
+                    Some(seq) => (seq, Err(IcdError::Busy)),
+                    None => match self.comms.to_pc.dequeue() {
+                        Some(tagged) => tagged,
+                        None => return false,
+                    },
+                };
+                let response = Response {
+                    seq,
+                    credits: self.comms.credits(),
+                    result,
+                };
+                match to_slice_cobs(&response, &mut self.tx_buf) {
+                    Ok(frame) => {
+                        self.tx_start = 0;
+                        self.tx_end = frame.len();
+                        return true;
+                    }
+                    Err(_) => {
+                        defmt::error!("Response too large for a frame");
+                        self.comms.stats.dropped_responses.increment();
+                    }
+                }
+            }
+        }
+
+        /// Read from the PC for as long as `read` returns bytes, and queue the commands
+        /// in them for the worker.
+        ///
+        /// `read` returns how many bytes it read into the buffer, and an error or zero
+        /// when there are none. Returns the error of `read`, if any.
+        pub fn receive<E>(
+            &mut self,
+            mut read: impl FnMut(&mut [u8]) -> Result<usize, E>,
+        ) -> Result<(), E> {
+            let mut buf = [0u8; 128];
+
+            loop {
+                let mut window = match read(&mut buf)? {
+                    0 => return Ok(()),
+                    sz => &buf[..sz],
+                };
+
+                while !window.is_empty() {
+                    window = match self.cobs_buf.feed::<Request>(window) {
+                        FeedResult::Consumed => break,
+                        FeedResult::OverFull(new_wind) | FeedResult::DeserError(new_wind) => {
+                            self.comms.stats.decode_errors.increment();
+                            new_wind
+                        }
+                        FeedResult::Success { data, remaining } => {
+                            defmt::debug!("got: {:?}", data);
+                            if let Err(request) = self.comms.to_mcu.enqueue(data) {
+                                // Tell the PC, rather than leaving it waiting for a response
+                                self.comms.stats.dropped_commands.increment();
+                                if self.busy.push_back(request.seq).is_err() {
+                                    self.comms.stats.dropped_responses.increment();
+                                }
+                            }
+                            remaining
+                        }
+                    };
+                }
+            }
+        }
     }
 }
 
@@ -89,6 +330,452 @@ pub trait WorkerIo {
 
     /// Receive a message FROM the PC, TO the worker
     fn receive(&mut self) -> Option<ToMcu>;
+
+    /// Add the counters of the interface to `stats`.
+    fn read_stats(&self, _stats: &mut Stats) {}
+
+    /// Reset the counters of the interface.
+    fn reset_stats(&mut self) {}
+}
+
+/// A monotonic clock, used to enforce a deadline on every command
+pub trait Clock {
+    /// The current time in microseconds, wrapping around on overflow.
+    fn now_us(&mut self) -> u32;
+
+    /// Called when a command starts, `timeout_us` before its deadline.
+    ///
+    /// The worker can only check the deadline between driver calls. Boards can arm
+    /// a timer interrupt here that aborts a driver call still blocked at the deadline,
+    /// e.g. on an I2C device stretching the clock forever.
+    fn arm(&mut self, _timeout_us: u32) {}
+
+    /// Called when a command finishes, to cancel anything set up by [Clock::arm].
+    fn disarm(&mut self) {}
+
+    /// Whether [Clock::arm] aborts driver calls blocked at the deadline, reported
+    /// to the PC in [Capabilities::deadline_abort].
+    fn can_abort(&self) -> bool {
+        false
+    }
+}
+
+/// The most UART buses of a worker, limited by the receive buffers.
+pub const MAX_UART_BUSES: usize = 4;
+
+/// The instances of one kind of bus, addressed by their index
+///
+/// Implemented for arrays. Boards with several instances of a bus use a type
+/// covering all of them, such as an enum of the peripherals.
+pub trait Buses {
+    type Bus;
+
+    /// The number of buses.
+    fn count(&self) -> u8;
+
+    /// The bus with the given index.
+    fn bus(&mut self, index: u8) -> Option<&mut Self::Bus>;
+}
+
+impl<B, const N: usize> Buses for [B; N] {
+    type Bus = B;
+
+    fn count(&self) -> u8 {
+        N as u8
+    }
+
+    fn bus(&mut self, index: u8) -> Option<&mut B> {
+        self.get_mut(usize::from(index))
+    }
+}
+
+/// No buses at all, for workers without a kind of bus
+impl Buses for () {
+    type Bus = NoBus;
+
+    fn count(&self) -> u8 {
+        0
+    }
+
+    fn bus(&mut self, _index: u8) -> Option<&mut NoBus> {
+        None
+    }
+}
+
+/// The bus type of `()`, which can't be constructed
+pub enum NoBus {}
+
+impl i2c::Write for NoBus {
+    type Error = Infallible;
+
+    fn write(&mut self, _addr: u8, _bytes: &[u8]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl i2c::Read for NoBus {
+    type Error = Infallible;
+
+    fn read(&mut self, _addr: u8, _buffer: &mut [u8]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl i2c::WriteRead for NoBus {
+    type Error = Infallible;
+
+    fn write_read(
+        &mut self,
+        _addr: u8,
+        _bytes: &[u8],
+        _buffer: &mut [u8],
+    ) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl I2cRecovery for NoBus {}
+
+impl SmbusBlockRead for NoBus {}
+
+impl SmbusQuick for NoBus {}
+
+impl spi::Write<u8> for NoBus {
+    type Error = Infallible;
+
+    fn write(&mut self, _words: &[u8]) -> Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl spi::Transfer<u8> for NoBus {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, _words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+        match *self {}
+    }
+}
+
+impl SpiWords for NoBus {}
+
+impl serial::Write<u8> for NoBus {
+    type Error = Infallible;
+
+    fn write(&mut self, _word: u8) -> nb::Result<(), Infallible> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        match *self {}
+    }
+}
+
+impl serial::Read<u8> for NoBus {
+    type Error = Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Infallible> {
+        match *self {}
+    }
+}
+
+/// A set of SPI chip select lines, managed by the worker
+///
+/// Implemented for arrays of active-low output pins, and for `()` on boards
+/// without any chip select lines.
+pub trait ChipSelects {
+    /// The number of chip select lines.
+    fn count(&self) -> u8;
+
+    /// Assert the given chip select line.
+    fn select(&mut self, cs: u8) -> Result<(), Error>;
+
+    /// Deassert all chip select lines.
+    fn release(&mut self) -> Result<(), Error>;
+}
+
+impl<P: OutputPin, const N: usize> ChipSelects for [P; N] {
+    fn count(&self) -> u8 {
+        N as u8
+    }
+
+    fn select(&mut self, cs: u8) -> Result<(), Error> {
+        let pin = self.get_mut(usize::from(cs)).ok_or(Error::Spi)?;
+        pin.set_low().map_err(|_| Error::Spi)
+    }
+
+    fn release(&mut self) -> Result<(), Error> {
+        for pin in self.iter_mut() {
+            pin.set_high().map_err(|_| Error::Spi)?;
+        }
+        Ok(())
+    }
+}
+
+impl ChipSelects for () {
+    fn count(&self) -> u8 {
+        0
+    }
+
+    fn select(&mut self, _cs: u8) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn release(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Diagnosing and freeing a stuck I2C bus
+///
+/// The default methods return [Error::Unsupported], for boards that don't support it.
+pub trait I2cRecovery {
+    /// Read the levels of the SDA and SCL lines.
+    fn bus_status(&mut self) -> Result<I2cBusStatus, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Temporarily reconfigure SDA and SCL as GPIO, and free the bus with
+    /// [recover_i2c_bus].
+    fn recover(&mut self, _delay: &mut impl DelayUs<u32>) -> Result<I2cBusStatus, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// SMBus block reads, whose length is sent by the device
+///
+/// The default method returns [Error::Unsupported], for boards whose driver can't
+/// change the length of a read once it has started.
+pub trait SmbusBlockRead {
+    /// Write `command` to `addr`, then read the count byte sent by the device, and
+    /// exactly that many data bytes into `buf`, followed by a packet error code if
+    /// `pec` is set. Every byte but the last is acknowledged.
+    ///
+    /// `buf` starts with the count byte. Returns the number of bytes read, or
+    /// [Error::I2c] if the count is over 32.
+    fn block_read(
+        &mut self,
+        _addr: u8,
+        _command: u8,
+        _buf: &mut [u8; 34],
+        _pec: bool,
+    ) -> Result<usize, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// SMBus quick commands, which only send the address
+///
+/// The default method returns [Error::Unsupported], for boards whose driver rejects
+/// zero-length transfers, as many do, e.g. the rp2040-hal driver.
+pub trait SmbusQuick {
+    /// Send the address `addr` with the R/W bit set for a read if `read` is set,
+    /// followed by a STOP. Returns [Error::I2c] if the device doesn't acknowledge.
+    fn quick(&mut self, _addr: u8, _read: bool) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Free an I2C bus held by a device, by clocking SCL up to 9 times until the
+/// device releases SDA, then sending a STOP.
+///
+/// Both lines must be open-drain outputs that can read back the line level.
+/// Returns the levels of the lines afterwards.
+pub fn recover_i2c_bus<SDA, SCL, D>(
+    sda: &mut SDA,
+    scl: &mut SCL,
+    delay: &mut D,
+) -> Result<I2cBusStatus, Error>
+where
+    SDA: OutputPin + InputPin,
+    SCL: OutputPin + InputPin,
+    D: DelayUs<u32>,
+{
+    // Half of a 100kHz clock period
+    const HALF_PERIOD_US: u32 = 5;
+
+    sda.set_high().map_err(|_| Error::I2c)?;
+    scl.set_high().map_err(|_| Error::I2c)?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    for _ in 0..9 {
+        if sda.is_high().map_err(|_| Error::I2c)? {
+            break;
+        }
+        scl.set_low().map_err(|_| Error::I2c)?;
+        delay.delay_us(HALF_PERIOD_US);
+        scl.set_high().map_err(|_| Error::I2c)?;
+        delay.delay_us(HALF_PERIOD_US);
+    }
+
+    // STOP: SDA rises while SCL is high
+    scl.set_low().map_err(|_| Error::I2c)?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_low().map_err(|_| Error::I2c)?;
+    delay.delay_us(HALF_PERIOD_US);
+    scl.set_high().map_err(|_| Error::I2c)?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_high().map_err(|_| Error::I2c)?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    Ok(I2cBusStatus {
+        sda: sda.is_high().map_err(|_| Error::I2c)?,
+        scl: scl.is_high().map_err(|_| Error::I2c)?,
+    })
+}
+
+/// SPI transfers with words wider than 8 bits
+///
+/// The default methods only support 8-bit words, boards that can switch their
+/// SPI peripheral to 16-bit frames override all three.
+pub trait SpiWords {
+    /// The supported word sizes, in bits.
+    fn word_sizes(&self) -> &'static [u8] {
+        &[8]
+    }
+
+    /// Write words of `bits` bits, one of the [word sizes](SpiWords::word_sizes)
+    /// above 8.
+    fn write_u16(&mut self, _bits: u8, _words: &[u16]) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Transfer words of `bits` bits, one of the [word sizes](SpiWords::word_sizes)
+    /// above 8, replacing them with the words read.
+    fn transfer_u16(&mut self, _bits: u8, _words: &mut [u16]) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Analog inputs of the worker
+///
+/// Implemented for `()` on boards without analog inputs.
+pub trait Adc {
+    /// The analog input channels, in the order of their channel numbers.
+    fn channels(&self) -> &[AdcChannel];
+
+    /// Take one sample of `channel`.
+    fn read(&mut self, channel: u8) -> Result<u16, Error>;
+}
+
+impl Adc for () {
+    fn channels(&self) -> &[AdcChannel] {
+        &[]
+    }
+
+    fn read(&mut self, _channel: u8) -> Result<u16, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// PWM outputs of the worker
+///
+/// Implemented for `()` on boards without PWM outputs. Duty cycles are given as a
+/// fraction of `u16::MAX`, boards scale them to the resolution of their timers, and
+/// keep them when the frequency changes.
+pub trait Pwm {
+    /// The number of channels.
+    fn channels(&self) -> u8;
+
+    /// Set the frequency of `channel`, and of the channels sharing its timer.
+    fn set_frequency(&mut self, channel: u8, hz: u32) -> Result<(), Error>;
+
+    fn set_duty(&mut self, channel: u8, duty: u16) -> Result<(), Error>;
+
+    fn set_enabled(&mut self, channel: u8, enabled: bool) -> Result<(), Error>;
+
+    /// The state of `channel`, with the frequency actually generated.
+    fn state(&self, channel: u8) -> Result<PwmState, Error>;
+}
+
+impl Pwm for () {
+    fn channels(&self) -> u8 {
+        0
+    }
+
+    fn set_frequency(&mut self, _channel: u8, _hz: u32) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn set_duty(&mut self, _channel: u8, _duty: u16) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn set_enabled(&mut self, _channel: u8, _enabled: bool) -> Result<(), Error> {
+        Err(Error::Unsupported)
+    }
+
+    fn state(&self, _channel: u8) -> Result<PwmState, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Digital inputs of the worker for measuring pulses
+///
+/// Implemented for arrays of input pins, and for `()` on boards without pulse inputs.
+/// The worker polls the inputs, so pulses shorter than a few microseconds are missed.
+pub trait PulseInputs {
+    /// The number of inputs.
+    fn count(&self) -> u8;
+
+    /// Whether `input` is high.
+    fn is_high(&mut self, input: u8) -> Result<bool, Error>;
+}
+
+impl<P: InputPin, const N: usize> PulseInputs for [P; N] {
+    fn count(&self) -> u8 {
+        N as u8
+    }
+
+    fn is_high(&mut self, input: u8) -> Result<bool, Error> {
+        let pin = self.get(usize::from(input)).ok_or(Error::Pulse)?;
+        pin.is_high().map_err(|_| Error::Pulse)
+    }
+}
+
+impl PulseInputs for () {
+    fn count(&self) -> u8 {
+        0
+    }
+
+    fn is_high(&mut self, _input: u8) -> Result<bool, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Board-specific commands, see [phm_icd::custom]
+///
+/// Implemented for `()` on boards without custom commands. Commands defined with
+/// [CustomCommand] can be handled with [handle_typed].
+pub trait CustomHandler {
+    /// Execute the custom command `id`, returning the payload of its response.
+    ///
+    /// Unknown ids should be answered with [Error::Unsupported].
+    fn handle(&mut self, id: u16, payload: &[u8]) -> Result<CustomPayload, Error>;
+}
+
+impl CustomHandler for () {
+    fn handle(&mut self, _id: u16, _payload: &[u8]) -> Result<CustomPayload, Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Decode the request of the custom command `C` from `payload`, run it with `f`,
+/// and encode its response.
+///
+/// ```ignore
+/// fn handle(&mut self, id: u16, payload: &[u8]) -> Result<CustomPayload, Error> {
+///     match id {
+///         SetRelays::ID => handle_typed(payload, |req: SetRelays| self.set_relays(req)),
+///         _ => Err(Error::Unsupported),
+///     }
+/// }
+/// ```
+pub fn handle_typed<C: CustomCommand>(
+    payload: &[u8],
+    f: impl FnOnce(C) -> Result<C::Response, Error>,
+) -> Result<CustomPayload, Error> {
+    let request = from_payload(payload).map_err(|_| Error::Custom)?;
+    to_payload(&f(request)?).map_err(|_| Error::Custom)
 }
 
 /// A Pretty HAL Machine Worker
@@ -96,66 +783,468 @@ pub trait WorkerIo {
 /// This struct is intended to contain all of the shared logic between workers.
 /// It is highly generic, which should allow the logic to execute regardless of
 /// the MCU the worker is executing on.
-pub struct Worker<IO, I2C, SPI, UART>
-where
+///
+/// Every interface is optional. A worker starts out with none of them, and boards
+/// add the ones they have with the `with_*` methods. Commands for an interface the
+/// worker doesn't have are answered with [Error::Unsupported].
+pub struct Worker<
+    IO,
+    DELAY,
+    CLOCK,
+    I2C = (),
+    SPI = (),
+    UART = (),
+    CS = (),
+    ADC = (),
+    PWM = (),
+    PULSE = (),
+    CUSTOM = (),
+> where
     IO: WorkerIo,
-    I2C: i2c::Write + i2c::Read + i2c::WriteRead,
-    SPI: spi::Write<u8> + spi::Transfer<u8>,
-    UART: serial::Write<u8> + serial::Read<u8>,
+    DELAY: DelayUs<u32>,
+    CLOCK: Clock,
+    I2C: Buses,
+    I2C::Bus: i2c::Write + i2c::Read + i2c::WriteRead + I2cRecovery + SmbusBlockRead + SmbusQuick,
+    SPI: Buses,
+    SPI::Bus: spi::Write<u8> + spi::Transfer<u8> + SpiWords,
+    UART: Buses,
+    UART::Bus: serial::Write<u8> + serial::Read<u8>,
+    CS: ChipSelects,
+    ADC: Adc,
+    PWM: Pwm,
+    PULSE: PulseInputs,
+    CUSTOM: CustomHandler,
 {
     pub io: IO,
+    pub delay: DELAY,
+    pub clock: CLOCK,
     pub i2c: I2C,
     pub spi: SPI,
     pub uart: UART,
-    uart_rx: heapless::Deque<u8, 64>,
+    pub spi_cs: CS,
+    pub adc: ADC,
+    pub pwm: PWM,
+    pub pulse: PULSE,
+    pub custom: CUSTOM,
+    state: State,
+}
+
+/// The state of a worker that doesn't depend on its interfaces, moved as a whole
+/// when the `with_*` methods change the type of the worker
+struct State {
+    /// The bytes received by each UART bus
+    uart_rx: heapless::Vec<heapless::Deque<u8, 64>, MAX_UART_BUSES>,
+    i2c_ten_bit: bool,
+    command_timeout_us: u32,
+    deadline_us: u32,
+    stats: Stats,
 }
 
-impl<IO, I2C, SPI, UART> Worker<IO, I2C, SPI, UART>
+impl<IO, DELAY, CLOCK> Worker<IO, DELAY, CLOCK>
 where
     IO: WorkerIo,
-    I2C: i2c::Write + i2c::Read + i2c::WriteRead,
-    SPI: spi::Write<u8> + spi::Transfer<u8>,
-    UART: serial::Write<u8> + serial::Read<u8>,
+    DELAY: DelayUs<u32>,
+    CLOCK: Clock,
 {
-    pub fn new(io: IO, i2c: I2C, spi: SPI, uart: UART) -> Self {
+    /// A worker without any interfaces, which only answers pings, delays and
+    /// capability requests.
+    pub fn new(io: IO, delay: DELAY, clock: CLOCK) -> Self {
         Worker {
             io,
+            delay,
+            clock,
+            i2c: (),
+            spi: (),
+            uart: (),
+            spi_cs: (),
+            adc: (),
+            pwm: (),
+            pulse: (),
+            custom: (),
+            state: State {
+                uart_rx: heapless::Vec::new(),
+                i2c_ten_bit: false,
+                command_timeout_us: DEFAULT_COMMAND_TIMEOUT_MS * 1000,
+                deadline_us: 0,
+                stats: Stats::default(),
+            },
+        }
+    }
+}
+
+impl<IO, DELAY, CLOCK, I2C, SPI, UART, CS, ADC, PWM, PULSE, CUSTOM>
+    Worker<IO, DELAY, CLOCK, I2C, SPI, UART, CS, ADC, PWM, PULSE, CUSTOM>
+where
+    IO: WorkerIo,
+    DELAY: DelayUs<u32>,
+    CLOCK: Clock,
+    I2C: Buses,
+    I2C::Bus: i2c::Write + i2c::Read + i2c::WriteRead + I2cRecovery + SmbusBlockRead + SmbusQuick,
+    SPI: Buses,
+    SPI::Bus: spi::Write<u8> + spi::Transfer<u8> + SpiWords,
+    UART: Buses,
+    UART::Bus: serial::Write<u8> + serial::Read<u8>,
+    CS: ChipSelects,
+    ADC: Adc,
+    PWM: Pwm,
+    PULSE: PulseInputs,
+    CUSTOM: CustomHandler,
+{
+    /// Add I2C buses to the worker, such as an array of I2C drivers.
+    pub fn with_i2c<B>(
+        self,
+        i2c: B,
+    ) -> Worker<IO, DELAY, CLOCK, B, SPI, UART, CS, ADC, PWM, PULSE, CUSTOM>
+    where
+        B: Buses,
+        B::Bus: i2c::Write + i2c::Read + i2c::WriteRead + I2cRecovery + SmbusBlockRead + SmbusQuick,
+    {
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
             i2c,
-            spi,
-            uart,
-            uart_rx: heapless::Deque::new(),
+            spi: self.spi,
+            uart: self.uart,
+            spi_cs: self.spi_cs,
+            adc: self.adc,
+            pwm: self.pwm,
+            pulse: self.pulse,
+            custom: self.custom,
+            state: self.state,
         }
     }
-    /// Process any pending messages to the worker
-    pub fn step(&mut self) -> Result<(), Error> {
-        while let Ok(data_read) = serial::Read::<u8>::read(&mut self.uart) {
-            self.uart_rx.push_back(data_read).ok();
+
+    /// Add SPI buses to the worker, and the chip select lines used with them.
+    ///
+    /// Use `()` for `spi_cs` on boards without chip select lines.
+    pub fn with_spi<B, C>(
+        self,
+        spi: B,
+        mut spi_cs: C,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, B, UART, C, ADC, PWM, PULSE, CUSTOM>
+    where
+        B: Buses,
+        B::Bus: spi::Write<u8> + spi::Transfer<u8> + SpiWords,
+        C: ChipSelects,
+    {
+        // Start with all devices deselected
+        spi_cs.release().ok();
+
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi,
+            uart: self.uart,
+            spi_cs,
+            adc: self.adc,
+            pwm: self.pwm,
+            pulse: self.pulse,
+            custom: self.custom,
+            state: self.state,
         }
-        while let Some(data) = self.io.receive() {
-            let resp = match data {
-                ToMcu::I2c(i2c) => self.process_i2c(i2c),
-                ToMcu::Spi(spi) => self.process_spi(spi),
-                ToMcu::Uart(uart) => self.process_uart(uart),
-                ToMcu::Ping => {
-                    defmt::info!("Received Ping! Responding...");
-                    Ok(ToPc::Pong)
-                }
-            };
-            self.io.send(resp.map_err(drop)).map_err(|_| Error::Io)?;
+    }
+
+    /// Add UARTs to the worker, at most [MAX_UART_BUSES].
+    pub fn with_uart<B>(
+        self,
+        uart: B,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, SPI, B, CS, ADC, PWM, PULSE, CUSTOM>
+    where
+        B: Buses,
+        B::Bus: serial::Write<u8> + serial::Read<u8>,
+    {
+        let mut state = self.state;
+        state.uart_rx = (0..uart.count())
+            .take(MAX_UART_BUSES)
+            .map(|_| heapless::Deque::new())
+            .collect();
+
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi: self.spi,
+            uart,
+            spi_cs: self.spi_cs,
+            adc: self.adc,
+            pwm: self.pwm,
+            pulse: self.pulse,
+            custom: self.custom,
+            state,
         }
-        Ok(())
     }
 
-    fn process_i2c(&mut self, i2c_cmd: ToMcuI2c) -> Result<ToPc, Error> {
-        match i2c_cmd {
-            ToMcuI2c::Write { addr, output } => {
+    /// Add analog inputs to the worker.
+    pub fn with_adc<A: Adc>(
+        self,
+        adc: A,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, SPI, UART, CS, A, PWM, PULSE, CUSTOM> {
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi: self.spi,
+            uart: self.uart,
+            spi_cs: self.spi_cs,
+            adc,
+            pwm: self.pwm,
+            pulse: self.pulse,
+            custom: self.custom,
+            state: self.state,
+        }
+    }
+
+    /// Add PWM outputs to the worker.
+    pub fn with_pwm<P: Pwm>(
+        self,
+        pwm: P,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, SPI, UART, CS, ADC, P, PULSE, CUSTOM> {
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi: self.spi,
+            uart: self.uart,
+            spi_cs: self.spi_cs,
+            adc: self.adc,
+            pwm,
+            pulse: self.pulse,
+            custom: self.custom,
+            state: self.state,
+        }
+    }
+
+    /// Add pulse inputs to the worker.
+    pub fn with_pulse_inputs<P: PulseInputs>(
+        self,
+        pulse: P,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, SPI, UART, CS, ADC, PWM, P, CUSTOM> {
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi: self.spi,
+            uart: self.uart,
+            spi_cs: self.spi_cs,
+            adc: self.adc,
+            pwm: self.pwm,
+            pulse,
+            custom: self.custom,
+            state: self.state,
+        }
+    }
+
+    /// Add board-specific commands to the worker.
+    pub fn with_custom<C: CustomHandler>(
+        self,
+        custom: C,
+    ) -> Worker<IO, DELAY, CLOCK, I2C, SPI, UART, CS, ADC, PWM, PULSE, C> {
+        Worker {
+            io: self.io,
+            delay: self.delay,
+            clock: self.clock,
+            i2c: self.i2c,
+            spi: self.spi,
+            uart: self.uart,
+            spi_cs: self.spi_cs,
+            adc: self.adc,
+            pwm: self.pwm,
+            pulse: self.pulse,
+            custom,
+            state: self.state,
+        }
+    }
+
+    /// Set the deadline for each command, instead of [DEFAULT_COMMAND_TIMEOUT_MS].
+    ///
+    /// Timeouts are limited to 30 minutes, so that the deadline can be compared
+    /// with a wrapping microsecond clock.
+    pub fn with_command_timeout(mut self, timeout_ms: u32) -> Self {
+        self.state.command_timeout_us = timeout_ms.min(30 * 60 * 1000) * 1000;
+        self
+    }
+
+    /// Allow I2C devices with 10-bit addresses.
+    ///
+    /// These are addressed through the reserved 7-bit addresses `0b11110xx`, followed
+    /// by the low byte of the address, so this is only for I2C drivers that accept
+    /// the reserved addresses.
+    pub fn with_ten_bit_i2c(mut self) -> Self {
+        self.state.i2c_ten_bit = true;
+        self
+    }
+
+    /// Process any pending messages to the worker
+    pub fn step(&mut self) -> Result<(), Error> {
+        for (index, rx) in self.state.uart_rx.iter_mut().enumerate() {
+            if let Some(uart) = self.uart.bus(index as u8) {
+                loop {
+                    match serial::Read::<u8>::read(uart) {
+                        Ok(data_read) if rx.push_back(data_read).is_ok() => {}
+                        Ok(_) => count(&mut self.state.stats.uart_overruns),
+                        Err(nb::Error::WouldBlock) => break,
+                        Err(nb::Error::Other(_)) => {
+                            count(&mut self.state.stats.uart_overruns);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        while let Some(data) = self.io.receive() {
+            count(&mut self.state.stats.commands);
+            let i2c_bus = match data {
+                ToMcu::I2c { bus, .. } | ToMcu::Smbus { bus, .. } => Some(bus),
+                _ => None,
+            };
+
+            self.state.deadline_us = self
+                .clock
+                .now_us()
+                .wrapping_add(self.state.command_timeout_us);
+            self.clock.arm(self.state.command_timeout_us);
+            let resp = match data {
+                ToMcu::I2c { bus, cmd } => self.process_i2c(bus, cmd),
+                ToMcu::Smbus { bus, cmd } => self.process_smbus(bus, cmd),
+                ToMcu::Spi { bus, cmd } => self.process_spi(bus, cmd),
+                ToMcu::Uart { bus, cmd } => self.process_uart(bus, cmd),
+                ToMcu::Adc(adc) => self.process_adc(adc),
+                ToMcu::Pwm(pwm) => self.process_pwm(pwm),
+                ToMcu::Ping => {
+                    defmt::info!("Received Ping! Responding...");
+                    Ok(ToPc::Pong)
+                }
+                ToMcu::GetCapabilities => Ok(ToPc::Capabilities(Capabilities {
+                    i2c_ten_bit: self.state.i2c_ten_bit,
+                    spi_cs_count: self.spi_cs.count(),
+                    spi_word_sizes: match self.spi.bus(0) {
+                        Some(spi) => spi.word_sizes().iter().cloned().collect(),
+                        None => heapless::Vec::new(),
+                    },
+                    i2c_buses: self.i2c.count(),
+                    spi_buses: self.spi.count(),
+                    uart_buses: self.state.uart_rx.len() as u8,
+                    adc_channels: self.adc.channels().len() as u8,
+                    pwm_channels: self.pwm.channels(),
+                    pulse_inputs: self.pulse.count(),
+                    deadline_abort: self.clock.can_abort(),
+                })),
+                ToMcu::Delay { us } => self.wait_us(us),
+                ToMcu::MeasurePulse { input, gate_us } => self.measure_pulse(input, gate_us),
+                ToMcu::Custom { id, payload } => self
+                    .custom
+                    .handle(id, &payload)
+                    .map(|payload| ToPc::Custom { id, payload }),
+                ToMcu::GetStats => Ok(ToPc::Stats(self.stats())),
+                ToMcu::ResetStats => {
+                    let stats = self.stats();
+                    self.state.stats = Stats::default();
+                    self.io.reset_stats();
+                    Ok(ToPc::Stats(stats))
+                }
+            };
+            self.clock.disarm();
+
+            // A command that failed past its deadline may have been aborted halfway,
+            // which can leave a device holding the I2C bus. A command that succeeded
+            // late still completed, so its result is kept.
+            let resp = match resp {
+                Err(_) if self.deadline_passed() => {
+                    defmt::warn!("Command deadline passed");
+                    if let Some(i2c) = i2c_bus.and_then(|bus| self.i2c.bus(bus)) {
+                        i2c.recover(&mut self.delay).ok();
+                    }
+                    Err(Error::Timeout)
+                }
+                resp => resp,
+            };
+            match resp {
+                Err(Error::I2c) => count(&mut self.state.stats.i2c_errors),
+                Err(Error::Spi) => count(&mut self.state.stats.spi_errors),
+                Err(Error::Uart) => count(&mut self.state.stats.uart_errors),
+                Err(Error::Timeout) => count(&mut self.state.stats.timeouts),
+                _ => {}
+            }
+
+            // A full queue to the PC only loses this response, so keep going
+            if self.io.send(resp.map_err(IcdError::from)).is_err() {
+                count(&mut self.state.stats.dropped_responses);
+            }
+        }
+        Ok(())
+    }
+
+    /// The counters of the worker and its interface.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.state.stats.clone();
+        self.io.read_stats(&mut stats);
+        stats
+    }
+
+    fn deadline_passed(&mut self) -> bool {
+        passed(self.clock.now_us(), self.state.deadline_us)
+    }
+
+    /// Fail early with [Error::Timeout] unless something taking `duration_us` ends
+    /// at least [DEADLINE_MARGIN_US] before the deadline.
+    fn check_duration(&mut self, duration_us: u64) -> Result<(), Error> {
+        let now_us = self.clock.now_us();
+        let left_us = match passed(now_us, self.state.deadline_us) {
+            true => 0,
+            false => self.state.deadline_us.wrapping_sub(now_us),
+        };
+        match duration_us + u64::from(DEADLINE_MARGIN_US) > u64::from(left_us) {
+            true => Err(Error::Timeout),
+            false => Ok(()),
+        }
+    }
+
+    /// Wait for `us` microseconds on the clock, which must end before the deadline.
+    fn wait_us(&mut self, us: u32) -> Result<ToPc, Error> {
+        self.check_duration(us.into())?;
+        let end_us = self.clock.now_us().wrapping_add(us);
+        self.wait_until(end_us)?;
+        Ok(ToPc::DelayComplete)
+    }
+
+    /// Wait until the clock reaches `at_us`, or the deadline passes.
+    fn wait_until(&mut self, at_us: u32) -> Result<(), Error> {
+        while !passed(self.clock.now_us(), at_us) {
+            if self.deadline_passed() {
+                return Err(Error::Timeout);
+            }
+        }
+        Ok(())
+    }
+
+    fn process_i2c(&mut self, bus: u8, i2c_cmd: ToMcuI2c) -> Result<ToPc, Error> {
+        let ten_bit = self.state.i2c_ten_bit;
+        let i2c = self.i2c.bus(bus).ok_or(Error::Unsupported)?;
+        match i2c_cmd {
+            ToMcuI2c::Write { addr, mode, output } => {
+                let (bus_addr, prefix) = i2c_address(ten_bit, addr, mode)?;
+                let mut buf: heapless::Vec<u8, 65> = prefix.iter().cloned().collect();
+                buf.extend_from_slice(&output).map_err(|_| Error::I2c)?;
+
                 // embedded_hal::blocking::i2c::Write
-                match i2c::Write::write(&mut self.i2c, addr, &output) {
+                match i2c::Write::write(i2c, bus_addr, &buf) {
                     Ok(_) => Ok(ToPc::I2c(ToPcI2c::WriteComplete { addr })),
                     Err(_) => Err(Error::I2c),
                 }
             }
-            ToMcuI2c::Read { addr, to_read } => {
+            ToMcuI2c::Read {
+                addr,
+                mode,
+                to_read,
+            } => {
+                let (bus_addr, prefix) = i2c_address(ten_bit, addr, mode)?;
                 let mut buf = [0u8; 64];
                 let to_read_usize = to_read as usize;
 
@@ -164,7 +1253,15 @@ where
                 }
                 let buf_slice = &mut buf[..to_read_usize];
 
-                match i2c::Read::read(&mut self.i2c, addr, buf_slice) {
+                // A 10-bit read starts by writing the low byte of the address
+                let result = match prefix {
+                    Some(lo) => {
+                        i2c::WriteRead::write_read(i2c, bus_addr, &[lo], buf_slice).map_err(drop)
+                    }
+                    None => i2c::Read::read(i2c, bus_addr, buf_slice).map_err(drop),
+                };
+
+                match result {
                     Ok(_) => Ok(ToPc::I2c(ToPcI2c::Read {
                         addr,
                         data_read: buf_slice.iter().cloned().collect(),
@@ -174,9 +1271,14 @@ where
             }
             ToMcuI2c::WriteThenRead {
                 addr,
+                mode,
                 output,
                 to_read,
             } => {
+                let (bus_addr, prefix) = i2c_address(ten_bit, addr, mode)?;
+                let mut out: heapless::Vec<u8, 65> = prefix.iter().cloned().collect();
+                out.extend_from_slice(&output).map_err(|_| Error::I2c)?;
+
                 let mut buf = [0u8; 64];
                 let to_read_usize = to_read as usize;
 
@@ -185,7 +1287,7 @@ where
                 }
                 let buf_slice = &mut buf[..to_read_usize];
 
-                match i2c::WriteRead::write_read(&mut self.i2c, addr, &output, buf_slice) {
+                match i2c::WriteRead::write_read(i2c, bus_addr, &out, buf_slice) {
                     Ok(_) => Ok(ToPc::I2c(ToPcI2c::WriteThenRead {
                         addr,
                         data_read: buf_slice.iter().cloned().collect(),
@@ -193,12 +1295,175 @@ where
                     Err(_) => Err(Error::I2c),
                 }
             }
+            ToMcuI2c::BusStatus => Ok(ToPc::I2c(ToPcI2c::BusStatus(i2c.bus_status()?))),
+            ToMcuI2c::Recover => {
+                let status = i2c.recover(&mut self.delay)?;
+                Ok(ToPc::I2c(ToPcI2c::Recovered(status)))
+            }
         }
     }
 
-    fn process_spi(&mut self, spi_cmd: ToMcuSpi) -> Result<ToPc, Error> {
+    fn process_smbus(&mut self, bus: u8, smbus_cmd: ToMcuSmbus) -> Result<ToPc, Error> {
+        match smbus_cmd {
+            ToMcuSmbus::Quick { addr, read } => {
+                let i2c = self.i2c.bus(bus).ok_or(Error::Unsupported)?;
+                i2c.quick(addr, read)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Complete { addr }))
+            }
+            ToMcuSmbus::SendByte { addr, data, pec } => {
+                self.smbus_write(bus, addr, &[data], pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Complete { addr }))
+            }
+            ToMcuSmbus::ReceiveByte { addr, pec } => {
+                let mut data = [0u8; 1];
+                self.smbus_read(bus, addr, &[], &mut data, pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Byte {
+                    addr,
+                    data: data[0],
+                }))
+            }
+            ToMcuSmbus::WriteByte {
+                addr,
+                command,
+                data,
+                pec,
+            } => {
+                self.smbus_write(bus, addr, &[command, data], pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Complete { addr }))
+            }
+            ToMcuSmbus::ReadByte { addr, command, pec } => {
+                let mut data = [0u8; 1];
+                self.smbus_read(bus, addr, &[command], &mut data, pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Byte {
+                    addr,
+                    data: data[0],
+                }))
+            }
+            ToMcuSmbus::WriteWord {
+                addr,
+                command,
+                data,
+                pec,
+            } => {
+                let [lo, hi] = data.to_le_bytes();
+                self.smbus_write(bus, addr, &[command, lo, hi], pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Complete { addr }))
+            }
+            ToMcuSmbus::ReadWord { addr, command, pec } => {
+                let mut data = [0u8; 2];
+                self.smbus_read(bus, addr, &[command], &mut data, pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Word {
+                    addr,
+                    data: u16::from_le_bytes(data),
+                }))
+            }
+            ToMcuSmbus::ProcessCall {
+                addr,
+                command,
+                data,
+                pec,
+            } => {
+                let [lo, hi] = data.to_le_bytes();
+                let mut data = [0u8; 2];
+                self.smbus_read(bus, addr, &[command, lo, hi], &mut data, pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Word {
+                    addr,
+                    data: u16::from_le_bytes(data),
+                }))
+            }
+            ToMcuSmbus::BlockWrite {
+                addr,
+                command,
+                data,
+                pec,
+            } => {
+                let mut output: heapless::Vec<u8, 34> = heapless::Vec::new();
+                output.push(command).ok();
+                output.push(data.len() as u8).ok();
+                output.extend_from_slice(&data).ok();
+                self.smbus_write(bus, addr, &output, pec)?;
+                Ok(ToPc::Smbus(ToPcSmbus::Complete { addr }))
+            }
+            ToMcuSmbus::BlockRead { addr, command, pec } => {
+                let i2c = self.i2c.bus(bus).ok_or(Error::Unsupported)?;
+                let mut buf = [0u8; 34];
+                let len = i2c.block_read(addr, command, &mut buf, pec)?;
+
+                let count = usize::from(buf[0]);
+                if count > 32 || len != 1 + count + usize::from(pec) {
+                    return Err(Error::I2c);
+                }
+                let block = &buf[..1 + count];
+                if pec && smbus_pec(addr, &[command], block) != buf[1 + count] {
+                    return Err(Error::I2c);
+                }
+
+                Ok(ToPc::Smbus(ToPcSmbus::Block {
+                    addr,
+                    data: block[1..].iter().cloned().collect(),
+                }))
+            }
+        }
+    }
+
+    /// Write `output`, followed by its packet error code if `pec` is set.
+    fn smbus_write(&mut self, bus: u8, addr: u8, output: &[u8], pec: bool) -> Result<(), Error> {
+        let mut buf: heapless::Vec<u8, 35> = heapless::Vec::new();
+        buf.extend_from_slice(output).map_err(|_| Error::I2c)?;
+        if pec {
+            buf.push(smbus_pec(addr, output, &[]))
+                .map_err(|_| Error::I2c)?;
+        }
+        let i2c = self.i2c.bus(bus).ok_or(Error::Unsupported)?;
+        i2c::Write::write(i2c, addr, &buf).map_err(|_| Error::I2c)
+    }
+
+    /// Write `output` (if any), then read `input`, followed by its packet error code
+    /// if `pec` is set.
+    fn smbus_read(
+        &mut self,
+        bus: u8,
+        addr: u8,
+        output: &[u8],
+        input: &mut [u8],
+        pec: bool,
+    ) -> Result<(), Error> {
+        let mut buf = [0u8; 3];
+        let buf = buf
+            .get_mut(..input.len() + usize::from(pec))
+            .ok_or(Error::I2c)?;
+        self.i2c_read(bus, addr, output, buf)?;
+
+        let (data, code) = buf.split_at(input.len());
+        if pec && code[0] != smbus_pec(addr, output, data) {
+            return Err(Error::I2c);
+        }
+        input.copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Read into `input`, after writing `output` if it isn't empty.
+    fn i2c_read(
+        &mut self,
+        bus: u8,
+        addr: u8,
+        output: &[u8],
+        input: &mut [u8],
+    ) -> Result<(), Error> {
+        let i2c = self.i2c.bus(bus).ok_or(Error::Unsupported)?;
+        match output.is_empty() {
+            true => i2c::Read::read(i2c, addr, input).map_err(|_| Error::I2c),
+            false => i2c::WriteRead::write_read(i2c, addr, output, input).map_err(|_| Error::I2c),
+        }
+    }
+
+    /// Chip select lines aren't tied to a bus, so they can be used with any bus.
+    fn process_spi(&mut self, bus: u8, spi_cmd: ToMcuSpi) -> Result<ToPc, Error> {
+        if bus >= self.spi.count() {
+            return Err(Error::Unsupported);
+        }
         match spi_cmd {
-            ToMcuSpi::Write { output } => match spi::Write::write(&mut self.spi, &output) {
+            ToMcuSpi::Write { output } => match spi::Write::write(self.spi_bus(bus)?, &output) {
                 Ok(_) => Ok(ToPc::Spi(ToPcSpi::WriteComplete)),
                 Err(_) => Err(Error::Spi),
             },
@@ -212,36 +1477,886 @@ where
                 let buf_slice = &mut buf[..output.len()];
                 buf_slice.copy_from_slice(&output);
 
-                match spi::Transfer::transfer(&mut self.spi, buf_slice) {
+                match spi::Transfer::transfer(self.spi_bus(bus)?, buf_slice) {
                     Ok(_) => Ok(ToPc::Spi(ToPcSpi::Transfer {
                         data_read: buf_slice.iter().cloned().collect(),
                     })),
                     Err(_) => Err(Error::Spi),
                 }
             }
+
+            ToMcuSpi::WriteU16 { bits, output } => {
+                self.spi_words_bus(bus, bits)?.write_u16(bits, &output)?;
+                Ok(ToPc::Spi(ToPcSpi::WriteComplete))
+            }
+
+            ToMcuSpi::TransferU16 { bits, output } => {
+                let mut buf = output;
+                self.spi_words_bus(bus, bits)?
+                    .transfer_u16(bits, &mut buf)?;
+                Ok(ToPc::Spi(ToPcSpi::TransferU16 { data_read: buf }))
+            }
+
+            ToMcuSpi::Select { cs } => {
+                self.select(cs)?;
+                Ok(ToPc::Spi(ToPcSpi::Selected { cs }))
+            }
+
+            ToMcuSpi::Release => {
+                self.spi_cs.release()?;
+                Ok(ToPc::Spi(ToPcSpi::Released))
+            }
+
+            ToMcuSpi::Transaction { cs, ops, output } => {
+                if let Some(cs) = cs {
+                    self.select(cs)?;
+                }
+
+                let result = self.spi_transaction(bus, &ops, &output);
+                if cs.is_some() {
+                    self.spi_cs.release()?;
+                }
+
+                Ok(ToPc::Spi(ToPcSpi::Transaction { data_read: result? }))
+            }
+        }
+    }
+
+    /// Assert the chip select line `cs`, and deassert the others.
+    fn select(&mut self, cs: u8) -> Result<(), Error> {
+        match self.spi_cs.count() {
+            0 => return Err(Error::Unsupported),
+            count if cs >= count => return Err(Error::Spi),
+            _ => {}
+        }
+        self.spi_cs.release()?;
+        self.spi_cs.select(cs)
+    }
+
+    fn spi_bus(&mut self, bus: u8) -> Result<&mut SPI::Bus, Error> {
+        self.spi.bus(bus).ok_or(Error::Unsupported)
+    }
+
+    /// The SPI bus with index `bus`, if it supports words of `bits` bits, above 8.
+    fn spi_words_bus(&mut self, bus: u8, bits: u8) -> Result<&mut SPI::Bus, Error> {
+        let spi = self.spi_bus(bus)?;
+        match bits > 8 && spi.word_sizes().contains(&bits) {
+            true => Ok(spi),
+            false => Err(Error::Unsupported),
+        }
+    }
+
+    fn spi_transaction(
+        &mut self,
+        bus: u8,
+        ops: &[SpiOp],
+        output: &[u8],
+    ) -> Result<heapless::Vec<u8, 64>, Error> {
+        let mut output = output;
+        let mut data_read = heapless::Vec::new();
+        let mut buf = [0u8; 64];
+
+        for op in ops {
+            if self.deadline_passed() {
+                return Err(Error::Timeout);
+            }
+
+            let spi = self.spi.bus(bus).ok_or(Error::Unsupported)?;
+            let read_len = match *op {
+                SpiOp::Write { len } => {
+                    let bytes = take(&mut output, len)?;
+                    spi::Write::write(spi, bytes).map_err(|_| Error::Spi)?;
+                    0
+                }
+                SpiOp::Read { len } => {
+                    let buf_slice = buf.get_mut(..usize::from(len)).ok_or(Error::Spi)?;
+                    buf_slice.fill(0);
+                    spi::Transfer::transfer(spi, buf_slice).map_err(|_| Error::Spi)?;
+                    len
+                }
+                SpiOp::Transfer {
+                    read_len,
+                    write_len,
+                } => {
+                    let bytes = take(&mut output, write_len)?;
+                    let len = usize::from(read_len.max(write_len));
+                    let buf_slice = buf.get_mut(..len).ok_or(Error::Spi)?;
+                    buf_slice.fill(0);
+                    buf_slice[..bytes.len()].copy_from_slice(bytes);
+                    spi::Transfer::transfer(spi, buf_slice).map_err(|_| Error::Spi)?;
+                    read_len
+                }
+                SpiOp::TransferInPlace { len } => {
+                    let bytes = take(&mut output, len)?;
+                    let buf_slice = &mut buf[..bytes.len()];
+                    buf_slice.copy_from_slice(bytes);
+                    spi::Transfer::transfer(spi, buf_slice).map_err(|_| Error::Spi)?;
+                    len
+                }
+                SpiOp::DelayNs { ns } => {
+                    // Round up, the delay is a minimum
+                    self.delay.delay_us(ns.div_ceil(1000));
+                    0
+                }
+            };
+
+            data_read
+                .extend_from_slice(&buf[..usize::from(read_len)])
+                .map_err(|_| Error::Spi)?;
         }
+
+        Ok(data_read)
     }
 
-    fn process_uart(&mut self, uart_cmd: ToMcuUart) -> Result<ToPc, Error> {
+    fn process_uart(&mut self, bus: u8, uart_cmd: ToMcuUart) -> Result<ToPc, Error> {
+        let uart = self.uart.bus(bus).ok_or(Error::Unsupported)?;
         match uart_cmd {
             ToMcuUart::Write { output } => {
                 for &b in output.iter() {
-                    nb::block!(serial::Write::<u8>::write(&mut self.uart, b))
-                        .map_err(|_| Error::Uart)?;
+                    block_until(&mut self.clock, self.state.deadline_us, || {
+                        serial::Write::<u8>::write(&mut *uart, b)
+                            .map_err(|e| e.map(|_| Error::Uart))
+                    })?;
                 }
                 Ok(ToPc::Uart(ToPcUart::WriteComplete))
             }
             ToMcuUart::Flush => {
-                nb::block!(serial::Write::<u8>::flush(&mut self.uart)).map_err(|_| Error::Uart)?;
+                block_until(&mut self.clock, self.state.deadline_us, || {
+                    serial::Write::<u8>::flush(uart).map_err(|e| e.map(|_| Error::Uart))
+                })?;
                 Ok(ToPc::Uart(ToPcUart::WriteComplete))
             }
             ToMcuUart::Read => {
+                let rx = self
+                    .state
+                    .uart_rx
+                    .get_mut(usize::from(bus))
+                    .ok_or(Error::Unsupported)?;
                 let response = ToPc::Uart(ToPcUart::Read {
-                    data_read: self.uart_rx.clone().into_iter().collect(),
+                    data_read: rx.clone().into_iter().collect(),
                 });
-                self.uart_rx.clear();
+                rx.clear();
                 Ok(response)
             }
         }
     }
+
+    fn process_adc(&mut self, adc_cmd: ToMcuAdc) -> Result<ToPc, Error> {
+        if self.adc.channels().is_empty() {
+            return Err(Error::Unsupported);
+        }
+        match adc_cmd {
+            ToMcuAdc::ListChannels => Ok(ToPc::Adc(ToPcAdc::Channels(
+                self.adc.channels().iter().take(8).cloned().collect(),
+            ))),
+            ToMcuAdc::Sample {
+                channel,
+                count,
+                interval_us,
+            } => {
+                let info = *self
+                    .adc
+                    .channels()
+                    .get(usize::from(channel))
+                    .ok_or(Error::Adc)?;
+                if count > 32 {
+                    return Err(Error::Adc);
+                }
+                let duration_us = u64::from(count.saturating_sub(1)) * u64::from(interval_us);
+                self.check_duration(duration_us)?;
+
+                // Time each sample from the start, so that the interval doesn't drift
+                let start_us = self.clock.now_us();
+                let mut samples = heapless::Vec::new();
+                for i in 0..u32::from(count) {
+                    self.wait_until(start_us.wrapping_add(i * interval_us))?;
+                    samples
+                        .push(self.adc.read(channel)?)
+                        .map_err(|_| Error::Adc)?;
+                }
+
+                Ok(ToPc::Adc(ToPcAdc::Samples {
+                    channel,
+                    info,
+                    samples,
+                }))
+            }
+        }
+    }
+
+    /// Poll `input` for `gate_us`, timing its edges.
+    fn measure_pulse(&mut self, input: u8, gate_us: u32) -> Result<ToPc, Error> {
+        match self.pulse.count() {
+            0 => return Err(Error::Unsupported),
+            count if input >= count => return Err(Error::Pulse),
+            _ => {}
+        }
+        self.check_duration(gate_us.into())?;
+
+        let mut stats = PulseStats {
+            input,
+            gate_us,
+            rising_edges: 0,
+            falling_edges: 0,
+            level: self.pulse.is_high(input)?,
+            period: PulseTimes::default(),
+            high: PulseTimes::default(),
+        };
+        let mut last_rise_us = None;
+        let end_us = self.clock.now_us().wrapping_add(gate_us);
+        loop {
+            let now_us = self.clock.now_us();
+            if passed(now_us, end_us) {
+                break;
+            }
+            let level = self.pulse.is_high(input)?;
+            if level == stats.level {
+                continue;
+            }
+            stats.level = level;
+
+            // Only times between two edges seen during the gate are complete
+            match level {
+                true => {
+                    stats.rising_edges += 1;
+                    if let Some(rise_us) = last_rise_us {
+                        add_time(&mut stats.period, now_us.wrapping_sub(rise_us));
+                    }
+                    last_rise_us = Some(now_us);
+                }
+                false => {
+                    stats.falling_edges += 1;
+                    if let Some(rise_us) = last_rise_us {
+                        add_time(&mut stats.high, now_us.wrapping_sub(rise_us));
+                    }
+                }
+            }
+        }
+        Ok(ToPc::Pulse(stats))
+    }
+
+    fn process_pwm(&mut self, pwm_cmd: ToMcuPwm) -> Result<ToPc, Error> {
+        let channel = match pwm_cmd {
+            ToMcuPwm::SetFrequency { channel, .. }
+            | ToMcuPwm::SetDuty { channel, .. }
+            | ToMcuPwm::SetEnabled { channel, .. }
+            | ToMcuPwm::GetState { channel } => channel,
+        };
+        match self.pwm.channels() {
+            0 => return Err(Error::Unsupported),
+            channels if channel >= channels => return Err(Error::Pwm),
+            _ => {}
+        }
+
+        match pwm_cmd {
+            ToMcuPwm::SetFrequency { hz, .. } => self.pwm.set_frequency(channel, hz)?,
+            ToMcuPwm::SetDuty { duty, .. } => self.pwm.set_duty(channel, duty)?,
+            ToMcuPwm::SetEnabled { enabled, .. } => self.pwm.set_enabled(channel, enabled)?,
+            ToMcuPwm::GetState { .. } => {}
+        }
+
+        Ok(ToPc::Pwm(ToPcPwm::State {
+            channel,
+            state: self.pwm.state(channel)?,
+        }))
+    }
+}
+
+/// Like `nb::block!`, but gives up once `deadline_us` has passed.
+fn block_until<T>(
+    clock: &mut impl Clock,
+    deadline_us: u32,
+    mut f: impl FnMut() -> nb::Result<T, Error>,
+) -> Result<T, Error> {
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(nb::Error::Other(err)) => return Err(err),
+            Err(nb::Error::WouldBlock) if passed(clock.now_us(), deadline_us) => {
+                return Err(Error::Timeout)
+            }
+            Err(nb::Error::WouldBlock) => {}
+        }
+    }
+}
+
+/// The 7-bit address to use on the bus, and for 10-bit addresses the low byte
+/// of the address, which is written first.
+fn i2c_address(ten_bit: bool, addr: u16, mode: AddressMode) -> Result<(u8, Option<u8>), Error> {
+    match mode {
+        AddressMode::SevenBit if addr <= 0x7F => Ok((addr as u8, None)),
+        AddressMode::TenBit if !ten_bit => Err(Error::Unsupported),
+        AddressMode::TenBit if addr <= 0x3FF => {
+            Ok((0b1111000 | (addr >> 8) as u8, Some(addr as u8)))
+        }
+        _ => Err(Error::I2c),
+    }
+}
+
+fn add_time(times: &mut PulseTimes, us: u32) {
+    times.min_us = match times.count {
+        0 => us,
+        _ => times.min_us.min(us),
+    };
+    times.max_us = times.max_us.max(us);
+    times.total_us = times.total_us.saturating_add(us);
+    times.count += 1;
+}
+
+fn count(counter: &mut u32) {
+    *counter = counter.wrapping_add(1);
+}
+
+/// Whether the wrapping time `now_us` is at or after `deadline_us`.
+fn passed(now_us: u32, deadline_us: u32) -> bool {
+    now_us.wrapping_sub(deadline_us) < (1 << 31)
+}
+
+/// The SMBus packet error code of a transaction, a CRC-8 with the polynomial
+/// x^8 + x^2 + x + 1 over every byte on the bus, including the address bytes.
+fn smbus_pec(addr: u8, output: &[u8], input: &[u8]) -> u8 {
+    let write_addr = (!output.is_empty()).then_some(addr << 1);
+    let read_addr = (!input.is_empty()).then_some((addr << 1) | 1);
+
+    write_addr
+        .iter()
+        .chain(output)
+        .chain(read_addr.iter())
+        .chain(input)
+        .fold(0, |crc, &byte| {
+            (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x07,
+            })
+        })
+}
+
+/// Split the first `len` bytes off of `output`.
+fn take<'a>(output: &mut &'a [u8], len: u8) -> Result<&'a [u8], Error> {
+    let len = usize::from(len);
+    if len > output.len() {
+        return Err(Error::Spi);
+    }
+    let (bytes, rest) = output.split_at(len);
+    *output = rest;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::Cell;
+    use std::{boxed::Box, collections::VecDeque, rc::Rc, vec, vec::Vec};
+
+    use heapless::spsc::Queue;
+    use phm_icd::{Request, Response};
+    use postcard::{from_bytes_cobs, to_slice_cobs};
+
+    use super::comms::{CommsLink, InterfaceComms, InterfaceStats, SerialPump, WorkerComms};
+    use super::*;
+
+    /// Discards the log messages of the worker
+    #[defmt::global_logger]
+    struct Logger;
+
+    unsafe impl defmt::Logger for Logger {
+        fn acquire() {}
+
+        unsafe fn flush() {}
+
+        unsafe fn release() {}
+
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    defmt::timestamp!("");
+
+    /// The commands to a worker, and its responses
+    #[derive(Default)]
+    struct Io {
+        commands: VecDeque<ToMcu>,
+        responses: Vec<Result<ToPc, IcdError>>,
+    }
+
+    impl WorkerIo for Io {
+        type Error = ();
+
+        fn send(&mut self, msg: Result<ToPc, IcdError>) -> Result<(), ()> {
+            self.responses.push(msg);
+            Ok(())
+        }
+
+        fn receive(&mut self) -> Option<ToMcu> {
+            self.commands.pop_front()
+        }
+    }
+
+    /// A clock that advances by a microsecond every time it is read
+    struct FakeClock(Rc<Cell<u32>>);
+
+    impl Clock for FakeClock {
+        fn now_us(&mut self) -> u32 {
+            let now_us = self.0.get();
+            self.0.set(now_us.wrapping_add(1));
+            now_us
+        }
+    }
+
+    /// Logs each delay, and advances the clock by it
+    struct FakeDelay {
+        now_us: Rc<Cell<u32>>,
+        delays_us: Vec<u32>,
+    }
+
+    impl DelayUs<u32> for FakeDelay {
+        fn delay_us(&mut self, us: u32) {
+            self.now_us.set(self.now_us.get().wrapping_add(us));
+            self.delays_us.push(us);
+        }
+    }
+
+    /// Logs the address and the bytes written of each transfer, and answers reads
+    /// with the start of `reply`
+    struct FakeI2c {
+        now_us: Rc<Cell<u32>>,
+        transfers: Vec<(u8, Vec<u8>)>,
+        reply: Vec<u8>,
+        /// How long each transfer takes
+        stretch_us: u32,
+        /// Whether each transfer fails
+        nack: bool,
+    }
+
+    impl FakeI2c {
+        fn transfer(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            self.now_us
+                .set(self.now_us.get().wrapping_add(self.stretch_us));
+            self.transfers.push((addr, bytes.to_vec()));
+            buffer.copy_from_slice(&self.reply[..buffer.len()]);
+            match self.nack {
+                true => Err(()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    impl i2c::Write for FakeI2c {
+        type Error = ();
+
+        fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), ()> {
+            self.transfer(addr, bytes, &mut [])
+        }
+    }
+
+    impl i2c::Read for FakeI2c {
+        type Error = ();
+
+        fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), ()> {
+            self.transfer(addr, &[], buffer)
+        }
+    }
+
+    impl i2c::WriteRead for FakeI2c {
+        type Error = ();
+
+        fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+            self.transfer(addr, bytes, buffer)
+        }
+    }
+
+    impl I2cRecovery for FakeI2c {}
+
+    impl SmbusBlockRead for FakeI2c {}
+
+    impl SmbusQuick for FakeI2c {}
+
+    /// Logs the bytes written, and answers each byte with its complement
+    #[derive(Default)]
+    struct FakeSpi {
+        transfers: Vec<Vec<u8>>,
+    }
+
+    impl spi::Write<u8> for FakeSpi {
+        type Error = ();
+
+        fn write(&mut self, words: &[u8]) -> Result<(), ()> {
+            self.transfers.push(words.to_vec());
+            Ok(())
+        }
+    }
+
+    impl spi::Transfer<u8> for FakeSpi {
+        type Error = ();
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], ()> {
+            self.transfers.push(words.to_vec());
+            words.iter_mut().for_each(|word| *word = !*word);
+            Ok(words)
+        }
+    }
+
+    impl SpiWords for FakeSpi {}
+
+    /// Logs each select as `Some(cs)`, and each release as `None`
+    #[derive(Default)]
+    struct FakeCs {
+        log: Vec<Option<u8>>,
+    }
+
+    impl ChipSelects for FakeCs {
+        fn count(&self) -> u8 {
+            2
+        }
+
+        fn select(&mut self, cs: u8) -> Result<(), Error> {
+            self.log.push(Some(cs));
+            Ok(())
+        }
+
+        fn release(&mut self) -> Result<(), Error> {
+            self.log.push(None);
+            Ok(())
+        }
+    }
+
+    /// A pulse input with a 25us pulse every 100us, timed by the clock
+    struct SquareWave(Rc<Cell<u32>>);
+
+    impl PulseInputs for SquareWave {
+        fn count(&self) -> u8 {
+            1
+        }
+
+        fn is_high(&mut self, _input: u8) -> Result<bool, Error> {
+            Ok(self.0.get() % 100 < 25)
+        }
+    }
+
+    type TestWorker = Worker<
+        Io,
+        FakeDelay,
+        FakeClock,
+        [FakeI2c; 1],
+        [FakeSpi; 1],
+        (),
+        FakeCs,
+        (),
+        (),
+        SquareWave,
+    >;
+
+    /// A worker whose clock starts at `start_us`.
+    fn worker(start_us: u32) -> TestWorker {
+        let now_us = Rc::new(Cell::new(start_us));
+        let delay = FakeDelay {
+            now_us: now_us.clone(),
+            delays_us: Vec::new(),
+        };
+        let i2c = FakeI2c {
+            now_us: now_us.clone(),
+            transfers: Vec::new(),
+            reply: Vec::new(),
+            stretch_us: 0,
+            nack: false,
+        };
+        Worker::new(Io::default(), delay, FakeClock(now_us.clone()))
+            .with_i2c([i2c])
+            .with_spi([FakeSpi::default()], FakeCs::default())
+            .with_pulse_inputs(SquareWave(now_us))
+    }
+
+    /// Run a single command.
+    fn run(worker: &mut TestWorker, cmd: ToMcu) -> Result<ToPc, IcdError> {
+        worker.io.commands.push_back(cmd);
+        worker.step().unwrap();
+        worker.io.responses.pop().unwrap()
+    }
+
+    #[test]
+    fn smbus_pec_is_crc8() {
+        // The CRC-8 check value of "123456789", with 0x18 as a read address for the '1'
+        assert_eq!(smbus_pec(0x18, &[], b"23456789"), 0xF4);
+        assert_eq!(smbus_pec(0x5A, &[0x10, 0x42], &[]), 0xDF);
+        assert_eq!(smbus_pec(0x5A, &[0x10], &[0x42]), 0xA5);
+    }
+
+    #[test]
+    fn smbus_pec_is_sent_and_checked() {
+        let mut worker = worker(0);
+        let write = ToMcuSmbus::WriteByte {
+            addr: 0x5A,
+            command: 0x10,
+            data: 0x42,
+            pec: true,
+        };
+        run(&mut worker, ToMcu::Smbus { bus: 0, cmd: write }).unwrap();
+        assert_eq!(worker.i2c[0].transfers, [(0x5A, vec![0x10, 0x42, 0xDF])]);
+
+        let read = || ToMcu::Smbus {
+            bus: 0,
+            cmd: ToMcuSmbus::ReadByte {
+                addr: 0x5A,
+                command: 0x10,
+                pec: true,
+            },
+        };
+        worker.i2c[0].reply = vec![0x42, 0xA5];
+        assert!(matches!(
+            run(&mut worker, read()),
+            Ok(ToPc::Smbus(ToPcSmbus::Byte { data: 0x42, .. }))
+        ));
+        worker.i2c[0].reply = vec![0x42, 0xA6];
+        assert_eq!(run(&mut worker, read()).unwrap_err(), IcdError::Failed);
+    }
+
+    #[test]
+    fn ten_bit_addresses() {
+        use AddressMode::*;
+        assert_eq!(i2c_address(false, 0x76, SevenBit), Ok((0x76, None)));
+        assert_eq!(i2c_address(true, 0x342, TenBit), Ok((0x7B, Some(0x42))));
+        assert_eq!(i2c_address(true, 0x0FF, TenBit), Ok((0x78, Some(0xFF))));
+        assert_eq!(i2c_address(true, 0x400, TenBit), Err(Error::I2c));
+        assert_eq!(i2c_address(true, 0x80, SevenBit), Err(Error::I2c));
+        assert_eq!(i2c_address(false, 0x342, TenBit), Err(Error::Unsupported));
+
+        // A read writes the low byte of the address first
+        let mut worker = worker(0).with_ten_bit_i2c();
+        worker.i2c[0].reply = vec![0x12, 0x34];
+        let read = ToMcuI2c::Read {
+            addr: 0x342,
+            mode: TenBit,
+            to_read: 2,
+        };
+        match run(&mut worker, ToMcu::I2c { bus: 0, cmd: read }) {
+            Ok(ToPc::I2c(ToPcI2c::Read { addr, data_read })) => {
+                assert_eq!(addr, 0x342);
+                assert_eq!(&data_read[..], [0x12, 0x34]);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(worker.i2c[0].transfers, [(0x7B, vec![0x42])]);
+    }
+
+    #[test]
+    fn spi_transaction_ops_take_their_part_of_the_output() {
+        let mut worker = worker(0);
+        let ops = [
+            SpiOp::Write { len: 2 },
+            SpiOp::Read { len: 3 },
+            SpiOp::Transfer {
+                read_len: 1,
+                write_len: 2,
+            },
+            SpiOp::TransferInPlace { len: 2 },
+            SpiOp::DelayNs { ns: 1_500 },
+        ];
+        let transaction = ToMcuSpi::Transaction {
+            cs: Some(1),
+            ops: ops.iter().cloned().collect(),
+            output: [1, 2, 3, 4, 5, 6].iter().cloned().collect(),
+        };
+        match run(
+            &mut worker,
+            ToMcu::Spi {
+                bus: 0,
+                cmd: transaction,
+            },
+        ) {
+            Ok(ToPc::Spi(ToPcSpi::Transaction { data_read })) => {
+                assert_eq!(&data_read[..], [0xFF, 0xFF, 0xFF, 0xFC, 0xFA, 0xF9]);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert_eq!(
+            worker.spi[0].transfers,
+            [vec![1, 2], vec![0, 0, 0], vec![3, 4], vec![5, 6]]
+        );
+        assert_eq!(worker.delay.delays_us, [2]);
+        // Released by `with_spi`, and before selecting
+        assert_eq!(worker.spi_cs.log, [None, None, Some(1), None]);
+
+        let short = ToMcuSpi::Transaction {
+            cs: None,
+            ops: [SpiOp::Write { len: 4 }].iter().cloned().collect(),
+            output: [1, 2].iter().cloned().collect(),
+        };
+        let result = run(&mut worker, ToMcu::Spi { bus: 0, cmd: short });
+        assert_eq!(result.unwrap_err(), IcdError::Failed);
+        assert_eq!(worker.spi[0].transfers.len(), 4);
+    }
+
+    #[test]
+    fn deadlines_wrap_around() {
+        assert!(passed(100, 100));
+        assert!(passed(101, 100));
+        assert!(!passed(99, 100));
+        assert!(passed(5, u32::MAX - 5));
+        assert!(!passed(u32::MAX - 5, 5));
+
+        // Delays must end a margin before the deadline
+        let mut worker = worker(u32::MAX - 100);
+        let delay = |us| ToMcu::Delay { us };
+        assert!(matches!(
+            run(&mut worker, delay(500)),
+            Ok(ToPc::DelayComplete)
+        ));
+        assert!(matches!(
+            run(&mut worker, delay(990_000)),
+            Ok(ToPc::DelayComplete)
+        ));
+        let result = run(&mut worker, delay(999_500));
+        assert_eq!(result.unwrap_err(), IcdError::Timeout);
+    }
+
+    #[test]
+    fn late_results_are_only_timeouts_if_they_failed() {
+        let mut worker = worker(0);
+        let write = || ToMcu::I2c {
+            bus: 0,
+            cmd: ToMcuI2c::Write {
+                addr: 0x76,
+                mode: AddressMode::SevenBit,
+                output: [0xF4, 0x27].iter().cloned().collect(),
+            },
+        };
+
+        worker.i2c[0].stretch_us = 2_000_000;
+        assert!(matches!(
+            run(&mut worker, write()),
+            Ok(ToPc::I2c(ToPcI2c::WriteComplete { addr: 0x76 }))
+        ));
+        worker.i2c[0].nack = true;
+        assert_eq!(run(&mut worker, write()).unwrap_err(), IcdError::Timeout);
+        worker.i2c[0].stretch_us = 0;
+        assert_eq!(run(&mut worker, write()).unwrap_err(), IcdError::Failed);
+        assert_eq!(worker.stats().timeouts, 1);
+        assert_eq!(worker.stats().i2c_errors, 1);
+    }
+
+    #[test]
+    fn pulses_are_timed() {
+        let mut worker = worker(0);
+        let measure = |input| ToMcu::MeasurePulse {
+            input,
+            gate_us: 1_000,
+        };
+        let stats = match run(&mut worker, measure(0)) {
+            Ok(ToPc::Pulse(stats)) => stats,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert!(matches!(stats.rising_edges, 9..=10));
+        assert_eq!(stats.period.count, stats.rising_edges - 1);
+        assert_eq!(stats.period.total_us, 100 * stats.period.count);
+        assert_eq!((stats.period.min_us, stats.period.max_us), (100, 100));
+        assert_eq!((stats.high.min_us, stats.high.max_us), (25, 25));
+
+        assert_eq!(run(&mut worker, measure(1)).unwrap_err(), IcdError::Failed);
+        let too_long = ToMcu::MeasurePulse {
+            input: 0,
+            gate_us: 1_000_000,
+        };
+        assert_eq!(run(&mut worker, too_long).unwrap_err(), IcdError::Timeout);
+    }
+
+    /// A link between a worker and its interface, with queues holding `N - 1` commands.
+    fn link<const N: usize>() -> (WorkerComms<N>, InterfaceComms<N>, &'static InterfaceStats) {
+        let stats = Box::leak(Box::new(InterfaceStats::new()));
+        let link = CommsLink {
+            to_pc: Box::leak(Box::new(Queue::new())),
+            to_mcu: Box::leak(Box::new(Queue::new())),
+            stats,
+        };
+        let (worker, interface) = link.split();
+        (worker, interface, stats)
+    }
+
+    /// The frames of pings with the given `seq`s.
+    fn pings(seqs: &[u16]) -> Vec<u8> {
+        let mut frames = Vec::new();
+        for &seq in seqs {
+            let mut buf = [0u8; 16];
+            let request = Request {
+                seq,
+                cmd: ToMcu::Ping,
+            };
+            frames.extend_from_slice(to_slice_cobs(&request, &mut buf).unwrap());
+        }
+        frames
+    }
+
+    /// Pass `bytes` to the pump in a single read.
+    fn receive<const N: usize>(pump: &mut SerialPump<N>, bytes: &[u8]) {
+        let mut bytes = Some(bytes);
+        let read = |buf: &mut [u8]| -> Result<usize, ()> {
+            match bytes.take() {
+                Some(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                None => Ok(0),
+            }
+        };
+        pump.receive(read).unwrap();
+    }
+
+    /// Take at most `limit` bytes from the pump, in writes of at most 3 bytes.
+    fn send<const N: usize>(pump: &mut SerialPump<N>, limit: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        pump.send(|frame| -> Result<usize, ()> {
+            let len = frame.len().min(3).min(limit - out.len());
+            out.extend_from_slice(&frame[..len]);
+            Ok(len)
+        });
+        out
+    }
+
+    fn responses(bytes: &[u8]) -> Vec<Response> {
+        bytes
+            .split_inclusive(|&b| b == 0)
+            .map(|frame| from_bytes_cobs(&mut frame.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn credits_are_the_free_slots_of_the_command_queue() {
+        let (mut worker, interface, _) = link::<4>();
+        let mut pump = SerialPump::new(interface);
+
+        receive(&mut pump, &pings(&[1, 2]));
+        assert_eq!(worker.receive().map(|_| ()), Some(()));
+        worker.send(Ok(ToPc::Pong)).unwrap();
+
+        let responses = responses(&send(&mut pump, usize::MAX));
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0].seq, 1);
+        // One of three slots is still taken by the second ping
+        assert_eq!(responses[0].credits, 2);
+        assert!(matches!(responses[0].result, Ok(ToPc::Pong)));
+    }
+
+    #[test]
+    fn serial_pump_rejects_commands_when_busy_and_resumes_frames() {
+        let (mut worker, interface, stats) = link::<2>();
+        let mut pump = SerialPump::new(interface);
+
+        receive(&mut pump, &pings(&[1, 2, 3]));
+        assert_eq!(stats.dropped_commands.get(), 2);
+
+        // Rejected commands are answered first, and a frame that doesn't fit is
+        // finished by the next call
+        let mut out = send(&mut pump, 4);
+        assert_eq!(out.len(), 4);
+        assert!(worker.receive().is_some());
+        worker.send(Ok(ToPc::Pong)).unwrap();
+        out.extend(send(&mut pump, usize::MAX));
+
+        let responses = responses(&out);
+        let seqs: Vec<u16> = responses.iter().map(|r| r.seq).collect();
+        assert_eq!(seqs, [2, 3, 1]);
+        assert!(matches!(responses[0].result, Err(IcdError::Busy)));
+        assert!(matches!(responses[1].result, Err(IcdError::Busy)));
+        assert!(matches!(responses[2].result, Ok(ToPc::Pong)));
+        assert_eq!(stats.dropped_responses.get(), 0);
+    }
 }
@@ -0,0 +1,42 @@
+use embedded_hal::adc::OneShot;
+use phm_icd::AdcChannel;
+use phm_worker::{Adc as WorkerAdc, Error};
+use rp_pico::hal::{
+    adc::Adc,
+    gpio::pin::{
+        bank0::{Gpio26, Gpio27, Gpio28},
+        FloatingInput, Pin,
+    },
+};
+
+/// 12-bit samples, with the Pico's 3.3V reference
+const CHANNEL: AdcChannel = AdcChannel {
+    resolution_bits: 12,
+    full_scale_mv: 3300,
+};
+static CHANNELS: [AdcChannel; 3] = [CHANNEL; 3];
+
+/// The ADC, on GPIO26 (channel 0), GPIO27 (channel 1) and GPIO28 (channel 2)
+pub struct PhmAdc {
+    pub adc: Adc,
+    pub gpio26: Pin<Gpio26, FloatingInput>,
+    pub gpio27: Pin<Gpio27, FloatingInput>,
+    pub gpio28: Pin<Gpio28, FloatingInput>,
+}
+
+impl WorkerAdc for PhmAdc {
+    fn channels(&self) -> &[AdcChannel] {
+        &CHANNELS
+    }
+
+    fn read(&mut self, channel: u8) -> Result<u16, Error> {
+        let adc = &mut self.adc;
+        let sample: Result<u16, ()> = match channel {
+            0 => nb::block!(adc.read(&mut self.gpio26)),
+            1 => nb::block!(adc.read(&mut self.gpio27)),
+            2 => nb::block!(adc.read(&mut self.gpio28)),
+            _ => return Err(Error::Adc),
+        };
+        sample.map_err(|_| Error::Adc)
+    }
+}
@@ -0,0 +1,325 @@
+use core::{
+    convert::Infallible,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use embedded_hal::{
+    blocking::{
+        delay::DelayUs,
+        i2c::{Read, Write, WriteRead},
+    },
+    digital::v2::{InputPin, OutputPin},
+};
+use phm_icd::I2cBusStatus;
+use phm_worker::{recover_i2c_bus, Error, I2cRecovery, SmbusBlockRead, SmbusQuick};
+use rp_pico::{
+    hal::{
+        gpio::pin::{
+            bank0::{Gpio16, Gpio17, Gpio18, Gpio19},
+            FunctionI2C, Pin,
+        },
+        i2c::Error as I2cError,
+        I2C,
+    },
+    pac::{self, I2C0, I2C1},
+};
+
+// GPIO function select values
+const FUNCSEL_I2C: u32 = 3;
+const FUNCSEL_SIO: u32 = 5;
+
+/// The controller of the most recent transfer, the one to abort
+static ACTIVE: AtomicU8 = AtomicU8::new(0);
+
+/// One of the I2C buses: I2C0 on GPIO16 (SDA) and GPIO17 (SCL), or I2C1 on
+/// GPIO18 (SDA) and GPIO19 (SCL)
+pub enum PhmI2c {
+    I2c0(I2C<I2C0, (Pin<Gpio16, FunctionI2C>, Pin<Gpio17, FunctionI2C>)>),
+    I2c1(I2C<I2C1, (Pin<Gpio18, FunctionI2C>, Pin<Gpio19, FunctionI2C>)>),
+}
+
+/// Run `$body` with `$i2c` bound to the driver of `$self`, marking its controller as active
+macro_rules! with_i2c {
+    ($self:ident, |$i2c:ident| $body:expr) => {
+        match $self {
+            PhmI2c::I2c0($i2c) => {
+                ACTIVE.store(0, Ordering::Relaxed);
+                $body
+            }
+            PhmI2c::I2c1($i2c) => {
+                ACTIVE.store(1, Ordering::Relaxed);
+                $body
+            }
+        }
+    };
+}
+
+impl PhmI2c {
+    /// Abort the current transfer from another context, such as a timer interrupt.
+    ///
+    /// The controller flushes its FIFO and flags the abort, which makes a blocked
+    /// driver call return with an error. The worker then calls
+    /// [recover](I2cRecovery::recover), which resets the controller.
+    pub fn abort() {
+        let regs = match ACTIVE.load(Ordering::Relaxed) {
+            0 => I2C0::ptr(),
+            _ => I2C1::ptr(),
+        };
+        // Safety: only sets the self-clearing ABORT bit, which is not otherwise
+        // used by the driver
+        let regs = unsafe { &*regs };
+        regs.ic_enable.modify(|_, w| w.abort().set_bit());
+    }
+
+    /// The registers of the controller, marking it as active
+    fn regs(&self) -> &'static pac::i2c0::RegisterBlock {
+        let (index, regs) = match self {
+            PhmI2c::I2c0(_) => (0, I2C0::ptr()),
+            PhmI2c::I2c1(_) => (1, I2C1::ptr()),
+        };
+        ACTIVE.store(index, Ordering::Relaxed);
+        // SAFETY: the controller belongs to the driver in `self`, which is not used
+        // while the registers are
+        unsafe { &*regs }
+    }
+
+    /// Reset the controller, which an aborted transfer can leave waiting on the
+    /// device, and restore the configuration set up by the driver.
+    fn reset_controller(&self) {
+        let regs = self.regs();
+        let con = regs.ic_con.read().bits();
+        let hcnt = regs.ic_fs_scl_hcnt.read().bits();
+        let lcnt = regs.ic_fs_scl_lcnt.read().bits();
+        let spklen = regs.ic_fs_spklen.read().bits();
+        let sda_hold = regs.ic_sda_hold.read().bits();
+        let tx_tl = regs.ic_tx_tl.read().bits();
+        let rx_tl = regs.ic_rx_tl.read().bits();
+
+        // SAFETY: RESETS is only used during init otherwise, and this only toggles
+        // the reset of our own controller
+        let resets = unsafe { &*pac::RESETS::ptr() };
+        match self {
+            PhmI2c::I2c0(_) => {
+                resets.reset.modify(|_, w| w.i2c0().set_bit());
+                resets.reset.modify(|_, w| w.i2c0().clear_bit());
+                while resets.reset_done.read().i2c0().bit_is_clear() {}
+            }
+            PhmI2c::I2c1(_) => {
+                resets.reset.modify(|_, w| w.i2c1().set_bit());
+                resets.reset.modify(|_, w| w.i2c1().clear_bit());
+                while resets.reset_done.read().i2c1().bit_is_clear() {}
+            }
+        }
+
+        // SAFETY: the values were read from the same registers
+        regs.ic_enable.write(|w| w.enable().disabled());
+        regs.ic_con.write(|w| unsafe { w.bits(con) });
+        regs.ic_fs_scl_hcnt.write(|w| unsafe { w.bits(hcnt) });
+        regs.ic_fs_scl_lcnt.write(|w| unsafe { w.bits(lcnt) });
+        regs.ic_fs_spklen.write(|w| unsafe { w.bits(spklen) });
+        regs.ic_sda_hold.write(|w| unsafe { w.bits(sda_hold) });
+        regs.ic_tx_tl.write(|w| unsafe { w.bits(tx_tl) });
+        regs.ic_rx_tl.write(|w| unsafe { w.bits(rx_tl) });
+        regs.ic_enable.write(|w| w.enable().enabled());
+    }
+
+    /// The GPIO numbers of SDA and SCL
+    fn pins(&self) -> (usize, usize) {
+        match self {
+            PhmI2c::I2c0(_) => (16, 17),
+            PhmI2c::I2c1(_) => (18, 19),
+        }
+    }
+}
+
+impl Write for PhmI2c {
+    type Error = I2cError;
+
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        with_i2c!(self, |i2c| i2c.write(addr, bytes))
+    }
+}
+
+impl Read for PhmI2c {
+    type Error = I2cError;
+
+    fn read(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        with_i2c!(self, |i2c| i2c.read(addr, buffer))
+    }
+}
+
+impl WriteRead for PhmI2c {
+    type Error = I2cError;
+
+    fn write_read(&mut self, addr: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Self::Error> {
+        with_i2c!(self, |i2c| i2c.write_read(addr, bytes, buffer))
+    }
+}
+
+impl I2cRecovery for PhmI2c {
+    fn bus_status(&mut self) -> Result<I2cBusStatus, Error> {
+        // The input levels can be read whichever function drives the pins
+        let (sda, scl) = self.pins();
+        Ok(I2cBusStatus {
+            sda: level(sda),
+            scl: level(scl),
+        })
+    }
+
+    fn recover(&mut self, delay: &mut impl DelayUs<u32>) -> Result<I2cBusStatus, Error> {
+        // The I2C driver keeps the pins, so switch them over to SIO behind its back,
+        // and back to I2C when done.
+        let (sda, scl) = self.pins();
+        let mut sda = SioLine::take(sda);
+        let mut scl = SioLine::take(scl);
+        let status = recover_i2c_bus(&mut sda, &mut scl, delay);
+        sda.release();
+        scl.release();
+
+        // Start over with a fresh controller, in case it saw part of the recovery or
+        // was left halfway through an aborted transfer
+        self.reset_controller();
+        status
+    }
+}
+
+impl SmbusBlockRead for PhmI2c {
+    fn block_read(
+        &mut self,
+        addr: u8,
+        command: u8,
+        buf: &mut [u8; 34],
+        pec: bool,
+    ) -> Result<usize, Error> {
+        // The driver only reads a length given up front, so use the controller
+        // directly. It holds SCL low while it has no more commands, so the count byte
+        // can be read before the rest of the block is requested.
+        let regs = self.regs();
+        regs.ic_enable.write(|w| w.enable().disabled());
+        regs.ic_tar
+            .write(|w| unsafe { w.ic_tar().bits(addr.into()) });
+        regs.ic_enable.write(|w| w.enable().enabled());
+        regs.ic_clr_tx_abrt.read();
+        regs.ic_clr_stop_det.read();
+
+        regs.ic_data_cmd.write(|w| unsafe { w.dat().bits(command) });
+        buf[0] = read_byte(regs, true, false)?;
+
+        let count = usize::from(buf[0]);
+        if count > 32 || (count == 0 && !pec) {
+            // The count byte was acknowledged, so one more byte ends the read
+            read_byte(regs, false, true)?;
+            wait_for_stop(regs)?;
+            return match count {
+                0 => Ok(1),
+                _ => Err(Error::I2c),
+            };
+        }
+
+        let len = 1 + count + usize::from(pec);
+        for (i, byte) in buf[1..len].iter_mut().enumerate() {
+            *byte = read_byte(regs, false, i == len - 2)?;
+        }
+        wait_for_stop(regs)?;
+        Ok(len)
+    }
+}
+
+// The controller always transfers at least one data byte after the address, so
+// quick commands are unsupported
+impl SmbusQuick for PhmI2c {}
+
+/// Request one byte from the device, ending with a STOP and without an ACK if `stop`
+/// is set, and wait for it.
+fn read_byte(regs: &pac::i2c0::RegisterBlock, restart: bool, stop: bool) -> Result<u8, Error> {
+    regs.ic_data_cmd
+        .write(|w| w.cmd().set_bit().restart().bit(restart).stop().bit(stop));
+    while regs.ic_rxflr.read().bits() == 0 {
+        check_abort(regs)?;
+    }
+    Ok(regs.ic_data_cmd.read().dat().bits())
+}
+
+fn wait_for_stop(regs: &pac::i2c0::RegisterBlock) -> Result<(), Error> {
+    while regs.ic_raw_intr_stat.read().stop_det().bit_is_clear() {
+        check_abort(regs)?;
+    }
+    regs.ic_clr_stop_det.read();
+    Ok(())
+}
+
+/// Fail if the controller gave up on the transfer, e.g. on a NACK or after
+/// [PhmI2c::abort].
+fn check_abort(regs: &pac::i2c0::RegisterBlock) -> Result<(), Error> {
+    if regs.ic_raw_intr_stat.read().tx_abrt().bit_is_set() {
+        // Reading the clear register clears the abort, and lets the FIFOs run again
+        regs.ic_clr_tx_abrt.read();
+        return Err(Error::I2c);
+    }
+    Ok(())
+}
+
+fn level(pin: usize) -> bool {
+    // SAFETY: read-only access to the input levels
+    let sio = unsafe { &*pac::SIO::ptr() };
+    sio.gpio_in.read().bits() & (1 << pin) != 0
+}
+
+/// A bus line driven as an open-drain output through SIO. The output value is
+/// always low, and the line is released by disabling the output.
+struct SioLine {
+    pin: usize,
+}
+
+impl SioLine {
+    fn take(pin: usize) -> Self {
+        // SAFETY: the pin belongs to the I2C driver, which is not used until the
+        // pin is released again. The set/clear registers don't affect other pins.
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_oe_clr.write(|w| unsafe { w.bits(1 << pin) });
+        sio.gpio_out_clr.write(|w| unsafe { w.bits(1 << pin) });
+        set_funcsel(pin, FUNCSEL_SIO);
+        SioLine { pin }
+    }
+
+    fn release(self) {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_oe_clr.write(|w| unsafe { w.bits(1 << self.pin) });
+        set_funcsel(self.pin, FUNCSEL_I2C);
+    }
+}
+
+fn set_funcsel(pin: usize, funcsel: u32) {
+    // SAFETY: only called for the pins of the I2C driver
+    let io_bank0 = unsafe { &*pac::IO_BANK0::ptr() };
+    io_bank0.gpio[pin]
+        .gpio_ctrl
+        .write(|w| unsafe { w.bits(funcsel) });
+}
+
+impl OutputPin for SioLine {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_oe_set.write(|w| unsafe { w.bits(1 << self.pin) });
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let sio = unsafe { &*pac::SIO::ptr() };
+        sio.gpio_oe_clr.write(|w| unsafe { w.bits(1 << self.pin) });
+        Ok(())
+    }
+}
+
+impl InputPin for SioLine {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(level(self.pin))
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!level(self.pin))
+    }
+}
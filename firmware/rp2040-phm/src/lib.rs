@@ -1,5 +1,11 @@
 #![no_std]
 
+pub mod adc;
+pub mod i2c;
+pub mod pwm;
+pub mod spi;
+pub mod uart;
+
 use core::sync::atomic::{AtomicUsize, Ordering};
 use defmt_rtt as _;
 use panic_probe as _;
@@ -5,37 +5,69 @@ use rp2040_phm as _; // global logger + panicking-behavior + memory layout
 
 #[rtic::app(device = rp_pico::hal::pac, dispatchers = [XIP_IRQ])]
 mod app {
+    use cortex_m::delay::Delay;
     use defmt::unwrap;
-    use embedded_time::rate::Extensions;
+    use embedded_time::{fixed_point::FixedPoint, rate::Extensions};
     use heapless::spsc::Queue;
-    use phm_icd::{ToMcu, ToPc};
+    use phm_icd::Request;
+    use phm_usb::{Descriptor, UsbInterface};
     use phm_worker::{
-        comms::{CommsLink, InterfaceComms, WorkerComms},
+        comms::{CommsLink, InterfaceStats, Tagged, WorkerComms},
         Worker,
     };
-    use postcard::{to_vec_cobs, CobsAccumulator, FeedResult};
     use rp2040_monotonic::*;
+    use rp2040_phm::{adc::PhmAdc, i2c::PhmI2c, pwm::PhmPwm, spi::PhmSpi, uart::PhmUart};
     use rp_pico::{
         hal::{
+            adc::Adc,
             clocks::init_clocks_and_plls,
-            gpio::pin::{
-                bank0::{Gpio16, Gpio17},
-                FunctionI2C, FunctionSpi, FunctionUart, Pin,
+            gpio::{
+                pin::{FunctionI2C, FunctionSpi, FunctionUart},
+                DynPin,
             },
-            spi::{self, Spi},
-            uart::{common_configs as UartConfig, Enabled as UartEnabled, UartPeripheral},
+            pwm::Slices,
+            spi::Spi,
+            uart::{common_configs as UartConfig, UartPeripheral},
             usb::UsbBus,
             watchdog::Watchdog,
             Clock, Sio, I2C,
         },
-        pac::{I2C0, SPI0, UART0},
         XOSC_CRYSTAL_FREQ,
     };
-    use usb_device::{class_prelude::*, prelude::*};
-    use usbd_serial::{SerialPort, USB_CLASS_CDC};
-    type PhmI2c = I2C<I2C0, (Pin<Gpio16, FunctionI2C>, Pin<Gpio17, FunctionI2C>)>;
-    type PhmSpi = Spi<spi::Enabled, SPI0, 8>;
-    type PhmUart = UartPeripheral<UartEnabled, UART0>;
+    use usb_device::class_prelude::UsbBusAllocator;
+    type PhmSpiCs = [DynPin; 3];
+    type PhmPulseInputs = [DynPin; 2];
+
+    /// This board identifies itself by its own product name
+    const USB_DESCRIPTOR: Descriptor = Descriptor {
+        product: "Powerbus Mini",
+        ..Descriptor::DEFAULT
+    };
+
+    /// Command deadlines for the worker, from the monotonic timer
+    pub struct PhmClock {
+        abort: Option<abort_command::SpawnHandle>,
+    }
+
+    impl phm_worker::Clock for PhmClock {
+        fn now_us(&mut self) -> u32 {
+            monotonics::now().duration_since_epoch().to_micros() as u32
+        }
+
+        fn arm(&mut self, timeout_us: u32) {
+            self.abort = abort_command::spawn_after(u64::from(timeout_us).micros()).ok();
+        }
+
+        fn disarm(&mut self) {
+            if let Some(handle) = self.abort.take() {
+                handle.cancel().ok();
+            }
+        }
+
+        fn can_abort(&self) -> bool {
+            true
+        }
+    }
 
     #[monotonic(binds = TIMER_IRQ_0, default = true)]
     type Monotonic = Rp2040Monotonic;
@@ -45,16 +77,26 @@ mod app {
 
     #[local]
     struct Local {
-        interface_comms: InterfaceComms<8>,
-        worker: Worker<WorkerComms<8>, PhmI2c, PhmSpi, PhmUart>,
-        usb_serial: SerialPort<'static, UsbBus>,
-        usb_dev: UsbDevice<'static, UsbBus>,
+        worker: Worker<
+            WorkerComms<8>,
+            Delay,
+            PhmClock,
+            [PhmI2c; 2],
+            [PhmSpi; 2],
+            [PhmUart; 2],
+            PhmSpiCs,
+            PhmAdc,
+            PhmPwm,
+            PhmPulseInputs,
+        >,
+        usb: UsbInterface<UsbBus, 8>,
     }
 
     #[init(local = [
         usb_bus: Option<UsbBusAllocator<UsbBus>> = None,
-        incoming: Queue<ToMcu, 8> = Queue::new(),
-        outgoing: Queue<Result<ToPc, ()>, 8> = Queue::new(),
+        incoming: Queue<Request, 8> = Queue::new(),
+        outgoing: Queue<Tagged, 8> = Queue::new(),
+        interface_stats: InterfaceStats = InterfaceStats::new(),
     ])]
     fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let device = cx.device;
@@ -75,6 +117,10 @@ mod app {
         .unwrap();
         let pclk_freq = clocks.peripheral_clock.freq();
 
+        // SysTick is used for delays requested by the host
+        let sys_freq = clocks.system_clock.freq().integer();
+        let delay = Delay::new(cx.core.SYST, sys_freq);
+
         // Configure the monotonic timer
         let mono = Monotonic::new(device.TIMER);
 
@@ -89,37 +135,91 @@ mod app {
             &mut resets,
         );
 
-        // Set up the I2C pins and driver
+        // Set up the I2C pins and drivers
         let sda_pin = pins.gpio16.into_mode::<FunctionI2C>();
         let scl_pin = pins.gpio17.into_mode::<FunctionI2C>();
-        let i2c = I2C::i2c0(
+        let i2c0 = PhmI2c::I2c0(I2C::i2c0(
             device.I2C0,
             sda_pin,
             scl_pin,
             100.kHz(),
             &mut resets,
-            clocks.peripheral_clock,
-        );
+            pclk_freq,
+        ));
+        let sda_pin = pins.gpio18.into_mode::<FunctionI2C>();
+        let scl_pin = pins.gpio19.into_mode::<FunctionI2C>();
+        let i2c1 = PhmI2c::I2c1(I2C::i2c1(
+            device.I2C1,
+            sda_pin,
+            scl_pin,
+            100.kHz(),
+            &mut resets,
+            pclk_freq,
+        ));
 
-        // Set up the SPI pins and driver
+        // Set up the SPI pins and drivers
         let _sck = pins.gpio2.into_mode::<FunctionSpi>();
         let _mosi = pins.gpio3.into_mode::<FunctionSpi>();
         let _miso = pins.gpio4.into_mode::<FunctionSpi>();
-        let spi = Spi::<_, _, 8>::new(device.SPI0).init(
+        let spi0 = PhmSpi::Spi0(Spi::<_, _, 8>::new(device.SPI0).init(
             &mut resets,
             pclk_freq,
             2_000_000_u32.Hz(),
             &embedded_hal::spi::MODE_0,
-        );
+        ));
+        let _sck = pins.gpio14.into_mode::<FunctionSpi>();
+        let _mosi = pins.gpio15.into_mode::<FunctionSpi>();
+        let _miso = pins.gpio12.into_mode::<FunctionSpi>();
+        let spi1 = PhmSpi::Spi1(Spi::<_, _, 8>::new(device.SPI1).init(
+            &mut resets,
+            pclk_freq,
+            2_000_000_u32.Hz(),
+            &embedded_hal::spi::MODE_0,
+        ));
 
-        // Set up UART
+        // Set up the SPI chip select pins, which are active low
+        let mut spi_cs: PhmSpiCs = [pins.gpio5.into(), pins.gpio6.into(), pins.gpio7.into()];
+        for pin in spi_cs.iter_mut() {
+            pin.into_push_pull_output();
+        }
+
+        // Set up the UARTs
         let _tx_pin = pins.gpio0.into_mode::<FunctionUart>();
         let _rx_pin = pins.gpio1.into_mode::<FunctionUart>();
-        let uart = UartPeripheral::new(device.UART0, &mut resets)
-            .enable(UartConfig::_9600_8_N_1, pclk_freq)
-            .unwrap();
+        let uart0 = PhmUart::Uart0(
+            UartPeripheral::new(device.UART0, &mut resets)
+                .enable(UartConfig::_9600_8_N_1, pclk_freq)
+                .unwrap(),
+        );
+        let _tx_pin = pins.gpio20.into_mode::<FunctionUart>();
+        let _rx_pin = pins.gpio21.into_mode::<FunctionUart>();
+        let uart1 = PhmUart::Uart1(
+            UartPeripheral::new(device.UART1, &mut resets)
+                .enable(UartConfig::_9600_8_N_1, pclk_freq)
+                .unwrap(),
+        );
+
+        // Set up the ADC inputs
+        let adc = PhmAdc {
+            adc: Adc::new(device.ADC, &mut resets),
+            gpio26: pins.gpio26.into_floating_input(),
+            gpio27: pins.gpio27.into_floating_input(),
+            gpio28: pins.gpio28.into_floating_input(),
+        };
+
+        // Set up the PWM outputs
+        let mut slices = Slices::new(device.PWM, &mut resets);
+        slices.pwm4.channel_a.output_to(pins.gpio8);
+        slices.pwm5.channel_a.output_to(pins.gpio10);
+        let pwm = PhmPwm::new(slices.pwm4, slices.pwm5, sys_freq);
+
+        // Set up the pulse inputs
+        let mut pulse_inputs: PhmPulseInputs = [pins.gpio9.into(), pins.gpio11.into()];
+        for pin in pulse_inputs.iter_mut() {
+            pin.into_floating_input();
+        }
 
-        // Set up USB Serial Port
+        // Set up the USB bus, for the serial port of the worker
         let usb_bus = cx.local.usb_bus;
         usb_bus.replace(UsbBusAllocator::new(UsbBus::new(
             device.USBCTRL_REGS,
@@ -128,82 +228,42 @@ mod app {
             true,
             &mut resets,
         )));
-        let usb_serial = SerialPort::new(usb_bus.as_ref().unwrap());
-        let usb_dev = UsbDeviceBuilder::new(usb_bus.as_ref().unwrap(), UsbVidPid(0x16c0, 0x27dd))
-            .manufacturer("OVAR Labs")
-            .product("Powerbus Mini")
-            // TODO: Use some kind of unique ID. This will probably require another singleton,
-            // as the storage must be static. Probably heapless::String -> singleton!()
-            .serial_number("ajm123")
-            .device_class(USB_CLASS_CDC)
-            .max_packet_size_0(64) // (makes control transfers 8x faster)
-            .build();
 
         let comms = CommsLink {
             to_pc: cx.local.outgoing,
             to_mcu: cx.local.incoming,
+            stats: cx.local.interface_stats,
         };
 
         let (worker_comms, interface_comms) = comms.split();
+        let usb = UsbInterface::new(usb_bus.as_ref().unwrap(), interface_comms, &USB_DESCRIPTOR);
 
-        let worker = Worker::new(worker_comms, i2c, spi, uart);
+        // No 10-bit I2C addresses, as the I2C driver rejects the reserved addresses they use
+        let worker = Worker::new(worker_comms, delay, PhmClock { abort: None })
+            .with_i2c([i2c0, i2c1])
+            .with_spi([spi0, spi1], spi_cs)
+            .with_uart([uart0, uart1])
+            .with_adc(adc)
+            .with_pwm(pwm)
+            .with_pulse_inputs(pulse_inputs);
 
         usb_tick::spawn().ok();
-        (
-            Shared {},
-            Local {
-                worker,
-                interface_comms,
-                usb_serial,
-                usb_dev,
-            },
-            init::Monotonics(mono),
-        )
+        (Shared {}, Local { worker, usb }, init::Monotonics(mono))
     }
 
-    #[task(local = [usb_serial, interface_comms, usb_dev, cobs_buf: CobsAccumulator<512> = CobsAccumulator::new()])]
+    #[task(local = [usb])]
     fn usb_tick(cx: usb_tick::Context) {
-        let usb_serial = cx.local.usb_serial;
-        let usb_dev = cx.local.usb_dev;
-        let cobs_buf = cx.local.cobs_buf;
-        let interface_comms = cx.local.interface_comms;
-
-        let mut buf = [0u8; 128];
-
-        usb_dev.poll(&mut [usb_serial]);
-
-        if let Some(out) = interface_comms.to_pc.dequeue() {
-            if let Ok(ser_msg) = to_vec_cobs::<_, 128>(&out) {
-                usb_serial.write(&ser_msg).ok();
-            } else {
-                defmt::panic!("Serialization error!");
-            }
-        }
-
-        match usb_serial.read(&mut buf) {
-            Ok(sz) if sz > 0 => {
-                let buf = &buf[..sz];
-                let mut window = &buf[..];
-
-                'cobs: while !window.is_empty() {
-                    window = match cobs_buf.feed::<phm_icd::ToMcu>(&window) {
-                        FeedResult::Consumed => break 'cobs,
-                        FeedResult::OverFull(new_wind) => new_wind,
-                        FeedResult::DeserError(new_wind) => new_wind,
-                        FeedResult::Success { data, remaining } => {
-                            defmt::println!("got: {:?}", data);
-                            interface_comms.to_mcu.enqueue(data).ok();
-                            remaining
-                        }
-                    };
-                }
-            }
-            Ok(_) | Err(usb_device::UsbError::WouldBlock) => {}
-            Err(_e) => defmt::panic!("Usb Error!"),
-        }
+        cx.local.usb.poll();
         usb_tick::spawn_after(1.millis()).ok();
     }
 
+    /// Abort a command still running at its deadline, so the worker can report the timeout
+    #[task]
+    fn abort_command(_cx: abort_command::Context) {
+        defmt::warn!("Aborting I2C transfer");
+        PhmI2c::abort();
+    }
+
     #[idle(local = [worker])]
     fn idle(cx: idle::Context) -> ! {
         defmt::println!("Hello, world!");
@@ -0,0 +1,130 @@
+use embedded_hal::PwmPin;
+use phm_icd::PwmState;
+use phm_worker::{Error, Pwm as WorkerPwm};
+use rp_pico::hal::pwm::{FreeRunning, Pwm4, Pwm5, Slice};
+
+/// The default frequency of both channels, in Hz
+const DEFAULT_HZ: u32 = 1_000;
+
+/// The PWM outputs, on GPIO8 (channel 0, slice 4 A) and GPIO10 (channel 1, slice 5 A)
+///
+/// Each channel has its own slice, so the frequencies of the channels are independent.
+pub struct PhmPwm {
+    slice4: Slice<Pwm4, FreeRunning>,
+    slice5: Slice<Pwm5, FreeRunning>,
+    sys_hz: u32,
+    states: [PwmState; 2],
+    tops: [u16; 2],
+}
+
+/// Run `$body` with `$slice` bound to the slice of `$channel`
+macro_rules! with_slice {
+    ($self:ident, $channel:expr, |$slice:ident| $body:expr) => {
+        match $channel {
+            0 => {
+                let $slice = &mut $self.slice4;
+                $body
+            }
+            1 => {
+                let $slice = &mut $self.slice5;
+                $body
+            }
+            _ => return Err(Error::Pwm),
+        }
+    };
+}
+
+impl PhmPwm {
+    /// The slices must have their channel A routed to GPIO8 and GPIO10.
+    pub fn new(
+        slice4: Slice<Pwm4, FreeRunning>,
+        slice5: Slice<Pwm5, FreeRunning>,
+        sys_hz: u32,
+    ) -> Self {
+        let mut pwm = Self {
+            slice4,
+            slice5,
+            sys_hz,
+            states: [PwmState {
+                hz: 0,
+                duty: 0,
+                enabled: false,
+            }; 2],
+            tops: [0; 2],
+        };
+        for channel in 0..2 {
+            pwm.set_frequency(channel, DEFAULT_HZ).ok();
+            pwm.set_enabled(channel, false).ok();
+        }
+        pwm.slice4.enable();
+        pwm.slice5.enable();
+        pwm
+    }
+
+    /// The integer divider and the counter top giving the frequency closest to `hz`
+    fn divider(&self, hz: u32) -> (u8, u16) {
+        let total = self.sys_hz / hz.max(1);
+        let div = ((total + 0xFFFF) / 0x1_0000).clamp(1, 255);
+        let top = (total / div).clamp(2, 0x1_0000) - 1;
+        (div as u8, top as u16)
+    }
+
+    /// The compare value giving `duty` with the counter top of `channel`
+    fn compare(&self, channel: u8, duty: u16) -> u16 {
+        let steps = u32::from(self.tops[usize::from(channel)]) + 1;
+        (u32::from(duty) * steps / u32::from(u16::MAX)).min(0xFFFF) as u16
+    }
+}
+
+impl WorkerPwm for PhmPwm {
+    fn channels(&self) -> u8 {
+        2
+    }
+
+    fn set_frequency(&mut self, channel: u8, hz: u32) -> Result<(), Error> {
+        if hz == 0 || hz > self.sys_hz / 2 {
+            return Err(Error::Pwm);
+        }
+        let (div, top) = self.divider(hz);
+        with_slice!(self, channel, |slice| {
+            slice.set_div_int(div);
+            slice.set_div_frac(0);
+            slice.set_top(top);
+        });
+
+        let idx = usize::from(channel);
+        self.tops[idx] = top;
+        self.states[idx].hz = self.sys_hz / (u32::from(div) * (u32::from(top) + 1));
+        // Keep the duty cycle as a fraction of the new period
+        let cc = self.compare(channel, self.states[idx].duty);
+        with_slice!(self, channel, |slice| slice.channel_a.set_duty(cc));
+        Ok(())
+    }
+
+    fn set_duty(&mut self, channel: u8, duty: u16) -> Result<(), Error> {
+        if channel >= self.channels() {
+            return Err(Error::Pwm);
+        }
+        let cc = self.compare(channel, duty);
+        with_slice!(self, channel, |slice| slice.channel_a.set_duty(cc));
+        self.states[usize::from(channel)].duty = duty;
+        Ok(())
+    }
+
+    fn set_enabled(&mut self, channel: u8, enabled: bool) -> Result<(), Error> {
+        with_slice!(self, channel, |slice| if enabled {
+            slice.channel_a.enable()
+        } else {
+            slice.channel_a.disable()
+        });
+        self.states[usize::from(channel)].enabled = enabled;
+        Ok(())
+    }
+
+    fn state(&self, channel: u8) -> Result<PwmState, Error> {
+        self.states
+            .get(usize::from(channel))
+            .copied()
+            .ok_or(Error::Pwm)
+    }
+}
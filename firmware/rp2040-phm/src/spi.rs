@@ -0,0 +1,98 @@
+use core::convert::Infallible;
+use embedded_hal::blocking::spi::{Transfer, Write};
+use phm_worker::{Error, SpiWords};
+use rp_pico::{
+    hal::spi::{Enabled, Spi},
+    pac::{self, SPI0, SPI1},
+};
+
+/// One of the SPI buses: SPI0 on GPIO2 (SCK), GPIO3 (MOSI) and GPIO4 (MISO), or
+/// SPI1 on GPIO14 (SCK), GPIO15 (MOSI) and GPIO12 (MISO)
+///
+/// The HAL drives 8-bit frames, so words of 9 to 16 bits are sent through the
+/// registers directly, with the frame size switched for the duration.
+pub enum PhmSpi {
+    Spi0(Spi<Enabled, SPI0, 8>),
+    Spi1(Spi<Enabled, SPI1, 8>),
+}
+
+impl PhmSpi {
+    /// Run `f` with the peripheral in `bits`-bit frame mode, returning to 8-bit
+    /// frames afterwards.
+    fn with_frames<T>(&mut self, bits: u8, f: impl FnOnce(&pac::spi0::RegisterBlock) -> T) -> T {
+        // SAFETY: the peripheral belongs to the driver in `self`, which is not used
+        // until the frame size is restored
+        let regs = unsafe {
+            &*match self {
+                PhmSpi::Spi0(_) => SPI0::ptr(),
+                PhmSpi::Spi1(_) => SPI1::ptr(),
+            }
+        };
+
+        set_frame_size(regs, bits);
+        let result = f(regs);
+        set_frame_size(regs, 8);
+        result
+    }
+}
+
+fn set_frame_size(regs: &pac::spi0::RegisterBlock, bits: u8) {
+    // The frame format may only be changed while the peripheral is disabled
+    while regs.sspsr.read().bsy().bit_is_set() {}
+    regs.sspcr1.modify(|_, w| w.sse().clear_bit());
+    regs.sspcr0.modify(|_, w| unsafe { w.dss().bits(bits - 1) });
+    regs.sspcr1.modify(|_, w| w.sse().set_bit());
+}
+
+fn transfer_word(regs: &pac::spi0::RegisterBlock, word: u16) -> u16 {
+    while regs.sspsr.read().tnf().bit_is_clear() {}
+    regs.sspdr.write(|w| unsafe { w.data().bits(word) });
+    while regs.sspsr.read().rne().bit_is_clear() {}
+    regs.sspdr.read().data().bits()
+}
+
+impl Write<u8> for PhmSpi {
+    type Error = Infallible;
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            PhmSpi::Spi0(spi) => spi.write(words),
+            PhmSpi::Spi1(spi) => spi.write(words),
+        }
+    }
+}
+
+impl Transfer<u8> for PhmSpi {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        match self {
+            PhmSpi::Spi0(spi) => spi.transfer(words),
+            PhmSpi::Spi1(spi) => spi.transfer(words),
+        }
+    }
+}
+
+impl SpiWords for PhmSpi {
+    fn word_sizes(&self) -> &'static [u8] {
+        &[8, 9, 10, 11, 12, 13, 14, 15, 16]
+    }
+
+    fn write_u16(&mut self, bits: u8, words: &[u16]) -> Result<(), Error> {
+        self.with_frames(bits, |regs| {
+            for &word in words {
+                transfer_word(regs, word);
+            }
+        });
+        Ok(())
+    }
+
+    fn transfer_u16(&mut self, bits: u8, words: &mut [u16]) -> Result<(), Error> {
+        self.with_frames(bits, |regs| {
+            for word in words.iter_mut() {
+                *word = transfer_word(regs, *word);
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use embedded_hal::serial::{Read, Write};
+use rp_pico::{
+    hal::uart::{Enabled, UartPeripheral},
+    pac::{UART0, UART1},
+};
+
+/// One of the UARTs: UART0 on GPIO0 (TX) and GPIO1 (RX), or UART1 on GPIO20 (TX)
+/// and GPIO21 (RX)
+pub enum PhmUart {
+    Uart0(UartPeripheral<Enabled, UART0>),
+    Uart1(UartPeripheral<Enabled, UART1>),
+}
+
+impl Read<u8> for PhmUart {
+    type Error = <UartPeripheral<Enabled, UART0> as Read<u8>>::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self {
+            PhmUart::Uart0(uart) => uart.read(),
+            PhmUart::Uart1(uart) => uart.read(),
+        }
+    }
+}
+
+impl Write<u8> for PhmUart {
+    type Error = <UartPeripheral<Enabled, UART0> as Write<u8>>::Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        match self {
+            PhmUart::Uart0(uart) => uart.write(word),
+            PhmUart::Uart1(uart) => uart.write(word),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        match self {
+            PhmUart::Uart0(uart) => uart.flush(),
+            PhmUart::Uart1(uart) => uart.flush(),
+        }
+    }
+}
@@ -1,7 +1,22 @@
-use std::{num::ParseIntError, str::FromStr};
+use std::{
+    num::ParseIntError,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use clap::{Args, Parser, Subcommand};
-use phm::Machine;
+use embedded_hal_1::spi::Operation;
+use phm::{i2c::I2cBus, register::Field, smbus::Smbus as SmbusDevice, I2cBusStatus, Machine};
+
+use crate::{
+    device::{self, DEVICE_HELP},
+    error::{BusLevels, Error},
+    output::{Data, OutputFormat, Report},
+    parse::{parse_bytes, ParseBytesError},
+    regs::{self, Layout, Width, Word},
+    script::{self, Define, SCRIPT_HELP},
+};
 
 #[derive(Debug)]
 struct Address(u8);
@@ -9,91 +24,278 @@ struct Address(u8);
 #[derive(Debug)]
 struct WriteBytes(Vec<u8>);
 
+const WRITE_BYTES_HELP: &str = r#"BYTE SYNTAX:
+    A comma-separated list of items, where each item is one of:
+        A0, 0y1010, 0n160       a byte given in hex, binary or decimal
+        0x1234:u16be, 7:u32le   a 16 or 32-bit word with explicit endianness
+        "hi\r\n"                a string, supporting \n \r \t \0 \\ \" and \xNN escapes
+        0x00*16, "ab"*4         any of the above, repeated (decimal count)
+        @payload.bin            the contents of a file
+    Numbers without a prefix are hex. As 'b' and 'd' are hex digits, binary and decimal
+    use the '0y' and '0n' prefixes. At most 65536 bytes in total.
+    For example: "0xA0,0xAB,0x11" or '"AT\r\n",0x00*4'"#;
+
 #[derive(Parser, Debug)]
-pub enum PhmCli {
+#[clap(name = "phm-cli")]
+pub struct PhmCli {
+    /// Output format for read and transfer results. One of: debug (default), hex, json, binary, hexdump.
+    #[clap(short = 'f', long = "format", global = true)]
+    format: Option<OutputFormat>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// State that is carried between commands, e.g. in the console.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The I2C address used by the most recent I2C command.
+    pub address: Option<u8>,
+    /// The output format, as given by the most recent `--format` option.
+    pub format: OutputFormat,
+    /// How many scripts are running inside of each other.
+    pub script_depth: usize,
+    /// Commands are given in the interactive console.
+    pub console: bool,
+}
+
+impl Session {
+    /// Commands are run from the console or a script, where a command that runs
+    /// until the program is stopped can't be used.
+    fn is_nested(&self) -> bool {
+        self.console || self.script_depth > 0
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
     /// Commands for I2C communication.
     I2C(I2C),
+    /// Commands for SMBus communication, over the I2C bus.
+    Smbus(Smbus),
     /// Commands for SPI communication.
     Spi(Spi),
-    /// Commands for SPI communication.
+    /// Commands for UART communication.
     Uart(Uart),
+    /// Commands for the analog inputs.
+    Adc(Adc),
+    /// Commands for the PWM outputs.
+    Pwm(Pwm),
+    /// Measure the frequency, duty cycle and pulse width on a pulse input.
+    Measure(Measure),
+    /// Send a board-specific custom command, and print the payload of its response.
+    #[clap(after_help = WRITE_BYTES_HELP)]
+    Custom(Custom),
+    /// Print the counters of the worker, such as dropped messages and bus errors.
+    Stats(Stats),
+    /// Read and write registers by name, using a device file. See the device files below.
+    #[clap(after_help = DEVICE_HELP)]
+    Reg(Reg),
+    /// Interactive console, accepting any of the other commands.
+    Console,
+    /// Run a script of commands. See the script syntax below.
+    #[clap(after_help = SCRIPT_HELP)]
+    Run(Run),
+}
+
+#[derive(Parser, Debug)]
+pub struct Run {
+    /// The script to run.
+    script: PathBuf,
+    /// Define a variable before running the script, e.g. `-D ADDR=0x76`.
+    #[clap(short = 'D', long = "define")]
+    defines: Vec<Define>,
 }
 
 #[derive(Parser, Debug)]
 pub struct I2C {
+    /// The bus to use, numbered from 0.
+    #[clap(long = "bus", default_value = "0", global = true)]
+    bus: u8,
     #[clap(subcommand)]
     command: I2CCommand,
 }
 
+#[derive(Parser, Debug)]
+pub struct Smbus {
+    /// The bus to use, numbered from 0.
+    #[clap(long = "bus", default_value = "0", global = true)]
+    bus: u8,
+    #[clap(subcommand)]
+    command: SmbusCommand,
+}
+
 #[derive(Parser, Debug)]
 pub struct Spi {
+    /// The bus to use, numbered from 0.
+    #[clap(long = "bus", default_value = "0", global = true)]
+    bus: u8,
     #[clap(subcommand)]
     command: SpiCommand,
 }
 
 #[derive(Parser, Debug)]
 pub struct Uart {
+    /// The bus to use, numbered from 0.
+    #[clap(long = "bus", default_value = "0", global = true)]
+    bus: u8,
     #[clap(subcommand)]
     command: UartCommand,
 }
 
+#[derive(Parser, Debug)]
+pub struct Adc {
+    #[clap(subcommand)]
+    command: AdcCommand,
+}
+
+#[derive(Parser, Debug)]
+pub struct Pwm {
+    #[clap(subcommand)]
+    command: PwmCommand,
+}
+
+#[derive(Parser, Debug)]
+pub struct Reg {
+    /// Directory to search for device files, before the default directories.
+    #[clap(long = "devices", global = true)]
+    devices: Option<PathBuf>,
+    /// The I2C bus of the device, numbered from 0.
+    #[clap(long = "bus", default_value = "0", global = true)]
+    bus: u8,
+    #[clap(subcommand)]
+    command: RegCommand,
+}
+
 #[derive(Subcommand, Debug)]
 enum I2CCommand {
     /// Write bytes to the given address
-    #[clap(name = "write")]
+    #[clap(name = "write", after_help = WRITE_BYTES_HELP)]
     I2CWrite(I2CWrite),
     /// Read count bytes from the given address
     #[clap(name = "read")]
     I2CRead(I2CRead),
     /// Write-Read bytes to and from the given address
-    #[clap(name = "write-read")]
+    #[clap(name = "write-read", after_help = WRITE_BYTES_HELP)]
     WriteRead(WriteRead),
-    /// I2C Write console mode
-    #[clap(name = "console")]
-    I2CConsole(I2CConsole),
+    /// Read a register, like `i2cget`
+    #[clap(name = "get")]
+    Get(I2CGet),
+    /// Write a register, like `i2cset`
+    #[clap(name = "set")]
+    Set(I2CSet),
+    /// Read-modify-write the bits of a register selected by a mask
+    #[clap(name = "modify")]
+    Modify(I2CModify),
+    /// Read a range of 8-bit registers, printed as a table like `i2cdump`
+    #[clap(name = "dump")]
+    Dump(I2CDump),
+    /// Show the levels of the SDA and SCL lines
+    #[clap(name = "status")]
+    Status,
+    /// Free a bus held by a device, by clocking SCL until SDA is released, then sending a STOP
+    #[clap(name = "recover")]
+    Recover,
+}
+
+#[derive(Subcommand, Debug)]
+enum SmbusCommand {
+    /// Send only the address, with the R/W bit as the data
+    #[clap(name = "quick")]
+    Quick(SmbusQuick),
+    /// Send a byte, without a command code
+    #[clap(name = "send-byte")]
+    SendByte(SmbusSendByte),
+    /// Receive a byte, without a command code
+    #[clap(name = "receive-byte")]
+    ReceiveByte(SmbusTarget),
+    /// Write a byte to a command code
+    #[clap(name = "write-byte")]
+    WriteByte(SmbusWrite),
+    /// Read a byte from a command code
+    #[clap(name = "read-byte")]
+    ReadByte(SmbusRead),
+    /// Write a 16-bit word to a command code
+    #[clap(name = "write-word")]
+    WriteWord(SmbusWrite),
+    /// Read a 16-bit word from a command code
+    #[clap(name = "read-word")]
+    ReadWord(SmbusRead),
+    /// Write a 16-bit word to a command code, and read the word sent back
+    #[clap(name = "process-call")]
+    ProcessCall(SmbusWrite),
+    /// Write a block of up to 32 bytes to a command code
+    #[clap(name = "block-write", after_help = WRITE_BYTES_HELP)]
+    BlockWrite(SmbusBlockWrite),
+    /// Read a block from a command code, with the length given by the device
+    #[clap(name = "block-read")]
+    BlockRead(SmbusRead),
 }
 
 #[derive(Subcommand, Debug)]
 enum SpiCommand {
     /// Write bytes over SPI
-    #[clap(name = "write")]
-    SpiWrite(SpiWrite),
+    #[clap(name = "write", after_help = WRITE_BYTES_HELP)]
+    Write(SpiWrite),
     /// Transfer bytes over SPI
-    #[clap(name = "transfer")]
-    SpiTransfer(SpiTransfer),
-    /// SPI Transfer console mode
-    #[clap(name = "console")]
-    SpiConsole,
+    #[clap(name = "transfer", after_help = WRITE_BYTES_HELP)]
+    Transfer(SpiTransfer),
 }
 
 #[derive(Subcommand, Debug)]
 enum UartCommand {
     /// Write bytes over UART
-    #[clap(name = "write")]
-    UartWrite(UartWrite),
-    /// UART Write console mode
-    #[clap(name = "console")]
-    UartConsole,
-    /// UART Read console
+    #[clap(name = "write", after_help = WRITE_BYTES_HELP)]
+    Write(UartWrite),
+    /// Read incoming bytes over UART
     #[clap(name = "listen")]
-    UartListen,
+    Listen(UartListen),
+}
+
+#[derive(Subcommand, Debug)]
+enum AdcCommand {
+    /// List the analog input channels
+    #[clap(name = "list")]
+    List,
+    /// Sample an analog input channel
+    #[clap(name = "read")]
+    Read(AdcRead),
+}
+
+#[derive(Subcommand, Debug)]
+enum PwmCommand {
+    /// Configure a PWM output and start it, then print its state
+    #[clap(name = "set")]
+    Set(PwmSet),
+}
+
+#[derive(Subcommand, Debug)]
+enum RegCommand {
+    /// Read a register, and decode its fields
+    #[clap(name = "read", after_help = DEVICE_HELP)]
+    Read(RegRead),
+    /// Write a register, by field or as a whole. Fields that aren't given keep their value
+    #[clap(name = "write", after_help = DEVICE_HELP)]
+    Write(RegWrite),
+    /// List the registers and fields of a device
+    #[clap(name = "list", after_help = DEVICE_HELP)]
+    List(RegList),
 }
 
 #[derive(Args, Debug)]
 struct I2CWrite {
-    /// The address to write to.
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
     #[clap(short = 'a')]
-    address: Address,
-    /// Bytes to write to the address. Should be given as a comma-separated list of hex values. For example: "0xA0,0xAB,0x11".
+    address: Option<Address>,
+    /// Bytes to write to the address. See the byte syntax below.
     #[clap(short = 'b', long = "write")]
     write_bytes: WriteBytes,
 }
 
 #[derive(Args, Debug)]
 struct I2CRead {
-    /// The address to write to.
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
     #[clap(short = 'a')]
-    address: Address,
+    address: Option<Address>,
     /// Number of bytes to read.
     #[clap(long = "read-ct")]
     read_count: usize,
@@ -101,149 +303,911 @@ struct I2CRead {
 
 #[derive(Args, Debug)]
 struct WriteRead {
-    /// The address to write to. Should be given as a hex value. For example: "0xA4".
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
     #[clap(short = 'a')]
-    address: Address,
+    address: Option<Address>,
+    /// Bytes to write to the address. See the byte syntax below.
     #[clap(short = 'b', long = "bytes")]
     write_bytes: WriteBytes,
-    /// Bytes to write to the address. Should be given as a comma-separated list of hex values. For example: "0xA0,0xAB,0x11".
+    /// Number of bytes to read.
     #[clap(long = "read-ct")]
     read_count: usize,
 }
 
 #[derive(Args, Debug)]
-struct I2CConsole {
-    /// The address to write to. Should be given as a hex value. For example: "0xA4".
+struct RegisterLayout {
+    /// Width of the register address in bits, 8 or 16.
+    #[clap(long = "reg-width", default_value = "8")]
+    reg_width: Width,
+    /// Width of the register value in bits, 8 or 16.
+    #[clap(short = 'w', long = "width", default_value = "8")]
+    value_width: Width,
+    /// Send 16-bit register addresses and values least significant byte first.
+    #[clap(long = "little-endian")]
+    little_endian: bool,
+}
+
+#[derive(Args, Debug)]
+struct I2CGet {
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The register to read. For example: "0xD0".
+    #[clap(short = 'r', long = "reg")]
+    register: Word,
+    #[clap(flatten)]
+    layout: RegisterLayout,
+}
+
+#[derive(Args, Debug)]
+struct I2CSet {
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The register to write. For example: "0xF4".
+    #[clap(short = 'r', long = "reg")]
+    register: Word,
+    /// The value to write. For example: "0x27".
+    #[clap(short = 'v', long = "value")]
+    value: Word,
+    #[clap(flatten)]
+    layout: RegisterLayout,
+}
+
+#[derive(Args, Debug)]
+struct I2CModify {
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The register to modify. For example: "0xF4".
+    #[clap(short = 'r', long = "reg")]
+    register: Word,
+    /// The new value of the bits selected by the mask. For example: "0b01000000".
+    #[clap(short = 'v', long = "value")]
+    value: Word,
+    /// The bits to change, all other bits keep their current value. For example: "0b11100000".
+    #[clap(short = 'm', long = "mask")]
+    mask: Word,
+    #[clap(flatten)]
+    layout: RegisterLayout,
+}
+
+#[derive(Args, Debug)]
+struct I2CDump {
+    /// The address to write to. Should be given as a hex value. For example: "0xA4". In the console, defaults to the last address used.
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The first register to read.
+    #[clap(long = "start", default_value = "0x00")]
+    start: Word,
+    /// The last register to read.
+    #[clap(long = "end", default_value = "0xFF")]
+    end: Word,
+    /// Width of the register address in bits, 8 or 16.
+    #[clap(long = "reg-width", default_value = "8")]
+    reg_width: Width,
+    /// Send 16-bit register addresses least significant byte first.
+    #[clap(long = "little-endian")]
+    little_endian: bool,
+    /// Read one register per transaction, for devices that don't auto-increment the register address.
+    #[clap(long = "no-increment")]
+    no_increment: bool,
+}
+
+#[derive(Args, Debug)]
+struct RegRead {
+    /// The I2C address of the device, if different from the device file. Should be given as a hex value. For example: "0x77".
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The device name or file. For example: "bme280".
+    device: String,
+    /// The register name. For example: "ctrl_meas".
+    register: String,
+}
+
+#[derive(Args, Debug)]
+struct RegWrite {
+    /// The I2C address of the device, if different from the device file. Should be given as a hex value. For example: "0x77".
+    #[clap(short = 'a')]
+    address: Option<Address>,
+    /// The device name or file. For example: "bme280".
+    device: String,
+    /// The register name. For example: "ctrl_meas".
+    register: String,
+    /// Fields to set, for example: "osrs_t=2 mode=normal". A single number sets the whole register.
+    #[clap(required = true)]
+    values: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct RegList {
+    /// The device name or file. For example: "bme280".
+    device: String,
+}
+
+#[derive(Args, Debug)]
+struct SmbusTarget {
+    /// The address of the device. Should be given as a hex value. For example: "0x0B". In the console, defaults to the last address used.
     #[clap(short = 'a')]
-    address: Address,
+    address: Option<Address>,
+    /// Append a packet error code to the bytes written, and check the one after the bytes read.
+    #[clap(long = "pec")]
+    pec: bool,
+}
+
+#[derive(Args, Debug)]
+struct SmbusQuick {
+    #[clap(flatten)]
+    target: SmbusTarget,
+    /// Set the R/W bit for a read, instead of a write. Quick reads are not supported
+    /// by the workers, and quick writes only by the blackpill worker.
+    #[clap(long = "read")]
+    read: bool,
+}
+
+#[derive(Args, Debug)]
+struct SmbusSendByte {
+    #[clap(flatten)]
+    target: SmbusTarget,
+    /// The byte to send. For example: "0x27".
+    #[clap(short = 'd', long = "data")]
+    data: Word,
+}
+
+#[derive(Args, Debug)]
+struct SmbusRead {
+    #[clap(flatten)]
+    target: SmbusTarget,
+    /// The command code. For example: "0x09".
+    #[clap(short = 'c', long = "command")]
+    command: Word,
+}
+
+#[derive(Args, Debug)]
+struct SmbusWrite {
+    #[clap(flatten)]
+    target: SmbusTarget,
+    /// The command code. For example: "0x09".
+    #[clap(short = 'c', long = "command")]
+    command: Word,
+    /// The byte or word to write. For example: "0x1234".
+    #[clap(short = 'd', long = "data")]
+    data: Word,
+}
+
+#[derive(Args, Debug)]
+struct SmbusBlockWrite {
+    #[clap(flatten)]
+    target: SmbusTarget,
+    /// The command code. For example: "0x09".
+    #[clap(short = 'c', long = "command")]
+    command: Word,
+    /// Bytes to write, without the length. See the byte syntax below.
+    #[clap(short = 'b', long = "write")]
+    write_bytes: WriteBytes,
 }
 
 #[derive(Args, Debug)]
 struct SpiWrite {
-    /// Bytes to write over SPI. Should be given as a comma-separated list of hex values. For example: "0xA0,0xAB,0x11".
+    /// Bytes to write over SPI. See the byte syntax below.
     #[clap(short = 'b', long = "write")]
     write_bytes: WriteBytes,
+    /// Chip select line of the worker to assert during the write.
+    #[clap(short = 'c', long = "cs")]
+    cs: Option<u8>,
 }
 
 #[derive(Args, Debug)]
 struct SpiTransfer {
-    /// Bytes to transfer over SPI. Should be given as a comma-separated list of hex values. For example: "0xA0,0xAB,0x11".
+    /// Bytes to transfer over SPI. See the byte syntax below.
     #[clap(short = 'b', long = "write")]
     write_bytes: WriteBytes,
+    /// Chip select line of the worker to assert during the transfer.
+    #[clap(short = 'c', long = "cs")]
+    cs: Option<u8>,
 }
 
 #[derive(Args, Debug)]
 struct UartWrite {
-    /// Bytes to write over UART. Should be given as a comma-separated list of hex values. For example: "0xA0,0xAB,0x11".
+    /// Bytes to write over UART. See the byte syntax below.
     #[clap(short = 'b', long = "write")]
     write_bytes: WriteBytes,
 }
 
+#[derive(Args, Debug)]
+struct UartListen {
+    /// Stop listening after the given number of milliseconds, and print everything received.
+    /// Without this, bytes are printed as they are received, until the program is stopped,
+    /// which is why the console and scripts require it.
+    #[clap(short = 't', long = "duration")]
+    duration_ms: Option<u64>,
+}
+
+#[derive(Args, Debug)]
+struct AdcRead {
+    /// The channel to sample, see `adc list`.
+    #[clap(short = 'c', long = "channel")]
+    channel: u8,
+    /// The number of samples to take, at most 32.
+    #[clap(short = 'n', long = "count", default_value = "1")]
+    count: usize,
+    /// The time between samples, in microseconds.
+    #[clap(short = 'i', long = "interval", default_value = "0")]
+    interval_us: u64,
+}
+
+#[derive(Parser, Debug)]
+pub struct Measure {
+    /// The pulse input to measure.
+    #[clap(short = 'i', long = "input")]
+    input: u8,
+    /// The gate time in milliseconds, which must be shorter than the worker's one second deadline.
+    #[clap(short = 'g', long = "gate", default_value = "100")]
+    gate_ms: u64,
+}
+
+#[derive(Parser, Debug)]
+pub struct Custom {
+    /// The id of the command, as defined by the worker. For example: "0x01".
+    #[clap(short = 'i', long = "id")]
+    id: Word,
+    /// The payload of the command. See the byte syntax below.
+    #[clap(short = 'b', long = "payload")]
+    payload: Option<WriteBytes>,
+}
+
+#[derive(Parser, Debug)]
+pub struct Stats {
+    /// Reset the counters after reading them.
+    #[clap(long = "reset")]
+    reset: bool,
+}
+
+#[derive(Args, Debug)]
+struct PwmSet {
+    /// The channel to configure.
+    #[clap(short = 'c', long = "channel")]
+    channel: u8,
+    /// The frequency in Hz. Channels sharing a timer with this channel change their frequency too.
+    #[clap(short = 'F', long = "freq")]
+    hz: Option<u32>,
+    /// The duty cycle in percent, e.g. "12.5".
+    #[clap(short = 'd', long = "duty")]
+    duty: Option<Percent>,
+    /// Stop the output instead of starting it.
+    #[clap(long = "disable")]
+    disable: bool,
+}
+
+/// A percentage from 0 to 100.
+#[derive(Debug, Clone, Copy)]
+struct Percent(f64);
+
 impl PhmCli {
-    pub fn run(&self, machine: &mut Machine) -> Result<String, phm::Error> {
+    /// Is this the interactive console command?
+    pub fn is_console(&self) -> bool {
+        matches!(self.command, Command::Console)
+    }
+
+    /// Parse a line of text into a command, as if it was given on the command line.
+    pub fn from_line(line: &str) -> Result<Self, Error> {
+        let words =
+            shlex::split(line).ok_or_else(|| Error::InvalidLine("unbalanced quotes".into()))?;
+        Self::try_parse_from(std::iter::once("phm-cli".to_string()).chain(words))
+            .map_err(Error::Clap)
+    }
+
+    /// Update the session with the global options given, e.g. `--format`.
+    pub fn apply_options(&self, session: &mut Session) {
+        if let Some(format) = self.format {
+            session.format = format;
+        }
+    }
+
+    /// Run the command, updating the session with any options given.
+    pub fn run(&self, machine: &mut Machine, session: &mut Session) -> Report {
+        self.apply_options(session);
+
+        let address = match self.command.address() {
+            Some(Some(address)) => {
+                session.address = Some(address.0);
+                Some(address.0)
+            }
+            Some(None) => session.address,
+            None => None,
+        };
+
+        Report {
+            command: self.command.name(),
+            address,
+            result: self.command.execute(machine, session, address),
+        }
+    }
+}
+
+impl Command {
+    /// The name of the command, e.g. "i2c read".
+    fn name(&self) -> &'static str {
         match self {
-            PhmCli::I2C(cmd) => match &cmd.command {
-                I2CCommand::I2CWrite(args) => embedded_hal::blocking::i2c::Write::write(
-                    machine,
-                    args.address.0,
-                    &args.write_bytes.0,
-                )
-                .map(|_| "".into()),
-                I2CCommand::I2CRead(args) => {
-                    let mut buffer = vec![0u8; args.read_count];
-                    embedded_hal::blocking::i2c::Read::read(machine, args.address.0, &mut buffer)?;
-                    Ok(format!("{:02x?}", &buffer))
-                }
-                I2CCommand::WriteRead(args) => {
-                    let mut buffer = vec![0u8; args.read_count];
-                    embedded_hal::blocking::i2c::WriteRead::write_read(
-                        machine,
-                        args.address.0,
-                        &args.write_bytes.0,
-                        &mut buffer,
-                    )?;
-                    Ok(format!("{:02x?}", &buffer))
+            Command::I2C(cmd) => match &cmd.command {
+                I2CCommand::I2CWrite(_) => "i2c write",
+                I2CCommand::I2CRead(_) => "i2c read",
+                I2CCommand::WriteRead(_) => "i2c write-read",
+                I2CCommand::Get(_) => "i2c get",
+                I2CCommand::Set(_) => "i2c set",
+                I2CCommand::Modify(_) => "i2c modify",
+                I2CCommand::Dump(_) => "i2c dump",
+                I2CCommand::Status => "i2c status",
+                I2CCommand::Recover => "i2c recover",
+            },
+            Command::Smbus(cmd) => match &cmd.command {
+                SmbusCommand::Quick(_) => "smbus quick",
+                SmbusCommand::SendByte(_) => "smbus send-byte",
+                SmbusCommand::ReceiveByte(_) => "smbus receive-byte",
+                SmbusCommand::WriteByte(_) => "smbus write-byte",
+                SmbusCommand::ReadByte(_) => "smbus read-byte",
+                SmbusCommand::WriteWord(_) => "smbus write-word",
+                SmbusCommand::ReadWord(_) => "smbus read-word",
+                SmbusCommand::ProcessCall(_) => "smbus process-call",
+                SmbusCommand::BlockWrite(_) => "smbus block-write",
+                SmbusCommand::BlockRead(_) => "smbus block-read",
+            },
+            Command::Spi(cmd) => match &cmd.command {
+                SpiCommand::Write(_) => "spi write",
+                SpiCommand::Transfer(_) => "spi transfer",
+            },
+            Command::Uart(cmd) => match &cmd.command {
+                UartCommand::Write(_) => "uart write",
+                UartCommand::Listen(_) => "uart listen",
+            },
+            Command::Adc(cmd) => match &cmd.command {
+                AdcCommand::List => "adc list",
+                AdcCommand::Read(_) => "adc read",
+            },
+            Command::Pwm(cmd) => match &cmd.command {
+                PwmCommand::Set(_) => "pwm set",
+            },
+            Command::Measure(_) => "measure",
+            Command::Custom(_) => "custom",
+            Command::Stats(_) => "stats",
+            Command::Reg(cmd) => match &cmd.command {
+                RegCommand::Read(_) => "reg read",
+                RegCommand::Write(_) => "reg write",
+                RegCommand::List(_) => "reg list",
+            },
+            Command::Console => "console",
+            Command::Run(_) => "run",
+        }
+    }
+
+    /// The I2C address argument of the command, or `None` if this is not an I2C or SMBus command.
+    fn address(&self) -> Option<&Option<Address>> {
+        match self {
+            Command::I2C(cmd) => match &cmd.command {
+                I2CCommand::I2CWrite(args) => Some(&args.address),
+                I2CCommand::I2CRead(args) => Some(&args.address),
+                I2CCommand::WriteRead(args) => Some(&args.address),
+                I2CCommand::Get(args) => Some(&args.address),
+                I2CCommand::Set(args) => Some(&args.address),
+                I2CCommand::Modify(args) => Some(&args.address),
+                I2CCommand::Dump(args) => Some(&args.address),
+                I2CCommand::Status | I2CCommand::Recover => None,
+            },
+            Command::Smbus(cmd) => Some(&cmd.command.target().address),
+            _ => None,
+        }
+    }
+
+    fn execute(
+        &self,
+        machine: &mut Machine,
+        session: &mut Session,
+        address: Option<u8>,
+    ) -> Result<Option<Data>, Error> {
+        match self {
+            Command::I2C(I2C {
+                bus,
+                command: I2CCommand::Status,
+            }) => {
+                let status = machine.i2c(*bus).status()?;
+                bus_status(status)
+            }
+            Command::I2C(I2C {
+                bus,
+                command: I2CCommand::Recover,
+            }) => {
+                let status = machine.i2c(*bus).recover()?;
+                match status.sda && status.scl {
+                    true => bus_status(status),
+                    false => Err(Error::BusStuck(status)),
                 }
-                I2CCommand::I2CConsole(args) => {
-                    println!("I2C Write console (address: 0x{:02x})", args.address.0);
-                    println!("Provide a comma separated list of bytes (hex) then press enter to execute:");
-                    loop {
-                        let mut buffer = String::new();
-                        std::io::stdin().read_line(&mut buffer).unwrap();
-                        let mut bytes = WriteBytes::from_str(&buffer.trim()).unwrap().0;
+            }
+            Command::I2C(cmd) => {
+                let address = address.ok_or(Error::MissingAddress)?;
+                let mut bus = machine.i2c(cmd.bus);
+                match &cmd.command {
+                    I2CCommand::I2CWrite(args) => {
                         embedded_hal::blocking::i2c::Write::write(
-                            machine,
-                            args.address.0,
-                            &mut bytes,
+                            &mut bus,
+                            address,
+                            &args.write_bytes.0,
                         )?;
+                        Ok(None)
+                    }
+                    I2CCommand::I2CRead(args) => {
+                        let mut buffer = vec![0u8; args.read_count];
+                        embedded_hal::blocking::i2c::Read::read(&mut bus, address, &mut buffer)?;
+                        Ok(Some(buffer.into()))
+                    }
+                    I2CCommand::WriteRead(args) => {
+                        let mut buffer = vec![0u8; args.read_count];
+                        embedded_hal::blocking::i2c::WriteRead::write_read(
+                            &mut bus,
+                            address,
+                            &args.write_bytes.0,
+                            &mut buffer,
+                        )?;
+                        Ok(Some(buffer.into()))
+                    }
+                    I2CCommand::Get(args) => {
+                        let layout = args.layout.layout(args.register)?;
+                        let value = regs::get(&mut bus, address, &layout, args.register.0)?;
+                        Ok(Some(layout.display_bytes(value).into()))
+                    }
+                    I2CCommand::Set(args) => {
+                        let layout = args.layout.layout(args.register)?;
+                        check_value(&layout, "value", args.value)?;
+                        regs::set(&mut bus, address, &layout, args.register.0, args.value.0)?;
+                        Ok(None)
                     }
+                    I2CCommand::Modify(args) => {
+                        let layout = args.layout.layout(args.register)?;
+                        check_value(&layout, "value", args.value)?;
+                        check_value(&layout, "mask", args.mask)?;
+                        let old = regs::get(&mut bus, address, &layout, args.register.0)?;
+                        let new = (old & !args.mask.0) | (args.value.0 & args.mask.0);
+                        regs::set(&mut bus, address, &layout, args.register.0, new)?;
+                        Ok(Some(layout.display_bytes(new).into()))
+                    }
+                    I2CCommand::Dump(args) => i2c_dump(&mut bus, address, args),
+                    I2CCommand::Status | I2CCommand::Recover => unreachable!(),
                 }
-            },
-            PhmCli::Spi(cmd) => match &cmd.command {
-                SpiCommand::SpiWrite(args) => {
-                    embedded_hal::blocking::spi::Write::write(machine, &args.write_bytes.0)
-                        .map(|_| "".into())
+            }
+            Command::Smbus(cmd) => {
+                let address = address.ok_or(Error::MissingAddress)?;
+                let data = smbus(machine, cmd.bus, address, &cmd.command)?;
+                Ok(data.map(Data::from))
+            }
+            Command::Spi(cmd) => match &cmd.command {
+                SpiCommand::Write(args) => {
+                    let op = Operation::Write(&args.write_bytes.0);
+                    machine.spi(cmd.bus).transaction(args.cs, &mut [op])?;
+                    Ok(None)
                 }
-                SpiCommand::SpiTransfer(args) => {
+                SpiCommand::Transfer(args) => {
                     let mut buffer = args.write_bytes.0.clone();
-                    embedded_hal::blocking::spi::Transfer::transfer(machine, &mut buffer)
-                        .map(|bytes| format!("{:02x?}", &bytes))
-                }
-                SpiCommand::SpiConsole => {
-                    println!("SPI Transfer console\nProvide a comma separated list of bytes (hex) then press enter to execute:");
-                    loop {
-                        let mut buffer = String::new();
-                        std::io::stdin().read_line(&mut buffer).unwrap();
-                        let mut bytes = WriteBytes::from_str(&buffer.trim()).unwrap().0;
-                        match embedded_hal::blocking::spi::Transfer::transfer(machine, &mut bytes) {
-                            Ok(bytes) => println!("{:02x?}", &bytes),
-                            Err(err) => eprintln!("{:?}", err),
-                        }
-                    }
+                    let op = Operation::TransferInPlace(&mut buffer);
+                    machine.spi(cmd.bus).transaction(args.cs, &mut [op])?;
+                    Ok(Some(buffer.into()))
                 }
             },
-            PhmCli::Uart(cmd) => match &cmd.command {
-                UartCommand::UartWrite(args) => {
-                    embedded_hal::blocking::serial::Write::bwrite_all(machine, &args.write_bytes.0)
-                        .map(|_| "".into())
+            Command::Uart(cmd) => match &cmd.command {
+                UartCommand::Write(args) => {
+                    embedded_hal::blocking::serial::Write::bwrite_all(
+                        &mut machine.uart(cmd.bus),
+                        &args.write_bytes.0,
+                    )?;
+                    Ok(None)
                 }
-                UartCommand::UartConsole => {
-                    println!("UART TX console\nProvide a comma separated list of bytes (hex) then press enter to execute:");
-                    loop {
-                        let mut buffer = String::new();
-                        std::io::stdin().read_line(&mut buffer).unwrap();
-                        let bytes = WriteBytes::from_str(&buffer.trim()).unwrap().0;
-                        embedded_hal::blocking::serial::Write::bwrite_all(machine, &bytes)?;
-                    }
+                UartCommand::Listen(args) if args.duration_ms.is_none() && session.is_nested() => {
+                    Err(Error::Unsupported(
+                        "`uart listen` needs a duration (`-t`) in the console and in scripts",
+                    ))
                 }
-                UartCommand::UartListen => {
-                    use std::io::Write;
-                    println!("UART RX console");
-                    loop {
-                        while let Ok(b) = embedded_hal::serial::Read::<u8>::read(machine) {
-                            print!("{:02x} ", b);
-                        }
-                        std::io::stdout().flush().unwrap();
-                        std::thread::sleep(std::time::Duration::from_millis(10));
-                    }
+                UartCommand::Listen(args) => {
+                    uart_listen(machine, cmd.bus, session.format, args.duration_ms)
                 }
             },
+            Command::Adc(cmd) => adc(machine, &cmd.command),
+            Command::Pwm(cmd) => match &cmd.command {
+                PwmCommand::Set(args) => pwm_set(machine, args),
+            },
+            Command::Measure(args) => measure(machine, args),
+            Command::Custom(args) => {
+                let payload = args.payload.as_ref().map_or(&[][..], |bytes| &bytes.0);
+                Ok(Some(machine.custom(args.id.0, payload)?.into()))
+            }
+            Command::Stats(args) => stats(machine, args),
+            Command::Reg(cmd) => reg(machine, cmd),
+            Command::Console => Err(Error::Unsupported("already in the console")),
+            Command::Run(args) => script::run(machine, session, &args.script, &args.defines),
         }
     }
 }
 
-impl FromStr for WriteBytes {
-    type Err = ParseIntError;
+impl SmbusCommand {
+    fn target(&self) -> &SmbusTarget {
+        match self {
+            SmbusCommand::Quick(args) => &args.target,
+            SmbusCommand::SendByte(args) => &args.target,
+            SmbusCommand::ReceiveByte(target) => target,
+            SmbusCommand::WriteByte(args) => &args.target,
+            SmbusCommand::ReadByte(args) => &args.target,
+            SmbusCommand::WriteWord(args) => &args.target,
+            SmbusCommand::ReadWord(args) => &args.target,
+            SmbusCommand::ProcessCall(args) => &args.target,
+            SmbusCommand::BlockWrite(args) => &args.target,
+            SmbusCommand::BlockRead(args) => &args.target,
+        }
+    }
+}
+
+impl RegisterLayout {
+    fn layout(&self, register: Word) -> Result<Layout, Error> {
+        let layout = Layout {
+            reg_width: self.reg_width,
+            value_width: self.value_width,
+            little_endian: self.little_endian,
+        };
+        check_register(self.reg_width, register)?;
+        Ok(layout)
+    }
+}
+
+fn check_register(width: Width, register: Word) -> Result<(), Error> {
+    match (width, register.0) {
+        (Width::Bits8, 0x100..) => Err(Error::InvalidArgument(format!(
+            "register 0x{:x} does not fit in an 8-bit register address, use `--reg-width 16`",
+            register.0
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn check_value(layout: &Layout, name: &str, value: Word) -> Result<(), Error> {
+    match value.0 > layout.max_value() {
+        true => Err(Error::InvalidArgument(format!(
+            "{} 0x{:x} does not fit in an 8-bit register, use `--width 16`",
+            name, value.0
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Read a range of registers, shown as a table with the default output format.
+fn i2c_dump(bus: &mut I2cBus, address: u8, args: &I2CDump) -> Result<Option<Data>, Error> {
+    check_register(args.reg_width, args.end)?;
+    if args.end.0 < args.start.0 {
+        return Err(Error::InvalidArgument(
+            "the end register must not be before the start register".into(),
+        ));
+    }
+
+    let layout = Layout {
+        reg_width: args.reg_width,
+        value_width: Width::Bits8,
+        little_endian: args.little_endian,
+    };
+    let data = regs::read_range(
+        bus,
+        address,
+        &layout,
+        args.start.0,
+        args.end.0,
+        !args.no_increment,
+    )?;
+
+    let text = render(|out| regs::write_dump(out, args.start.0, &data))?;
+    Ok(Some(Data::with_text(data, text)))
+}
+
+/// Render text written by `write`, for the default output format.
+fn render(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> Result<String, Error> {
+    let mut out = Vec::new();
+    write(&mut out)?;
+    Ok(String::from_utf8_lossy(&out).into_owned())
+}
+
+/// The bus line levels as bytes (SDA, SCL), 1 for high.
+fn bus_status(status: I2cBusStatus) -> Result<Option<Data>, Error> {
+    let bytes = vec![status.sda.into(), status.scl.into()];
+    Ok(Some(Data::with_text(bytes, BusLevels(status).to_string())))
+}
+
+/// Words are returned most significant byte first, the way they would be written as a number.
+fn smbus(
+    machine: &mut Machine,
+    bus: u8,
+    address: u8,
+    cmd: &SmbusCommand,
+) -> Result<Option<Vec<u8>>, Error> {
+    let mut device = SmbusDevice::new(machine, address)
+        .bus(bus)
+        .pec(cmd.target().pec);
+
+    match cmd {
+        SmbusCommand::Quick(args) => {
+            device.quick(args.read)?;
+            Ok(None)
+        }
+        SmbusCommand::SendByte(args) => {
+            device.send_byte(byte("data", args.data)?)?;
+            Ok(None)
+        }
+        SmbusCommand::ReceiveByte(_) => Ok(Some(vec![device.receive_byte()?])),
+        SmbusCommand::WriteByte(args) => {
+            device.write_byte(byte("command", args.command)?, byte("data", args.data)?)?;
+            Ok(None)
+        }
+        SmbusCommand::ReadByte(args) => {
+            let data = device.read_byte(byte("command", args.command)?)?;
+            Ok(Some(vec![data]))
+        }
+        SmbusCommand::WriteWord(args) => {
+            device.write_word(byte("command", args.command)?, args.data.0)?;
+            Ok(None)
+        }
+        SmbusCommand::ReadWord(args) => {
+            let data = device.read_word(byte("command", args.command)?)?;
+            Ok(Some(data.to_be_bytes().to_vec()))
+        }
+        SmbusCommand::ProcessCall(args) => {
+            let data = device.process_call(byte("command", args.command)?, args.data.0)?;
+            Ok(Some(data.to_be_bytes().to_vec()))
+        }
+        SmbusCommand::BlockWrite(args) => {
+            if args.write_bytes.0.len() > phm::smbus::MAX_BLOCK {
+                return Err(Error::InvalidArgument(format!(
+                    "a block is at most {} bytes",
+                    phm::smbus::MAX_BLOCK
+                )));
+            }
+            device.block_write(byte("command", args.command)?, &args.write_bytes.0)?;
+            Ok(None)
+        }
+        SmbusCommand::BlockRead(args) => {
+            let data = device.block_read(byte("command", args.command)?)?;
+            Ok(Some(data))
+        }
+    }
+}
+
+fn byte(name: &str, value: Word) -> Result<u8, Error> {
+    u8::try_from(value.0).map_err(|_| {
+        Error::InvalidArgument(format!("{} 0x{:x} does not fit in a byte", name, value.0))
+    })
+}
+
+fn reg(machine: &mut Machine, cmd: &Reg) -> Result<Option<Data>, Error> {
+    let (name, address) = match &cmd.command {
+        RegCommand::Read(args) => (&args.device, &args.address),
+        RegCommand::Write(args) => (&args.device, &args.address),
+        RegCommand::List(args) => (&args.device, &None),
+    };
+    let device = device::load(name, cmd.devices.as_deref())?;
+    let address = address.as_ref().map(|a| a.0).or(device.address);
+    let address = || address.ok_or(Error::MissingAddress);
+    let mut bus = machine.i2c(cmd.bus);
+
+    let (reg_name, register, value) = match &cmd.command {
+        RegCommand::Read(args) => {
+            let register = device.register(&args.register)?;
+            let value = device.read(&mut bus, address()?, register)?;
+            (&args.register, register, value)
+        }
+        RegCommand::Write(args) => {
+            let register = device.register(&args.register)?;
+            let (mask, bits) = register.encode(&args.values)?;
+            let old = match mask == Field::new(0, register.width).mask() {
+                true => 0,
+                false => device.read(&mut bus, address()?, register)?,
+            };
+            let value = (old & !mask) | bits;
+            device.write(&mut bus, address()?, register, value)?;
+            (&args.register, register, value)
+        }
+        RegCommand::List(_) => {
+            device.write_list(&mut std::io::stdout().lock())?;
+            return Ok(None);
+        }
+    };
+
+    let text = render(|out| register.write_decoded(out, reg_name, value))?;
+    Ok(Some(Data::with_text(register.to_bytes(value), text)))
+}
+
+/// Channels are returned as their resolution followed by their full-scale voltage
+/// in millivolts, and samples as their raw values, both most significant byte first.
+fn adc(machine: &mut Machine, cmd: &AdcCommand) -> Result<Option<Data>, Error> {
+    match cmd {
+        AdcCommand::List => {
+            let channels = machine.adc_channels()?;
+            let bytes = channels
+                .iter()
+                .flat_map(|c| {
+                    let [hi, lo] = c.full_scale_mv.to_be_bytes();
+                    [c.resolution_bits, hi, lo]
+                })
+                .collect();
+            let lines: Vec<String> = channels
+                .iter()
+                .enumerate()
+                .map(|(i, channel)| {
+                    format!(
+                        "{}: {}-bit, {} mV full scale",
+                        i, channel.resolution_bits, channel.full_scale_mv
+                    )
+                })
+                .collect();
+            Ok(Some(Data::with_text(bytes, lines.join("\n"))))
+        }
+        AdcCommand::Read(args) => {
+            let interval = Duration::from_micros(args.interval_us);
+            let samples = machine.adc_sample(args.channel, args.count, interval)?;
+            let bytes = samples.iter().flat_map(|s| s.raw.to_be_bytes()).collect();
+            let lines: Vec<String> = samples
+                .iter()
+                .map(|sample| format!("{} ({} mV)", sample.raw, sample.millivolts))
+                .collect();
+            Ok(Some(Data::with_text(bytes, lines.join("\n"))))
+        }
+    }
+}
+
+/// The state of the channel is returned as the frequency (4 bytes) and the duty cycle
+/// as a fraction of 0xFFFF (2 bytes), most significant byte first, followed by 1 if the
+/// output is enabled.
+fn pwm_set(machine: &mut Machine, args: &PwmSet) -> Result<Option<Data>, Error> {
+    if let Some(hz) = args.hz {
+        machine.pwm_set_frequency(args.channel, hz)?;
+    }
+    if let Some(Percent(duty)) = args.duty {
+        let duty = (duty / 100.0 * f64::from(u16::MAX)).round() as u16;
+        machine.pwm_set_duty(args.channel, duty)?;
+    }
+    let state = machine.pwm_set_enabled(args.channel, !args.disable)?;
+
+    let mut bytes = state.hz.to_be_bytes().to_vec();
+    bytes.extend(state.duty.to_be_bytes());
+    bytes.push(state.enabled.into());
+    let text = format!(
+        "channel {}: {} Hz, {:.1}% duty, {}",
+        args.channel,
+        state.hz,
+        f64::from(state.duty) * 100.0 / f64::from(u16::MAX),
+        if state.enabled { "enabled" } else { "disabled" }
+    );
+    Ok(Some(Data::with_text(bytes, text)))
+}
+
+/// The measurement is returned as the rising and falling edge counts, then the count, total, minimum and maximum of
+/// the periods and of the high pulses in microseconds, all 4 bytes and most significant
+/// byte first, followed by 1 if the input ended high.
+fn measure(machine: &mut Machine, args: &Measure) -> Result<Option<Data>, Error> {
+    let m = machine.measure_pulse(args.input, Duration::from_millis(args.gate_ms))?;
+    let stats = &m.stats;
+
+    let words = [
+        stats.rising_edges,
+        stats.falling_edges,
+        stats.period.count,
+        stats.period.total_us,
+        stats.period.min_us,
+        stats.period.max_us,
+        stats.high.count,
+        stats.high.total_us,
+        stats.high.min_us,
+        stats.high.max_us,
+    ];
+    let mut bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+    bytes.push(stats.level.into());
+
+    let text = match (m.frequency_hz(), m.duty_cycle(), m.pulse_width()) {
+        (Some(hz), Some(duty), Some(width)) => format!(
+            "frequency: {:.1} Hz ({} periods of {} to {} us)\n\
+             duty cycle: {:.1}%\n\
+             pulse width: {:.1} us ({} pulses of {} to {} us)",
+            hz,
+            stats.period.count,
+            stats.period.min_us,
+            stats.period.max_us,
+            duty * 100.0,
+            width.as_secs_f64() * 1_000_000.0,
+            stats.high.count,
+            stats.high.min_us,
+            stats.high.max_us
+        ),
+        _ => format!(
+            "no complete periods: {} rising and {} falling edges, input ended {}",
+            stats.rising_edges,
+            stats.falling_edges,
+            if stats.level { "high" } else { "low" }
+        ),
+    };
+    Ok(Some(Data::with_text(bytes, text)))
+}
+
+/// The counters are returned as 4 bytes each, most significant byte first.
+fn stats(machine: &mut Machine, args: &Stats) -> Result<Option<Data>, Error> {
+    let stats = match args.reset {
+        true => machine.reset_stats()?,
+        false => machine.stats()?,
+    };
+    let counters = [
+        ("commands", stats.commands),
+        ("dropped commands", stats.dropped_commands),
+        ("dropped responses", stats.dropped_responses),
+        ("decode errors", stats.decode_errors),
+        ("uart overruns", stats.uart_overruns),
+        ("i2c errors", stats.i2c_errors),
+        ("spi errors", stats.spi_errors),
+        ("uart errors", stats.uart_errors),
+        ("timeouts", stats.timeouts),
+    ];
+
+    let bytes = counters
+        .iter()
+        .flat_map(|(_, count)| count.to_be_bytes())
+        .collect();
+    let lines: Vec<String> = counters
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect();
+    Ok(Some(Data::with_text(bytes, lines.join("\n"))))
+}
+
+fn uart_listen(
+    machine: &mut Machine,
+    bus: u8,
+    format: OutputFormat,
+    duration_ms: Option<u64>,
+) -> Result<Option<Data>, Error> {
+    let start = Instant::now();
+    let mut received = Vec::new();
+
+    loop {
+        let mut chunk = Vec::new();
+        while let Ok(b) = embedded_hal::serial::Read::<u8>::read(&mut machine.uart(bus)) {
+            chunk.push(b);
+        }
+
+        match duration_ms {
+            Some(ms) => {
+                received.extend(chunk);
+                if start.elapsed() >= Duration::from_millis(ms) {
+                    return Ok(Some(received.into()));
+                }
+            }
+            None if !chunk.is_empty() => {
+                format.print(&Report {
+                    command: "uart listen",
+                    address: None,
+                    result: Ok(Some(chunk.into())),
+                })?;
+            }
+            None => {}
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+impl FromStr for Percent {
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut bytes: Vec<u8> = Vec::new();
-        for b in s.split(',') {
-            let without_prefix = b.trim().trim_start_matches("0x");
-            let byte = u8::from_str_radix(without_prefix, 16)?;
-            bytes.push(byte);
+        match s.trim_end_matches('%').parse::<f64>() {
+            Ok(pct) if (0.0..=100.0).contains(&pct) => Ok(Self(pct)),
+            _ => Err(format!("'{}' is not a percentage from 0 to 100", s)),
         }
+    }
+}
 
-        Ok(Self(bytes))
+impl FromStr for WriteBytes {
+    type Err = ParseBytesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_bytes(s).map(Self)
     }
 }
 
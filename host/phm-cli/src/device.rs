@@ -0,0 +1,524 @@
+//! Device description files, used by the `reg` commands
+//!
+//! A device description lists the registers of a device, and the bitfields
+//! within them, so register values can be encoded and decoded by name.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use embedded_hal::blocking::i2c;
+use phm::register::{AddressWidth, Endian, Field, I2cRegisters};
+use serde::Deserialize;
+
+use crate::{error::Error, parse::parse_int};
+
+/// File extensions searched for, in order.
+const EXTENSIONS: &[&str] = &["toml", "yaml", "yml"];
+
+pub const DEVICE_HELP: &str = r#"DEVICE FILES:
+    A device is either the path to a TOML or YAML file, or the name of a file
+    (without extension) in the `--devices` directory, `$PHM_DEVICES`, `./devices`
+    or `~/.config/phm/devices`. For example, `devices/bme280.toml`:
+
+        address = 0x76
+
+        [registers.ctrl_meas]
+        address = 0xF4
+        fields.osrs_t = { bits = "7:5" }
+        fields.osrs_p = { bits = "4:2" }
+        fields.mode = { bits = "1:0", values = { sleep = 0, forced = 1, normal = 3 } }
+
+    Registers are 8 bits wide unless they have `width = 16` or `width = 32`.
+    Devices with 16-bit register addresses need `register_width = 16`, and
+    `little_endian = true` sends addresses and values least significant byte first."#;
+
+/// A device, as described by a device file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Device {
+    /// The I2C address of the device.
+    pub address: Option<u8>,
+    #[serde(default = "default_width")]
+    pub register_width: u8,
+    #[serde(default)]
+    pub little_endian: bool,
+    pub registers: BTreeMap<String, Register>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Register {
+    pub address: u16,
+    /// The width of the register value in bits, 8, 16 or 32.
+    #[serde(default = "default_width")]
+    pub width: u8,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldInfo {
+    /// The bits of the field, e.g. `"7:5"`, or `"3"` for a single bit.
+    pub bits: Bits,
+    pub description: Option<String>,
+    /// Names for values of the field.
+    #[serde(default)]
+    pub values: BTreeMap<String, u32>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Bits(pub Field);
+
+fn default_width() -> u8 {
+    8
+}
+
+impl TryFrom<String> for Bits {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let bit = |s: &str| {
+            s.trim()
+                .parse::<u8>()
+                .map_err(|_| format!("invalid bits '{}', expected e.g. \"7:5\" or \"3\"", s))
+        };
+        let (msb, lsb) = match s.split_once(':') {
+            Some((msb, lsb)) => (bit(msb)?, bit(lsb)?),
+            None => (bit(&s)?, bit(&s)?),
+        };
+        if lsb > msb || msb > 31 {
+            return Err(format!("invalid bits '{}', expected MSB:LSB below 32", s));
+        }
+        Ok(Bits(Field::new(lsb, msb - lsb + 1)))
+    }
+}
+
+impl Display for Bits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Field { offset, width } = self.0;
+        match width {
+            1 => write!(f, "[{}]", offset),
+            _ => write!(f, "[{}:{}]", offset + width - 1, offset),
+        }
+    }
+}
+
+/// Find and load a device file, either by path or by name.
+pub fn load(device: &str, dir: Option<&Path>) -> Result<Device, Error> {
+    let path = find(device, dir).ok_or_else(|| {
+        Error::Device(format!(
+            "no device file found for '{}', see `phm-cli reg --help`",
+            device
+        ))
+    })?;
+    let source = std::fs::read_to_string(&path)?;
+
+    let parsed = match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str::<Device>(&source).map_err(|e| e.to_string()),
+        _ => toml::from_str::<Device>(&source).map_err(|e| e.to_string()),
+    };
+    let device = parsed.map_err(|e| Error::Device(format!("{}: {}", path.display(), e)))?;
+    device
+        .validate()
+        .map_err(|e| Error::Device(format!("{}: {}", path.display(), e)))?;
+
+    Ok(device)
+}
+
+fn find(device: &str, dir: Option<&Path>) -> Option<PathBuf> {
+    let path = Path::new(device);
+    if path.is_file() {
+        return Some(path.into());
+    }
+
+    let mut dirs: Vec<PathBuf> = dir.map(Into::into).into_iter().collect();
+    dirs.extend(std::env::var_os("PHM_DEVICES").map(PathBuf::from));
+    dirs.push("devices".into());
+    dirs.extend(
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/phm/devices")),
+    );
+
+    dirs.iter()
+        .flat_map(|dir| {
+            EXTENSIONS
+                .iter()
+                .map(move |ext| dir.join(device).with_extension(ext))
+        })
+        .find(|path| path.is_file())
+}
+
+impl Device {
+    fn validate(&self) -> Result<(), String> {
+        if ![8, 16].contains(&self.register_width) {
+            return Err("register_width must be 8 or 16".into());
+        }
+        for (name, reg) in &self.registers {
+            if ![8, 16, 32].contains(&reg.width) {
+                return Err(format!("register '{}': width must be 8, 16 or 32", name));
+            }
+            if self.register_width == 8 && reg.address > 0xFF {
+                return Err(format!(
+                    "register '{}': address does not fit in an 8-bit register address",
+                    name
+                ));
+            }
+            for (field_name, field) in &reg.fields {
+                let Field { offset, width } = field.bits.0;
+                if offset + width > reg.width {
+                    return Err(format!(
+                        "field '{}.{}': bits {} are outside of the register",
+                        name, field_name, field.bits
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn register(&self, name: &str) -> Result<&Register, Error> {
+        self.registers.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.registers.keys().map(String::as_str).collect();
+            Error::InvalidArgument(format!(
+                "unknown register '{}', expected one of: {}",
+                name,
+                names.join(", ")
+            ))
+        })
+    }
+
+    fn registers_at(&self, address: u8) -> I2cRegisters {
+        let address_width = match self.register_width {
+            16 => AddressWidth::Bits16,
+            _ => AddressWidth::Bits8,
+        };
+        let endian = match self.little_endian {
+            true => Endian::Little,
+            false => Endian::Big,
+        };
+        I2cRegisters::new(address)
+            .address_width(address_width)
+            .endian(endian)
+    }
+
+    pub fn read<BUS>(&self, bus: &mut BUS, address: u8, reg: &Register) -> Result<u32, Error>
+    where
+        BUS: i2c::WriteRead<Error = phm::Error>,
+    {
+        let regs = self.registers_at(address);
+        let value = match reg.width {
+            8 => regs.read::<u8, _>(bus, reg.address)?.into(),
+            16 => regs.read::<u16, _>(bus, reg.address)?.into(),
+            _ => regs.read::<u32, _>(bus, reg.address)?,
+        };
+        Ok(value)
+    }
+
+    pub fn write<BUS>(
+        &self,
+        bus: &mut BUS,
+        address: u8,
+        reg: &Register,
+        value: u32,
+    ) -> Result<(), Error>
+    where
+        BUS: i2c::Write<Error = phm::Error>,
+    {
+        let regs = self.registers_at(address);
+        match reg.width {
+            8 => regs.write(bus, reg.address, value as u8)?,
+            16 => regs.write(bus, reg.address, value as u16)?,
+            _ => regs.write(bus, reg.address, value)?,
+        }
+        Ok(())
+    }
+
+    /// Print the registers of the device, and their fields.
+    pub fn write_list(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut registers: Vec<_> = self.registers.iter().collect();
+        registers.sort_by_key(|(_, reg)| reg.address);
+
+        for (name, reg) in registers {
+            write!(out, "0x{:02x} {} ({}-bit)", reg.address, name, reg.width)?;
+            match &reg.description {
+                Some(description) => writeln!(out, "  {}", description)?,
+                None => writeln!(out)?,
+            }
+            for (name, field) in reg.sorted_fields() {
+                write!(out, "    {:7} {}", field.bits.to_string(), name)?;
+                if !field.values.is_empty() {
+                    let mut values: Vec<_> = field.values.iter().collect();
+                    values.sort_by_key(|(_, value)| **value);
+                    let values: Vec<String> = values
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect();
+                    write!(out, " ({})", values.join(", "))?;
+                }
+                if let Some(description) = &field.description {
+                    write!(out, "  {}", description)?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Register {
+    /// The fields of the register, most significant first, as in most datasheets.
+    fn sorted_fields(&self) -> Vec<(&String, &FieldInfo)> {
+        let mut fields: Vec<_> = self.fields.iter().collect();
+        fields.sort_by_key(|(_, field)| std::cmp::Reverse(field.bits.0.offset));
+        fields
+    }
+
+    /// The register value as big-endian bytes, the way it would be written as a number.
+    pub fn to_bytes(&self, value: u32) -> Vec<u8> {
+        value.to_be_bytes()[4 - usize::from(self.width / 8)..].to_vec()
+    }
+
+    /// Encode `field=value` assignments into a mask of the bits that are set,
+    /// and the new value of those bits.
+    ///
+    /// A single assignment without a field name sets the whole register.
+    pub fn encode(&self, assignments: &[String]) -> Result<(u32, u32), Error> {
+        let full_mask = (Field::new(0, self.width)).mask();
+        let mut mask = 0;
+        let mut value = 0;
+
+        for assignment in assignments {
+            let (field_mask, field_value) = match assignment.split_once('=') {
+                Some((name, val)) => self.encode_field(name.trim(), val.trim())?,
+                None => {
+                    let val =
+                        parse_int(assignment).map_err(|e| Error::InvalidArgument(e.to_string()))?;
+                    if val > full_mask.into() {
+                        return Err(Error::InvalidArgument(format!(
+                            "value '{}' does not fit in the {}-bit register",
+                            assignment, self.width
+                        )));
+                    }
+                    (full_mask, val as u32)
+                }
+            };
+            if mask & field_mask != 0 {
+                return Err(Error::InvalidArgument(format!(
+                    "'{}' overlaps with an earlier assignment",
+                    assignment
+                )));
+            }
+            mask |= field_mask;
+            value |= field_value;
+        }
+
+        Ok((mask, value))
+    }
+
+    fn encode_field(&self, name: &str, value: &str) -> Result<(u32, u32), Error> {
+        let field = self.fields.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.fields.keys().map(String::as_str).collect();
+            Error::InvalidArgument(format!(
+                "unknown field '{}', expected one of: {}",
+                name,
+                names.join(", ")
+            ))
+        })?;
+
+        let raw = match field.values.get(value) {
+            Some(raw) => u64::from(*raw),
+            None => parse_int(value).map_err(|_| {
+                let names: Vec<&str> = field.values.keys().map(String::as_str).collect();
+                Error::InvalidArgument(match names.is_empty() {
+                    true => format!("invalid value '{}' for field '{}'", value, name),
+                    false => format!(
+                        "invalid value '{}' for field '{}', expected a number or one of: {}",
+                        value,
+                        name,
+                        names.join(", ")
+                    ),
+                })
+            })?,
+        };
+
+        let bits = field.bits.0;
+        if raw >> bits.width != 0 {
+            return Err(Error::InvalidArgument(format!(
+                "value '{}' does not fit in field '{}' {}",
+                value, name, field.bits
+            )));
+        }
+
+        Ok((bits.mask(), bits.insert(0, raw as u32)))
+    }
+
+    /// Print the value of the register, decoded into its fields.
+    pub fn write_decoded(&self, out: &mut impl Write, name: &str, value: u32) -> io::Result<()> {
+        let digits = usize::from(self.width / 4);
+        write!(
+            out,
+            "{} (0x{:02x}) = 0x{:0digits$x}",
+            name,
+            self.address,
+            value,
+            digits = digits
+        )?;
+        match &self.description {
+            Some(description) => writeln!(out, "  {}", description)?,
+            None => writeln!(out)?,
+        }
+
+        let fields = self.sorted_fields();
+        let name_width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+
+        for (name, field) in fields {
+            let raw = field.bits.0.extract(value);
+            let bits = field.bits.to_string();
+            write!(
+                out,
+                "  {:name_width$} {:5} = {}",
+                name,
+                bits,
+                raw,
+                name_width = name_width
+            )?;
+            if let Some((value_name, _)) = field.values.iter().find(|(_, v)| **v == raw) {
+                write!(out, " ({})", value_name)?;
+            }
+            if let Some(description) = &field.description {
+                write!(out, "  {}", description)?;
+            }
+            writeln!(out)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An I2C bus that records what is written, and answers reads with `reply`.
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<(u8, Vec<u8>)>,
+        reply: Vec<u8>,
+    }
+
+    impl i2c::Write for Recorder {
+        type Error = phm::Error;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), phm::Error> {
+            self.writes.push((address, bytes.to_vec()));
+            Ok(())
+        }
+    }
+
+    impl i2c::WriteRead for Recorder {
+        type Error = phm::Error;
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), phm::Error> {
+            self.writes.push((address, bytes.to_vec()));
+            buffer.copy_from_slice(&self.reply);
+            Ok(())
+        }
+    }
+
+    fn bme280() -> Device {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("devices/bme280.toml");
+        load(path.to_str().unwrap(), None).unwrap()
+    }
+
+    fn assignments(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn bits() {
+        let bits = |s: &str| Bits::try_from(s.to_string()).map(|b| b.0);
+        assert_eq!(bits("7:5"), Ok(Field::new(5, 3)));
+        assert_eq!(bits("3"), Ok(Field::new(3, 1)));
+        assert_eq!(bits("31:0"), Ok(Field::new(0, 32)));
+        assert!(bits("5:7").is_err());
+        assert!(bits("32").is_err());
+        assert!(bits("x").is_err());
+        assert_eq!(Bits(Field::new(5, 3)).to_string(), "[7:5]");
+        assert_eq!(Bits(Field::new(3, 1)).to_string(), "[3]");
+    }
+
+    #[test]
+    fn encode_fields() {
+        let device = bme280();
+        let ctrl_meas = device.register("ctrl_meas").unwrap();
+        assert_eq!(
+            ctrl_meas
+                .encode(&assignments(&["osrs_t=2", "mode=forced"]))
+                .unwrap(),
+            (0b1110_0011, 0b0100_0001)
+        );
+        assert_eq!(
+            ctrl_meas.encode(&assignments(&["0x27"])).unwrap(),
+            (0xFF, 0x27)
+        );
+        // Too wide, unknown, and overlapping assignments
+        assert!(ctrl_meas.encode(&assignments(&["osrs_t=8"])).is_err());
+        assert!(ctrl_meas.encode(&assignments(&["mode=sideways"])).is_err());
+        assert!(ctrl_meas.encode(&assignments(&["osrs_h=1"])).is_err());
+        assert!(ctrl_meas.encode(&assignments(&["0x27", "mode=1"])).is_err());
+        assert!(ctrl_meas.encode(&assignments(&["0x100"])).is_err());
+    }
+
+    #[test]
+    fn read_and_write_a_field() {
+        let device = bme280();
+        let ctrl_meas = device.register("ctrl_meas").unwrap();
+        let mut bus = Recorder {
+            reply: vec![0x27],
+            ..Default::default()
+        };
+
+        // Set osrs_t, keeping the other fields, as `reg write bme280 ctrl_meas osrs_t=2` does
+        let (mask, bits) = ctrl_meas.encode(&assignments(&["osrs_t=2"])).unwrap();
+        let old = device.read(&mut bus, 0x76, ctrl_meas).unwrap();
+        assert_eq!(old, 0x27);
+        device
+            .write(&mut bus, 0x76, ctrl_meas, (old & !mask) | bits)
+            .unwrap();
+        assert_eq!(bus.writes, [(0x76, vec![0xF4]), (0x76, vec![0xF4, 0x47])]);
+
+        let mut out = Vec::new();
+        ctrl_meas
+            .write_decoded(&mut out, "ctrl_meas", 0x47)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("ctrl_meas (0xf4) = 0x47\n"));
+        assert!(out.contains("osrs_t [7:5] = 2"));
+        assert!(out.contains("mode   [1:0] = 3 (normal)"));
+    }
+
+    #[test]
+    fn read_a_16_bit_register() {
+        let device = bme280();
+        let press = device.register("press").unwrap();
+        let mut bus = Recorder {
+            reply: vec![0x12, 0x34],
+            ..Default::default()
+        };
+        assert_eq!(device.read(&mut bus, 0x76, press).unwrap(), 0x1234);
+        assert_eq!(bus.writes, [(0x76, vec![0xF7])]);
+        assert_eq!(press.to_bytes(0x1234), [0x12, 0x34]);
+    }
+}
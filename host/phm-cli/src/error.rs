@@ -0,0 +1,90 @@
+use std::{fmt::Display, io};
+
+use phm::I2cBusStatus;
+
+/// The CLI Error type
+#[derive(Debug)]
+pub enum Error {
+    Machine(phm::Error),
+    Io(io::Error),
+    /// An I2C command was given without an address, and no previous address is known.
+    MissingAddress,
+    /// The command can't be used from where it was given (e.g. `console` inside the console).
+    Unsupported(&'static str),
+    /// A command line could not be parsed.
+    Clap(clap::Error),
+    /// A command argument is out of range for the other arguments given.
+    InvalidArgument(String),
+    /// The I2C bus was still held after recovering it.
+    BusStuck(I2cBusStatus),
+    /// A device file could not be found or loaded.
+    Device(String),
+    /// A console or script line could not be parsed.
+    InvalidLine(String),
+    /// Data read by a script did not match an `expect` line.
+    ExpectFailed {
+        expected: Vec<u8>,
+        mask: Option<Vec<u8>>,
+        actual: Option<Vec<u8>>,
+    },
+    /// A script step failed.
+    Script {
+        line: usize,
+        error: Box<Error>,
+    },
+}
+
+impl From<phm::Error> for Error {
+    fn from(err: phm::Error) -> Self {
+        Error::Machine(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Machine(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "IoError: {}", e),
+            Error::MissingAddress => write!(f, "No I2C address given, use `-a <ADDRESS>`"),
+            Error::Unsupported(why) => write!(f, "Unsupported: {}", why),
+            Error::Clap(e) => write!(f, "{}", e.to_string().trim_end()),
+            Error::InvalidArgument(why) => write!(f, "InvalidArgument: {}", why),
+            Error::BusStuck(status) => write!(f, "BusStuck: {}", BusLevels(*status)),
+            Error::Device(why) => write!(f, "DeviceError: {}", why),
+            Error::InvalidLine(why) => write!(f, "InvalidLine: {}", why),
+            Error::ExpectFailed {
+                expected,
+                mask,
+                actual,
+            } => {
+                write!(f, "ExpectFailed: expected {:02x?}", expected)?;
+                if let Some(mask) = mask {
+                    write!(f, " (mask {:02x?})", mask)?;
+                }
+                match actual {
+                    Some(actual) => write!(f, ", got {:02x?}", actual),
+                    None => write!(f, ", but no data was read"),
+                }
+            }
+            Error::Script { line, error } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Displays the levels of the I2C bus lines, e.g. "SDA low, SCL high".
+pub struct BusLevels(pub I2cBusStatus);
+
+impl Display for BusLevels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = |high| if high { "high" } else { "low" };
+        write!(f, "SDA {}, SCL {}", level(self.0.sda), level(self.0.scl))
+    }
+}
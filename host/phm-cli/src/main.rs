@@ -2,9 +2,16 @@ use clap::Parser;
 use phm::Machine;
 use std::time::Duration;
 
-use crate::cli::PhmCli;
+use crate::cli::{PhmCli, Session};
 
 mod cli;
+mod device;
+mod error;
+mod output;
+mod parse;
+mod regs;
+mod repl;
+mod script;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cmd = PhmCli::parse();
@@ -38,11 +45,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut ehal = Machine::from_port(port).unwrap();
 
-    match cmd.run(&mut ehal) {
-        Ok(out) => {
-            println!("{out}");
-            Ok(())
-        }
-        Err(e) => Err(e.into()),
+    let mut session = Session::default();
+
+    if cmd.is_console() {
+        cmd.apply_options(&mut session);
+        return repl::run(&mut ehal, &mut session).map_err(Into::into);
+    }
+
+    let report = cmd.run(&mut ehal, &mut session);
+    session.format.print(&report)?;
+    if let Err(e) = report.result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
+    Ok(())
 }
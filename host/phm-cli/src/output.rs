@@ -0,0 +1,205 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use crate::error::Error;
+
+/// How results of read/transfer commands are printed to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Rust debug formatting, e.g. `[a0, ab, 11]`.
+    #[default]
+    Debug,
+    /// Plain space-separated hex bytes, e.g. `a0 ab 11`.
+    Hex,
+    /// One JSON object per result.
+    Json,
+    /// Raw bytes.
+    Binary,
+    /// Offset, hex bytes and ASCII, similar to `hexdump -C`.
+    Hexdump,
+}
+
+/// The data returned by a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Data {
+    pub bytes: Vec<u8>,
+    /// How the default format shows the data instead of the bytes, e.g. as a table
+    /// of registers.
+    pub text: Option<String>,
+}
+
+impl Data {
+    pub fn with_text(bytes: Vec<u8>, text: impl Into<String>) -> Self {
+        Self {
+            bytes,
+            text: Some(text.into()),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Data {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self { bytes, text: None }
+    }
+}
+
+/// The outcome of a single CLI command.
+#[derive(Debug)]
+pub struct Report {
+    /// The name of the command, e.g. `"i2c read"`.
+    pub command: &'static str,
+    /// The I2C address used by the command, if any.
+    pub address: Option<u8>,
+    /// The data returned by the command. `None` for commands that only write.
+    pub result: Result<Option<Data>, Error>,
+}
+
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    command: &'a str,
+    address: Option<u8>,
+    bytes: Option<&'a [u8]>,
+    error: Option<String>,
+}
+
+impl OutputFormat {
+    /// Print a report to stdout.
+    ///
+    /// The default format prints the text of the data if it has one, other formats
+    /// print the bytes. Errors are only printed by the `json` format, other formats
+    /// leave reporting errors to the caller.
+    pub fn print(&self, report: &Report) -> io::Result<()> {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        self.write(&mut out, report)?;
+        out.flush()
+    }
+
+    pub fn write(&self, out: &mut impl Write, report: &Report) -> io::Result<()> {
+        let data = match (&report.result, self) {
+            (_, OutputFormat::Json) => {
+                let json = JsonReport {
+                    command: report.command,
+                    address: report.address,
+                    bytes: report
+                        .result
+                        .as_ref()
+                        .ok()
+                        .and_then(Option::as_ref)
+                        .map(|data| data.bytes.as_slice()),
+                    error: report.result.as_ref().err().map(|e| e.to_string()),
+                };
+                serde_json::to_writer(&mut *out, &json)?;
+                return writeln!(out);
+            }
+            (Ok(Some(data)), _) => data,
+            (Ok(None), _) | (Err(_), _) => return Ok(()),
+        };
+        let bytes = &data.bytes;
+
+        match self {
+            OutputFormat::Debug => match &data.text {
+                Some(text) => writeln!(out, "{}", text.trim_end()),
+                None => writeln!(out, "{:02x?}", bytes),
+            },
+            OutputFormat::Hex => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                writeln!(out, "{}", hex.join(" "))
+            }
+            OutputFormat::Binary => out.write_all(bytes),
+            OutputFormat::Hexdump => write_hexdump(out, bytes),
+            OutputFormat::Json => unreachable!(),
+        }
+    }
+}
+
+fn write_hexdump(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(out, "{:08x} ", i * 16)?;
+        for col in 0..16 {
+            if col == 8 {
+                write!(out, " ")?;
+            }
+            match line.get(col) {
+                Some(b) => write!(out, " {:02x}", b)?,
+                None => write!(out, "   ")?,
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(out, "  |{}|", ascii)?;
+    }
+    writeln!(out, "{:08x}", bytes.len())
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(OutputFormat::Debug),
+            "hex" => Ok(OutputFormat::Hex),
+            "json" => Ok(OutputFormat::Json),
+            "binary" => Ok(OutputFormat::Binary),
+            "hexdump" => Ok(OutputFormat::Hexdump),
+            _ => Err(format!(
+                "unknown format '{}', expected one of: debug, hex, json, binary, hexdump",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OutputFormat::Debug => "debug",
+            OutputFormat::Hex => "hex",
+            OutputFormat::Json => "json",
+            OutputFormat::Binary => "binary",
+            OutputFormat::Hexdump => "hexdump",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: OutputFormat, data: Data) -> String {
+        let report = Report {
+            command: "i2c dump",
+            address: Some(0x76),
+            result: Ok(Some(data)),
+        };
+        let mut out = Vec::new();
+        format.write(&mut out, &report).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn text_is_only_used_by_the_default_format() {
+        let data = Data::with_text(vec![0x60, 0xd1], "d0: 60 d1\n");
+        assert_eq!(write(OutputFormat::Debug, data.clone()), "d0: 60 d1\n");
+        assert_eq!(write(OutputFormat::Hex, data.clone()), "60 d1\n");
+        assert_eq!(
+            write(OutputFormat::Json, data),
+            "{\"command\":\"i2c dump\",\"address\":118,\"bytes\":[96,209],\"error\":null}\n"
+        );
+        assert_eq!(write(OutputFormat::Debug, vec![0x60].into()), "[60]\n");
+    }
+}
@@ -0,0 +1,440 @@
+//! Parsing of byte arguments given on the command line or in console modes.
+//!
+//! A byte argument is a comma-separated list of items, where each item is one of:
+//!
+//! * A number: `A0` or `0xA0` (hex), `0y1010_0000` (binary) or `0n160` (decimal)
+//! * A number with an explicit width and endianness: `0x1234:u16be`, `0n1000:u32le`
+//! * A quoted string with escapes: `"hello\r\n"`, `"\x00\xff"`
+//! * Any of the above repeated: `0x00*16`, `"ab"*4`, `0xBEEF:u16le*2`
+//! * The contents of a file: `@payload.bin`
+//!
+//! Numbers without a prefix are hex, as bytes always were. So `0b1` and `0d5` used
+//! to be 0xB1 and 0xD5, and are rejected rather than read as binary or decimal.
+//! Repeat counts are decimal.
+
+use std::fmt::Display;
+
+/// The longest byte list, far more than any command takes, so that a typo in a
+/// repeat count fails instead of allocating without bound.
+pub const MAX_LEN: usize = 64 * 1024;
+
+/// An error while parsing a byte argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBytesError {
+    item: String,
+    reason: String,
+}
+
+impl ParseBytesError {
+    fn new(item: &str, reason: impl Into<String>) -> Self {
+        Self {
+            item: item.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for ParseBytesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid byte item '{}': {}", self.item, self.reason)
+    }
+}
+
+impl std::error::Error for ParseBytesError {}
+
+#[derive(Debug, Clone, Copy)]
+enum Width {
+    U8,
+    U16Le,
+    U16Be,
+    U32Le,
+    U32Be,
+}
+
+impl Width {
+    fn max(&self) -> u64 {
+        match self {
+            Width::U8 => u8::MAX.into(),
+            Width::U16Le | Width::U16Be => u16::MAX.into(),
+            Width::U32Le | Width::U32Be => u32::MAX.into(),
+        }
+    }
+
+    fn push(&self, value: u64, out: &mut Vec<u8>) {
+        match self {
+            Width::U8 => out.push(value as u8),
+            Width::U16Le => out.extend_from_slice(&(value as u16).to_le_bytes()),
+            Width::U16Be => out.extend_from_slice(&(value as u16).to_be_bytes()),
+            Width::U32Le => out.extend_from_slice(&(value as u32).to_le_bytes()),
+            Width::U32Be => out.extend_from_slice(&(value as u32).to_be_bytes()),
+        }
+    }
+}
+
+/// Parse a comma-separated list of byte items into a single buffer.
+pub fn parse_bytes(s: &str) -> Result<Vec<u8>, ParseBytesError> {
+    let mut bytes = Vec::new();
+    for item in split_items(s)? {
+        parse_item(item, &mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+/// Parse a single unsigned integer literal, given in hex (`0x`), binary (`0b` or `0y`)
+/// or decimal (`0d`, `0n` or no prefix).
+pub fn parse_int(s: &str) -> Result<u64, ParseBytesError> {
+    parse_number(s, 10)
+}
+
+/// Parse a number with a `0x` (hex), `0y` (binary) or `0n` (decimal) prefix, or in
+/// `default_radix` without one.
+///
+/// Outside of hex, `0b` (binary) and `0d` (decimal) work too. In hex they are
+/// digits, so a lone `0b` or `0d` is a number of its own, and anything longer is
+/// ambiguous.
+fn parse_number(s: &str, default_radix: u32) -> Result<u64, ParseBytesError> {
+    let clean = s.trim().replace('_', "").to_ascii_lowercase();
+    let prefix = clean.get(..2).filter(|_| clean.len() > 2);
+    let (digits, radix) = match (prefix, default_radix) {
+        (Some("0x"), _) => (&clean[2..], 16),
+        (Some("0y"), _) => (&clean[2..], 2),
+        (Some("0n"), _) => (&clean[2..], 10),
+        (Some("0b" | "0d"), 16) => {
+            return Err(ParseBytesError::new(
+                s,
+                format!(
+                    "ambiguous, numbers without a prefix are hex, use '0x{}' for hex, \
+                     '0y' for binary or '0n' for decimal",
+                    clean
+                ),
+            ))
+        }
+        (Some("0b"), _) => (&clean[2..], 2),
+        (Some("0d"), _) => (&clean[2..], 10),
+        (None, _) if ["0x", "0y", "0n"].contains(&clean.as_str()) => {
+            return Err(ParseBytesError::new(s, "missing digits"))
+        }
+        (None, _) if default_radix != 16 && ["0b", "0d"].contains(&clean.as_str()) => {
+            return Err(ParseBytesError::new(s, "missing digits"))
+        }
+        _ => (clean.as_str(), default_radix),
+    };
+
+    u64::from_str_radix(digits, radix).map_err(|e| {
+        let is_hex = clean.chars().all(|c| c.is_ascii_hexdigit());
+        match (radix, digits.len() == clean.len()) {
+            (10, true) if is_hex => ParseBytesError::new(
+                s,
+                format!(
+                    "numbers without a prefix are decimal, use '0x{}' for hex",
+                    clean
+                ),
+            ),
+            (2 | 10, false) if is_hex => ParseBytesError::new(
+                s,
+                format!(
+                    "'{}' is the {} prefix, use '0x{}' for hex",
+                    &clean[..2],
+                    if radix == 2 { "binary" } else { "decimal" },
+                    clean
+                ),
+            ),
+            _ => ParseBytesError::new(s, e.to_string()),
+        }
+    })
+}
+
+/// Split on commas, ignoring any commas inside of quoted strings.
+fn split_items(s: &str) -> Result<Vec<&str>, ParseBytesError> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                items.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    if in_quotes {
+        return Err(ParseBytesError::new(&s[start..], "unterminated string"));
+    }
+    items.push(&s[start..]);
+
+    Ok(items.into_iter().map(str::trim).collect())
+}
+
+fn parse_item(item: &str, out: &mut Vec<u8>) -> Result<(), ParseBytesError> {
+    if item.is_empty() {
+        return Err(ParseBytesError::new(item, "empty item"));
+    }
+
+    if let Some(path) = item.strip_prefix('@') {
+        let contents = std::fs::read(path.trim())
+            .map_err(|e| ParseBytesError::new(item, format!("could not read file: {}", e)))?;
+        check_len(item, out.len(), contents.len(), 1)?;
+        out.extend_from_slice(&contents);
+        return Ok(());
+    }
+
+    let (value, count) = match item.rfind('*') {
+        // A '*' inside of a string is part of the string
+        Some(idx) if !item[idx..].contains('"') => {
+            let count = parse_int(&item[idx + 1..])
+                .map_err(|_| ParseBytesError::new(item, "invalid repeat count"))?;
+            (item[..idx].trim(), count)
+        }
+        _ => (item, 1),
+    };
+
+    let mut single = Vec::new();
+    if value.starts_with('"') {
+        parse_string(value, &mut single).map_err(|reason| ParseBytesError::new(item, reason))?;
+    } else {
+        let (number, width) = match value.split_once(':') {
+            Some((number, width)) => (number, parse_width(width.trim(), item)?),
+            None => (value, Width::U8),
+        };
+        let value = parse_number(number, 16).map_err(|e| ParseBytesError::new(item, e.reason))?;
+        if value > width.max() {
+            let number = number.trim();
+            let reason = match number.chars().all(|c| c.is_ascii_digit()) {
+                true => format!(
+                    "value does not fit in {} bits, numbers without a prefix are hex, \
+                     use '0n{}' for decimal",
+                    bits(width),
+                    number
+                ),
+                false => format!("value does not fit in {} bits", bits(width)),
+            };
+            return Err(ParseBytesError::new(item, reason));
+        }
+        width.push(value, &mut single);
+    }
+
+    check_len(item, out.len(), single.len(), count)?;
+    for _ in 0..count {
+        out.extend_from_slice(&single);
+    }
+
+    Ok(())
+}
+
+/// Check that `count` repeats of `len` bytes fit after `used` bytes, within [MAX_LEN].
+fn check_len(item: &str, used: usize, len: usize, count: u64) -> Result<(), ParseBytesError> {
+    let total = u64::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_mul(count))
+        .and_then(|total| total.checked_add(used as u64));
+    match total {
+        Some(total) if total <= MAX_LEN as u64 => Ok(()),
+        _ => Err(ParseBytesError::new(
+            item,
+            format!("more than {} bytes in total", MAX_LEN),
+        )),
+    }
+}
+
+fn bits(width: Width) -> u32 {
+    match width {
+        Width::U8 => 8,
+        Width::U16Le | Width::U16Be => 16,
+        Width::U32Le | Width::U32Be => 32,
+    }
+}
+
+fn parse_width(width: &str, item: &str) -> Result<Width, ParseBytesError> {
+    match width {
+        "u8" => Ok(Width::U8),
+        "u16le" => Ok(Width::U16Le),
+        "u16be" => Ok(Width::U16Be),
+        "u32le" => Ok(Width::U32Le),
+        "u32be" => Ok(Width::U32Be),
+        "u16" | "u32" => Err(ParseBytesError::new(
+            item,
+            format!("missing endianness, use '{}le' or '{}be'", width, width),
+        )),
+        _ => Err(ParseBytesError::new(
+            item,
+            format!(
+                "unknown width '{}', expected one of: u8, u16le, u16be, u32le, u32be",
+                width
+            ),
+        )),
+    }
+}
+
+fn parse_string(s: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| "unterminated string".to_string())?;
+
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push(b'\n'),
+            Some('r') => out.push(b'\r'),
+            Some('t') => out.push(b'\t'),
+            Some('0') => out.push(0),
+            Some('\\') => out.push(b'\\'),
+            Some('"') => out.push(b'"'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid escape '\\x{}'", hex))?;
+                out.push(byte);
+            }
+            Some(other) => return Err(format!("unknown escape '\\{}'", other)),
+            None => return Err("trailing '\\'".into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(s: &str) -> String {
+        parse_bytes(s).unwrap_err().reason
+    }
+
+    #[test]
+    fn numbers_are_hex_without_a_prefix() {
+        assert_eq!(parse_bytes("A0, 10, ff").unwrap(), [0xA0, 0x10, 0xFF]);
+        assert_eq!(parse_bytes("0b, 0d").unwrap(), [0x0B, 0x0D]);
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(
+            parse_bytes("0xA0, 0y1010_0000, 0n160, 0XA0, 0Y1010_0000, 0N160").unwrap(),
+            [160; 6]
+        );
+        assert_eq!(parse_int("160").unwrap(), 160);
+        assert_eq!(parse_int("0x10").unwrap(), 16);
+        assert_eq!(parse_int("0b10").unwrap(), 2);
+        assert_eq!(parse_int("0d10").unwrap(), 10);
+        assert_eq!(parse_int("0y10").unwrap(), 2);
+        assert_eq!(parse_int("0n10").unwrap(), 10);
+    }
+
+    #[test]
+    fn hex_digit_prefixes_are_ambiguous() {
+        // Byte lists were always hex, where these were 0xB1 and 0xD5
+        for item in ["0b1", "0d5", "0B1", "0b1010_0000", "0d160:u16le"] {
+            assert!(
+                reason(item).starts_with("ambiguous, numbers without a prefix are hex"),
+                "{}",
+                item
+            );
+        }
+        assert_eq!(
+            reason("0b1"),
+            "ambiguous, numbers without a prefix are hex, use '0x0b1' for hex, \
+             '0y' for binary or '0n' for decimal"
+        );
+        assert_eq!(parse_bytes("0x0b1:u16be").unwrap(), [0x00, 0xB1]);
+        assert_eq!(parse_bytes("b1, d5").unwrap(), [0xB1, 0xD5]);
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(parse_bytes("0x1234:u16le").unwrap(), [0x34, 0x12]);
+        assert_eq!(parse_bytes("0x1234:u16be").unwrap(), [0x12, 0x34]);
+        assert_eq!(parse_bytes("0n1000:u32le").unwrap(), 1000u32.to_le_bytes());
+        assert_eq!(parse_bytes("1:u32be").unwrap(), [0, 0, 0, 1]);
+        assert_eq!(parse_bytes("7:u8").unwrap(), [7]);
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(reason("0x100"), "value does not fit in 8 bits");
+        assert_eq!(
+            reason("255"),
+            "value does not fit in 8 bits, numbers without a prefix are hex, \
+             use '0n255' for decimal"
+        );
+        assert_eq!(reason("0x10000:u16le"), "value does not fit in 16 bits");
+        assert_eq!(parse_bytes("0xFFFFFFFF:u32be").unwrap(), [0xFF; 4]);
+        assert_eq!(reason("0x100000000:u32be"), "value does not fit in 32 bits");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(parse_bytes(r#""a,b\r\n""#).unwrap(), b"a,b\r\n");
+        assert_eq!(
+            parse_bytes(r#""\x00\xff\"\\", 01"#).unwrap(),
+            [0, 0xFF, b'"', b'\\', 1]
+        );
+        assert_eq!(reason(r#""\q""#), "unknown escape '\\q'");
+        assert_eq!(reason(r#""abc"#), "unterminated string");
+    }
+
+    #[test]
+    fn repeats() {
+        assert_eq!(parse_bytes("0x00*3").unwrap(), [0; 3]);
+        assert_eq!(parse_bytes("AA * 10").unwrap(), [0xAA; 10]);
+        assert_eq!(parse_bytes(r#""ab"*2"#).unwrap(), b"abab");
+        assert_eq!(parse_bytes(r#""a*2""#).unwrap(), b"a*2");
+        assert_eq!(
+            parse_bytes("0xBEEF:u16le*2").unwrap(),
+            [0xEF, 0xBE, 0xEF, 0xBE]
+        );
+        assert!(parse_bytes("01*0").unwrap().is_empty());
+        assert_eq!(reason("01*x"), "invalid repeat count");
+    }
+
+    #[test]
+    fn length_is_capped() {
+        assert_eq!(
+            parse_bytes(&format!("00*{}", MAX_LEN)).unwrap().len(),
+            MAX_LEN
+        );
+        let too_long = format!("more than {} bytes in total", MAX_LEN);
+        assert_eq!(reason(&format!("00*{}", MAX_LEN + 1)), too_long);
+        assert_eq!(reason(&format!("00, 00*{}", MAX_LEN)), too_long);
+        assert_eq!(reason("00*4000000000"), too_long);
+        assert_eq!(reason(&format!("00:u32le*{}", u64::MAX)), too_long);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(reason("01,,02"), "empty item");
+        assert_eq!(reason("0x"), "missing digits");
+        assert_eq!(reason("xyz"), "invalid digit found in string");
+        assert_eq!(reason("0y"), "missing digits");
+        assert_eq!(reason("0y12"), "invalid digit found in string");
+        assert_eq!(
+            parse_int("0b12").unwrap_err().reason,
+            "'0b' is the binary prefix, use '0x0b12' for hex"
+        );
+        assert_eq!(parse_int("0d").unwrap_err().reason, "missing digits");
+        assert_eq!(
+            reason("1:u16"),
+            "missing endianness, use 'u16le' or 'u16be'"
+        );
+        assert!(reason("1:u64le").starts_with("unknown width 'u64le'"));
+        assert_eq!(
+            parse_int("ff").unwrap_err().reason,
+            "numbers without a prefix are decimal, use '0xff' for hex"
+        );
+        assert!(reason("@/nonexistent/file").starts_with("could not read file"));
+    }
+}
@@ -0,0 +1,243 @@
+//! Register access helpers for the `i2c get`/`set`/`modify`/`dump` commands
+
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use phm::{
+    i2c::I2cBus,
+    register::{AddressWidth, Endian, I2cRegisters},
+};
+
+use crate::parse::parse_int;
+
+/// The width of a register address or value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Bits8,
+    Bits16,
+}
+
+/// How a register is addressed and how its value is encoded.
+#[derive(Debug, Clone, Copy)]
+pub struct Layout {
+    pub reg_width: Width,
+    pub value_width: Width,
+    /// Send 16-bit register addresses and values least significant byte first.
+    pub little_endian: bool,
+}
+
+impl Layout {
+    fn registers(&self, address: u8) -> I2cRegisters {
+        let address_width = match self.reg_width {
+            Width::Bits8 => AddressWidth::Bits8,
+            Width::Bits16 => AddressWidth::Bits16,
+        };
+        let endian = match self.little_endian {
+            true => Endian::Little,
+            false => Endian::Big,
+        };
+        I2cRegisters::new(address)
+            .address_width(address_width)
+            .endian(endian)
+    }
+
+    /// The value as big-endian bytes, the way it would be written as a number.
+    pub fn display_bytes(&self, value: u16) -> Vec<u8> {
+        match self.value_width {
+            Width::Bits8 => vec![value as u8],
+            Width::Bits16 => value.to_be_bytes().to_vec(),
+        }
+    }
+
+    /// The largest value that fits into a register.
+    pub fn max_value(&self) -> u16 {
+        match self.value_width {
+            Width::Bits8 => u8::MAX.into(),
+            Width::Bits16 => u16::MAX,
+        }
+    }
+}
+
+/// Read a single register.
+pub fn get(
+    bus: &mut I2cBus,
+    address: u8,
+    layout: &Layout,
+    register: u16,
+) -> Result<u16, phm::Error> {
+    let regs = layout.registers(address);
+    match layout.value_width {
+        Width::Bits8 => regs.read::<u8, _>(bus, register).map(u16::from),
+        Width::Bits16 => regs.read(bus, register),
+    }
+}
+
+/// Write a single register.
+pub fn set(
+    bus: &mut I2cBus,
+    address: u8,
+    layout: &Layout,
+    register: u16,
+    value: u16,
+) -> Result<(), phm::Error> {
+    let regs = layout.registers(address);
+    match layout.value_width {
+        Width::Bits8 => regs.write(bus, register, value as u8),
+        Width::Bits16 => regs.write(bus, register, value),
+    }
+}
+
+/// Read a range of 8-bit registers, either relying on the device to auto-increment
+/// the register address, or with one transaction per register.
+pub fn read_range(
+    bus: &mut I2cBus,
+    address: u8,
+    layout: &Layout,
+    start: u16,
+    end: u16,
+    auto_increment: bool,
+) -> Result<Vec<u8>, phm::Error> {
+    let regs = layout.registers(address);
+    let mut data = Vec::with_capacity(usize::from(end - start) + 1);
+    let chunk = if auto_increment { 16 } else { 1 };
+
+    let mut register = u32::from(start);
+    while register <= end.into() {
+        let len = (u32::from(end) + 1 - register).min(chunk) as usize;
+        let mut buf = vec![0u8; len];
+        regs.read_bytes(bus, register as u16, &mut buf)?;
+        data.extend(buf);
+        register += len as u32;
+    }
+
+    Ok(data)
+}
+
+/// Print registers starting at `start` as a table, like `i2cdump`.
+pub fn write_dump(out: &mut impl Write, start: u16, data: &[u8]) -> io::Result<()> {
+    let end = usize::from(start) + data.len();
+    let label_width = if end > 0x100 { 4 } else { 2 };
+
+    write!(out, "{:width$} ", "", width = label_width + 1)?;
+    for col in 0..16 {
+        write!(out, " {:x} ", col)?;
+    }
+    writeln!(out, "   0123456789abcdef")?;
+
+    let first_row = usize::from(start) & !0xF;
+    for row in (first_row..end).step_by(16) {
+        write!(out, "{:0width$x}: ", row, width = label_width)?;
+        let mut ascii = String::with_capacity(16);
+        for reg in row..row + 16 {
+            match reg
+                .checked_sub(start.into())
+                .and_then(|offset| data.get(offset))
+            {
+                Some(&b) => {
+                    write!(out, "{:02x} ", b)?;
+                    ascii.push(match b {
+                        0x20..=0x7E => b as char,
+                        _ => '.',
+                    });
+                }
+                None => {
+                    write!(out, "   ")?;
+                    ascii.push(' ');
+                }
+            }
+        }
+        writeln!(out, "   {}", ascii)?;
+    }
+
+    Ok(())
+}
+
+impl FromStr for Width {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(Width::Bits8),
+            "16" => Ok(Width::Bits16),
+            _ => Err(format!("invalid width '{}', expected 8 or 16", s)),
+        }
+    }
+}
+
+/// A 16-bit number, given in hex (`0x`), binary (`0b`) or decimal.
+#[derive(Debug, Clone, Copy)]
+pub struct Word(pub u16);
+
+impl FromStr for Word {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = parse_int(s).map_err(|e| e.to_string())?;
+        u16::try_from(value)
+            .map(Word)
+            .map_err(|_| format!("'{}' does not fit in 16 bits", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(reg_width: Width, value_width: Width) -> Layout {
+        Layout {
+            reg_width,
+            value_width,
+            little_endian: false,
+        }
+    }
+
+    #[test]
+    fn value_bytes() {
+        let bits8 = layout(Width::Bits8, Width::Bits8);
+        assert_eq!(bits8.display_bytes(0xAB), [0xAB]);
+        assert_eq!(bits8.max_value(), 0xFF);
+
+        let mut bits16 = layout(Width::Bits16, Width::Bits16);
+        assert_eq!(bits16.display_bytes(0x1234), [0x12, 0x34]);
+        assert_eq!(bits16.max_value(), 0xFFFF);
+        // Shown as a number, whatever the order on the bus
+        bits16.little_endian = true;
+        assert_eq!(bits16.display_bytes(0x1234), [0x12, 0x34]);
+    }
+
+    #[test]
+    fn widths_and_words() {
+        assert_eq!("8".parse::<Width>(), Ok(Width::Bits8));
+        assert_eq!("16".parse::<Width>(), Ok(Width::Bits16));
+        assert!("32".parse::<Width>().is_err());
+
+        assert_eq!("0x1234".parse::<Word>().unwrap().0, 0x1234);
+        assert_eq!("0b101".parse::<Word>().unwrap().0, 5);
+        assert_eq!("65535".parse::<Word>().unwrap().0, 0xFFFF);
+        assert_eq!(
+            "0x10000".parse::<Word>().unwrap_err(),
+            "'0x10000' does not fit in 16 bits"
+        );
+    }
+
+    #[test]
+    fn dump() {
+        let mut out = Vec::new();
+        write_dump(&mut out, 0x0E, b"AB\x00C").unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("     0  1  2"));
+        assert!(lines[1].starts_with("00: "));
+        assert!(lines[1].ends_with("41 42                  AB"));
+        assert!(lines[2].starts_with("10: 00 43    "));
+        assert!(lines[2].ends_with(".C              "));
+
+        // Registers above 0xFF get wider labels
+        let mut out = Vec::new();
+        write_dump(&mut out, 0x100, &[0; 16]).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("\n0100: 00 00"));
+    }
+}
@@ -0,0 +1,161 @@
+//! The interactive console (`phm-cli console`)
+
+use std::{io, path::PathBuf};
+
+use clap::CommandFactory;
+use phm::Machine;
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    validate::Validator, Context, Editor, Helper,
+};
+
+use crate::{
+    cli::{PhmCli, Session},
+    error::Error,
+};
+
+/// Commands that are handled by the console itself.
+const BUILTINS: &[&str] = &["exit", "quit"];
+
+/// Run the console until the user exits, or stdin is closed.
+pub fn run(machine: &mut Machine, session: &mut Session) -> Result<(), Error> {
+    session.console = true;
+    let mut editor = Editor::<PhmHelper>::new();
+    editor.set_helper(Some(PhmHelper {
+        command: PhmCli::command(),
+    }));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file is fine, it will be created on exit
+        editor.load_history(path).ok();
+    }
+
+    println!("pretty HAL machine console. Type `help` for a list of commands, `exit` to quit.");
+
+    let result = loop {
+        match editor.readline("phm> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(line);
+
+                if BUILTINS.contains(&line) {
+                    break Ok(());
+                }
+                execute_line(machine, session, line);
+            }
+            // Ctrl-C discards the current line, like in a shell
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break Ok(()),
+            Err(e) => break Err(Error::Io(io::Error::other(e))),
+        };
+    };
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Warning: failed to save history: {}", e);
+        }
+    }
+
+    result
+}
+
+/// Parse and run a single command line, printing the results.
+fn execute_line(machine: &mut Machine, session: &mut Session, line: &str) {
+    let cmd = match PhmCli::from_line(line) {
+        Ok(cmd) => cmd,
+        Err(Error::Clap(e)) => {
+            // This also prints `--help` output to stdout
+            e.print().ok();
+            return;
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+
+    let report = cmd.run(machine, session);
+    if let Err(e) = session.format.print(&report) {
+        eprintln!("Error: {}", e);
+    }
+    if let Err(e) = &report.result {
+        eprintln!("Error: {}", e);
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".phm_history"))
+}
+
+/// Tab completion of (sub)command names and options.
+struct PhmHelper {
+    command: clap::Command<'static>,
+}
+
+impl Completer for PhmHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let mut words: Vec<&str> = line.split_whitespace().collect();
+        let partial = match line.ends_with(char::is_whitespace) {
+            true => "",
+            false => words.pop().unwrap_or(""),
+        };
+
+        // Walk down the subcommands named so far
+        let mut current = &self.command;
+        for &word in words.iter().filter(|w| !w.starts_with('-')) {
+            match current.find_subcommand(word) {
+                Some(sub) => current = sub,
+                None => break,
+            }
+        }
+
+        let mut candidates: Vec<String> = if partial.starts_with('-') {
+            current
+                .get_arguments()
+                .chain(self.command.get_arguments())
+                .filter_map(|arg| arg.get_long())
+                .map(|long| format!("--{}", long))
+                .filter(|long| long.starts_with(partial))
+                .collect()
+        } else {
+            let mut names: Vec<String> = current
+                .get_subcommands()
+                .map(|sub| sub.get_name().to_string())
+                .collect();
+            if words.is_empty() {
+                names.push("help".into());
+                names.extend(BUILTINS.iter().map(|b| b.to_string()));
+            }
+            names
+                .into_iter()
+                .filter(|name| name.starts_with(partial))
+                .collect()
+        };
+        candidates.sort();
+        candidates.dedup();
+
+        Ok((pos - partial.len(), candidates))
+    }
+}
+
+impl Hinter for PhmHelper {
+    type Hint = String;
+}
+
+impl Highlighter for PhmHelper {}
+
+impl Validator for PhmHelper {}
+
+impl Helper for PhmHelper {}
@@ -0,0 +1,381 @@
+//! The script runner (`phm-cli run`)
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::Path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use phm::Machine;
+
+use crate::{
+    cli::{PhmCli, Session},
+    error::Error,
+    output::{Data, Report},
+    parse::{parse_bytes, parse_int},
+};
+
+/// How deeply scripts may `run` other scripts, so that a script running itself fails
+/// instead of overflowing the stack.
+const MAX_DEPTH: usize = 8;
+
+pub const SCRIPT_HELP: &str = r#"SCRIPT SYNTAX:
+    One command per line, where each line is one of:
+        i2c write -a 0x76 -b 0xF4,0x27   any phm-cli command, without the `phm-cli` prefix
+        sleep 10                         wait for 10ms. `us`, `ms` and `s` suffixes are supported
+        set ADDR = 0x76                  define a variable, used as `$ADDR` or `${ADDR}`
+        expect 0x60                      fail unless the last data read matches these bytes
+        expect 0x60,0x00 mask 0xFF,0xF0  as above, only comparing bits set in the mask
+        # a comment                      ignored, as are empty lines
+    The script stops at the first failing line, with a nonzero exit code. Progress is
+    logged to stderr, and the data read is printed to stdout in the `--format` given.
+    Scripts can `run` other scripts, nested up to 8 deep, but can't start the `console`."#;
+
+/// A variable definition given on the command line, e.g. `ADDR=0x76`.
+#[derive(Debug, Clone)]
+pub struct Define {
+    name: String,
+    value: String,
+}
+
+impl FromStr for Define {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got '{}'", s))?;
+        Ok(Define {
+            name: name.trim().into(),
+            value: value.trim().into(),
+        })
+    }
+}
+
+/// The state of a running script.
+struct Script<'a> {
+    machine: &'a mut Machine,
+    session: &'a mut Session,
+    variables: HashMap<String, String>,
+    /// The data read by the most recent command that read data.
+    last_read: Option<Vec<u8>>,
+}
+
+/// Run a script, logging a line for each step to stderr, and printing the data read
+/// to stdout in the output format of the session.
+///
+/// Stops at the first step that fails.
+pub fn run(
+    machine: &mut Machine,
+    session: &mut Session,
+    path: &Path,
+    defines: &[Define],
+) -> Result<Option<Data>, Error> {
+    if session.script_depth >= MAX_DEPTH {
+        return Err(Error::Unsupported("scripts are nested too deeply"));
+    }
+
+    session.script_depth += 1;
+    let result = run_source(machine, session, path, defines);
+    session.script_depth -= 1;
+    result
+}
+
+fn run_source(
+    machine: &mut Machine,
+    session: &mut Session,
+    path: &Path,
+    defines: &[Define],
+) -> Result<Option<Data>, Error> {
+    let source = std::fs::read_to_string(path)?;
+    let mut script = Script {
+        machine,
+        session,
+        variables: defines
+            .iter()
+            .map(|d| (d.name.clone(), d.value.clone()))
+            .collect(),
+        last_read: None,
+    };
+
+    let mut steps = 0;
+    for (idx, line) in source.lines().enumerate() {
+        let number = idx + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = substitute(&script.variables, line).map_err(|error| Error::Script {
+            line: number,
+            error: Box::new(error),
+        })?;
+
+        eprint!("[{:>4}] {} ... ", number, line);
+        std::io::stderr().flush().ok();
+
+        let start = Instant::now();
+        match script.step(&line) {
+            Ok(report) => {
+                eprintln!("ok ({} ms)", start.elapsed().as_millis());
+                if let Some(report) = report {
+                    script.session.format.print(&report)?;
+                }
+            }
+            Err(error) => {
+                eprintln!("FAILED");
+                return Err(Error::Script {
+                    line: number,
+                    error: Box::new(error),
+                });
+            }
+        }
+        steps += 1;
+    }
+
+    eprintln!("{}: {} steps completed", path.display(), steps);
+    Ok(None)
+}
+
+impl<'a> Script<'a> {
+    /// Run a single (already substituted) line, returning the report of a command.
+    fn step(&mut self, line: &str) -> Result<Option<Report>, Error> {
+        let (keyword, rest) = line
+            .split_once(char::is_whitespace)
+            .map(|(k, r)| (k, r.trim()))
+            .unwrap_or((line, ""));
+
+        match keyword {
+            "sleep" => {
+                std::thread::sleep(parse_duration(rest)?);
+                Ok(None)
+            }
+            "set" => {
+                let (name, value) = rest
+                    .split_once('=')
+                    .or_else(|| rest.split_once(char::is_whitespace))
+                    .ok_or_else(|| Error::InvalidLine("expected `set NAME = VALUE`".into()))?;
+                self.variables
+                    .insert(name.trim().into(), value.trim().into());
+                Ok(None)
+            }
+            "expect" => {
+                expect(self.last_read.as_deref(), rest)?;
+                Ok(None)
+            }
+            _ => {
+                let cmd = PhmCli::from_line(line)?;
+                if cmd.is_console() {
+                    return Err(Error::Unsupported(
+                        "the console can't be started from a script",
+                    ));
+                }
+
+                let Report {
+                    command,
+                    address,
+                    result,
+                } = cmd.run(self.machine, self.session);
+                let data = result?;
+                if let Some(data) = &data {
+                    self.last_read = Some(data.bytes.clone());
+                }
+                Ok(Some(Report {
+                    command,
+                    address,
+                    result: Ok(data),
+                }))
+            }
+        }
+    }
+}
+
+/// Check the data read last against an `expect` line, e.g. `0x60,0x00 mask 0xFF,0xF0`.
+fn expect(last_read: Option<&[u8]>, args: &str) -> Result<(), Error> {
+    let invalid = |e: crate::parse::ParseBytesError| Error::InvalidLine(e.to_string());
+    let (expected, mask) = match args.split_once(" mask ") {
+        Some((expected, mask)) => (
+            parse_bytes(expected.trim()).map_err(invalid)?,
+            Some(parse_bytes(mask.trim()).map_err(invalid)?),
+        ),
+        None => (parse_bytes(args).map_err(invalid)?, None),
+    };
+
+    if let Some(mask) = &mask {
+        if mask.len() != expected.len() {
+            return Err(Error::InvalidLine(
+                "mask must be the same length as the expected bytes".into(),
+            ));
+        }
+    }
+
+    let matches = match last_read {
+        Some(actual) if actual.len() == expected.len() => actual
+            .iter()
+            .zip(expected.iter())
+            .enumerate()
+            .all(|(i, (a, e))| {
+                let m = mask.as_ref().map(|m| m[i]).unwrap_or(0xFF);
+                (a & m) == (e & m)
+            }),
+        _ => false,
+    };
+
+    match matches {
+        true => Ok(()),
+        false => Err(Error::ExpectFailed {
+            expected,
+            mask,
+            actual: last_read.map(<[u8]>::to_vec),
+        }),
+    }
+}
+
+/// Replace `$NAME` and `${NAME}` with the value of the variable.
+fn substitute(variables: &HashMap<String, String>, line: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx + 1..];
+
+        let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| Error::InvalidLine("unterminated '${'".into()))?;
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            return Err(Error::InvalidLine(
+                "expected a variable name after '$'".into(),
+            ));
+        }
+        let value = variables
+            .get(name)
+            .ok_or_else(|| Error::InvalidLine(format!("undefined variable '{}'", name)))?;
+        out.push_str(value);
+        rest = remaining;
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Parse a duration such as `10`, `10ms`, `500us` or `2s`. Without a suffix, milliseconds are used.
+fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let invalid = || Error::InvalidLine(format!("invalid duration '{}'", s));
+
+    let (number, unit): (&str, fn(u64) -> Duration) = if let Some(us) = s.strip_suffix("us") {
+        (us, Duration::from_micros)
+    } else if let Some(ms) = s.strip_suffix("ms") {
+        (ms, Duration::from_millis)
+    } else if let Some(secs) = s.strip_suffix('s') {
+        (secs, Duration::from_secs)
+    } else {
+        (s, Duration::from_millis)
+    };
+
+    parse_int(number.trim()).map(unit).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> HashMap<String, String> {
+        [("ADDR", "0x76"), ("REG", "0xD0")]
+            .into_iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect()
+    }
+
+    fn invalid_line(error: Error) -> String {
+        match error {
+            Error::InvalidLine(why) => why,
+            other => panic!("expected an invalid line, got {}", other),
+        }
+    }
+
+    #[test]
+    fn substitute_variables() {
+        let vars = variables();
+        assert_eq!(
+            substitute(&vars, "i2c write -a $ADDR -b $REG").unwrap(),
+            "i2c write -a 0x76 -b 0xD0"
+        );
+        assert_eq!(
+            substitute(&vars, "i2c write -b ${REG},0x00").unwrap(),
+            "i2c write -b 0xD0,0x00"
+        );
+        assert_eq!(substitute(&vars, "$REG$ADDR").unwrap(), "0xD00x76");
+        assert_eq!(substitute(&vars, "no variables").unwrap(), "no variables");
+    }
+
+    #[test]
+    fn substitute_errors() {
+        let vars = variables();
+        let error = |line| invalid_line(substitute(&vars, line).unwrap_err());
+        assert_eq!(error("-a $OTHER"), "undefined variable 'OTHER'");
+        assert_eq!(error("-a ${ADDR"), "unterminated '${'");
+        assert_eq!(error("-a $ -b 1"), "expected a variable name after '$'");
+        assert_eq!(error("-a ${}"), "expected a variable name after '$'");
+    }
+
+    #[test]
+    fn expect_bytes() {
+        assert!(expect(Some(&[0x60]), "0x60").is_ok());
+        assert!(expect(Some(&[0x60, 0x01]), "60,01").is_ok());
+        match expect(Some(&[0x61]), "0x60").unwrap_err() {
+            Error::ExpectFailed {
+                expected,
+                mask,
+                actual,
+            } => {
+                assert_eq!(expected, [0x60]);
+                assert_eq!(mask, None);
+                assert_eq!(actual, Some(vec![0x61]));
+            }
+            other => panic!("expected a failed expect, got {}", other),
+        }
+        // The length must match too
+        assert!(expect(Some(&[0x60, 0x00]), "0x60").is_err());
+        assert!(matches!(
+            expect(None, "0x60"),
+            Err(Error::ExpectFailed { actual: None, .. })
+        ));
+    }
+
+    #[test]
+    fn expect_mask() {
+        assert!(expect(Some(&[0x67, 0x0F]), "0x60,0x00 mask 0xF0,0xF0").is_ok());
+        assert!(expect(Some(&[0x08]), "0x00 mask 0x08").is_err());
+        assert!(expect(Some(&[0xF7]), "0x00 mask 0x08").is_ok());
+        assert!(expect(Some(&[0x12]), "0x00 mask 0x00").is_ok());
+        assert_eq!(
+            invalid_line(expect(Some(&[0x60, 0x00]), "0x60,0x00 mask 0xFF").unwrap_err()),
+            "mask must be the same length as the expected bytes"
+        );
+        assert!(matches!(
+            expect(Some(&[0x60]), "0x60 mask zz"),
+            Err(Error::InvalidLine(_))
+        ));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("10").unwrap(), Duration::from_millis(10));
+        assert_eq!(parse_duration("10ms").unwrap(), Duration::from_millis(10));
+        assert_eq!(parse_duration("500us").unwrap(), Duration::from_micros(500));
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert!(parse_duration("2m").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Analog inputs of the worker
+
+use std::time::Duration;
+
+use phm_icd::{AdcChannel, ToMcu, ToMcuAdc, ToPc, ToPcAdc};
+
+use crate::{Error, Machine};
+
+/// The most samples taken by [Machine::adc_sample].
+pub const MAX_SAMPLES: usize = 32;
+
+/// A sample of an analog input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdcSample {
+    /// The sample as read from the ADC.
+    pub raw: u16,
+    /// The input voltage, in millivolts.
+    pub millivolts: u32,
+}
+
+impl AdcSample {
+    /// Scale a sample of a channel, whose resolution must be 1 to 16 bits.
+    fn new(info: &AdcChannel, raw: u16) -> Result<Self, Error> {
+        if !(1..=16).contains(&info.resolution_bits) {
+            return Err(Error::ResponseError);
+        }
+        let max = (1u32 << info.resolution_bits) - 1;
+        let millivolts = (u32::from(raw) * u32::from(info.full_scale_mv) + max / 2) / max;
+        Ok(Self { raw, millivolts })
+    }
+}
+
+impl Machine {
+    /// List the analog input channels of the worker, in the order of their channel numbers.
+    pub fn adc_channels(&mut self) -> Result<Vec<AdcChannel>, Error> {
+        self.command(&ToMcu::Adc(ToMcuAdc::ListChannels), |msg| match msg {
+            ToPc::Adc(ToPcAdc::Channels(channels)) => Some(channels.to_vec()),
+            _ => None,
+        })
+    }
+
+    /// Take one sample of an analog input channel.
+    pub fn adc_read(&mut self, channel: u8) -> Result<AdcSample, Error> {
+        let samples = self.adc_sample(channel, 1, Duration::ZERO)?;
+        samples.first().copied().ok_or(Error::ResponseError)
+    }
+
+    /// Take `count` samples of an analog input channel, `interval` apart, timed by the
+    /// worker. At most [MAX_SAMPLES] samples can be taken at once.
+    pub fn adc_sample(
+        &mut self,
+        channel: u8,
+        count: usize,
+        interval: Duration,
+    ) -> Result<Vec<AdcSample>, Error> {
+        if count > MAX_SAMPLES {
+            return Err(Error::InvalidParameter);
+        }
+        let msg = ToMcu::Adc(ToMcuAdc::Sample {
+            channel,
+            count: count as u8,
+            interval_us: interval
+                .as_micros()
+                .try_into()
+                .map_err(|_| Error::InvalidParameter)?,
+        });
+        let (info, samples) = self.command(&msg, |msg| match msg {
+            ToPc::Adc(ToPcAdc::Samples {
+                channel: ch,
+                info,
+                samples,
+            }) if ch == channel => Some((info, samples)),
+            _ => None,
+        })?;
+
+        if samples.len() != count {
+            return Err(Error::ResponseError);
+        }
+        samples
+            .iter()
+            .map(|&raw| AdcSample::new(&info, raw))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(resolution_bits: u8) -> AdcChannel {
+        AdcChannel {
+            resolution_bits,
+            full_scale_mv: 3300,
+        }
+    }
+
+    #[test]
+    fn samples_are_scaled_to_millivolts() {
+        let sample = AdcSample::new(&channel(12), 4095).unwrap();
+        assert_eq!(sample.millivolts, 3300);
+        let sample = AdcSample::new(&channel(12), 2048).unwrap();
+        assert_eq!(sample.millivolts, 1650);
+        let sample = AdcSample::new(&channel(16), u16::MAX).unwrap();
+        assert_eq!(sample.millivolts, 3300);
+        let sample = AdcSample::new(&channel(1), 1).unwrap();
+        assert_eq!(sample.millivolts, 3300);
+    }
+
+    #[test]
+    fn invalid_resolutions_are_rejected() {
+        assert!(AdcSample::new(&channel(0), 0).is_err());
+        assert!(AdcSample::new(&channel(17), 0).is_err());
+        assert!(AdcSample::new(&channel(32), 0).is_err());
+    }
+}
@@ -0,0 +1,46 @@
+//! Board-specific commands, see [phm_icd::custom]
+
+pub use phm_icd::custom::{CustomCommand, MAX_PAYLOAD};
+use phm_icd::{
+    custom::{from_payload, to_payload},
+    ToMcu, ToPc,
+};
+
+use crate::{Error, Machine};
+
+impl Machine {
+    /// Send the custom command `id` with the payload `bytes`, and return the payload
+    /// of the response.
+    ///
+    /// Workers answer ids they don't know with [Error::Unsupported].
+    pub fn custom(&mut self, id: u16, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let payload = bytes.try_into().map_err(|_| Error::InvalidParameter)?;
+        let payload = self.command(&ToMcu::Custom { id, payload }, |msg| match msg {
+            ToPc::Custom {
+                id: resp_id,
+                payload,
+            } if resp_id == id => Some(payload),
+            _ => None,
+        })?;
+        Ok(payload.to_vec())
+    }
+
+    /// Send a typed custom command, and decode its response.
+    ///
+    /// ```no_run
+    /// # use phm::custom::CustomCommand;
+    /// # use serde::{Deserialize, Serialize};
+    /// # #[derive(Serialize, Deserialize)]
+    /// # pub struct SetRelays { pub mask: u8, pub on: bool }
+    /// # impl CustomCommand for SetRelays { const ID: u16 = 1; type Response = u8; }
+    /// # fn demo(machine: &mut phm::Machine) -> Result<(), phm::Error> {
+    /// let relays = machine.custom_command(&SetRelays { mask: 0b11, on: true })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn custom_command<C: CustomCommand>(&mut self, request: &C) -> Result<C::Response, Error> {
+        let payload = to_payload(request)?;
+        let response = self.custom(C::ID, &payload)?;
+        Ok(from_payload(&response)?)
+    }
+}
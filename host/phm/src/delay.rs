@@ -0,0 +1,113 @@
+//! Delays timed by the worker
+
+use std::{cell::RefCell, time::Duration};
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal_1::delay::DelayNs;
+
+use crate::Machine;
+
+/// The longest delay sent in a single command, well within the worker's default
+/// command deadline.
+pub const MAX_DELAY_US: u32 = 500_000;
+
+/// A delay provider for drivers, executing its delays on the worker of a [Machine].
+///
+/// Shares the machine with the bus handles used by the same driver:
+///
+/// ```no_run
+/// # fn demo(machine: phm::Machine) {
+/// use std::cell::RefCell;
+/// use phm::{delay::MachineDelay, spi::SpiDevice};
+///
+/// let machine = RefCell::new(machine);
+/// let radio = SpiDevice::new(&machine, 0);
+/// let delay = MachineDelay::new(&machine);
+/// # }
+/// ```
+///
+/// Each delay is a command of its own, see [Machine::delay]. The embedded-hal delay
+/// traits can't report errors, so the delay methods panic if the worker doesn't
+/// complete the delay.
+pub struct MachineDelay<'a> {
+    machine: &'a RefCell<Machine>,
+}
+
+impl<'a> MachineDelay<'a> {
+    pub fn new(machine: &'a RefCell<Machine>) -> Self {
+        Self { machine }
+    }
+
+    fn delay(&mut self, duration: Duration) {
+        if let Err(e) = self.machine.borrow_mut().delay(duration) {
+            panic!("Remote delay failed: {}", e);
+        }
+    }
+}
+
+impl DelayNs for MachineDelay<'_> {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay(Duration::from_nanos(ns.into()));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        self.delay(Duration::from_micros(us.into()));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay(Duration::from_millis(ms.into()));
+    }
+}
+
+macro_rules! impl_delays {
+    ($($ty:ty),*) => {
+        $(
+            impl DelayUs<$ty> for MachineDelay<'_> {
+                fn delay_us(&mut self, us: $ty) {
+                    self.delay(Duration::from_micros(us.into()));
+                }
+            }
+
+            impl DelayMs<$ty> for MachineDelay<'_> {
+                fn delay_ms(&mut self, ms: $ty) {
+                    self.delay(Duration::from_millis(ms.into()));
+                }
+            }
+        )*
+    };
+}
+
+impl_delays!(u8, u16, u32);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use phm_icd::{ToMcu, ToPc};
+
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn delays_are_rounded_up_and_split() {
+        let delays = Arc::new(Mutex::new(Vec::new()));
+        let log = delays.clone();
+        let machine = RefCell::new(mock::machine(move |cmd| match cmd {
+            ToMcu::Delay { us } => {
+                log.lock().unwrap().push(us);
+                Ok(ToPc::DelayComplete)
+            }
+            _ => Err(phm_icd::Error::Unsupported),
+        }));
+        let mut delay = MachineDelay::new(&machine);
+
+        delay.delay_ns(1);
+        delay.delay_ns(1_500);
+        DelayUs::delay_us(&mut delay, 3u32);
+        DelayMs::delay_ms(&mut delay, 1_200u16);
+        assert_eq!(
+            *delays.lock().unwrap(),
+            [1, 2, 3, MAX_DELAY_US, MAX_DELAY_US, 200_000]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! The I2C buses of a [Machine]
+
+use embedded_hal::blocking::i2c::{Read, TenBitAddress, Write, WriteRead};
+use phm_icd::{AddressMode, I2cBusStatus, ToMcu, ToMcuI2c, ToPc, ToPcI2c};
+
+use crate::{copy_response, len_to_u32, output, Error, Machine};
+
+/// One of the I2C buses of a [Machine], see [Machine::i2c].
+///
+/// The `embedded-hal` I2C traits of [Machine] itself use the first bus. A write
+/// is at most 64 bytes, longer ones fail with [Error::InvalidParameter], as a
+/// transfer can't be split without a STOP or repeated START in between.
+///
+/// ```no_run
+/// # fn demo(machine: &mut phm::Machine) -> Result<(), phm::Error> {
+/// use embedded_hal::blocking::i2c::WriteRead;
+///
+/// let mut id = [0u8; 1];
+/// machine.i2c(1).write_read(0x76u8, &[0xD0], &mut id)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct I2cBus<'a> {
+    machine: &'a mut Machine,
+    bus: u8,
+}
+
+impl<'a> I2cBus<'a> {
+    pub(crate) fn new(machine: &'a mut Machine, bus: u8) -> Self {
+        Self { machine, bus }
+    }
+
+    /// Read the levels of the bus lines.
+    pub fn status(&mut self) -> Result<I2cBusStatus, Error> {
+        self.command(ToMcuI2c::BusStatus, |msg| match msg {
+            ToPcI2c::BusStatus(status) => Some(status),
+            _ => None,
+        })
+    }
+
+    /// Free the bus when held by a device, e.g. after an aborted transfer.
+    ///
+    /// The worker clocks SCL until the device releases SDA, then sends a STOP. Returns
+    /// the levels of the bus lines afterwards, both are high if the bus was freed.
+    pub fn recover(&mut self) -> Result<I2cBusStatus, Error> {
+        self.command(ToMcuI2c::Recover, |msg| match msg {
+            ToPcI2c::Recovered(status) => Some(status),
+            _ => None,
+        })
+    }
+
+    fn command<T>(
+        &mut self,
+        cmd: ToMcuI2c,
+        mut response: impl FnMut(ToPcI2c) -> Option<T>,
+    ) -> Result<T, Error> {
+        let msg = ToMcu::I2c { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::I2c(msg) => response(msg),
+            _ => None,
+        })
+    }
+
+    fn i2c_write(&mut self, address: u16, mode: AddressMode, bytes: &[u8]) -> Result<(), Error> {
+        let cmd = ToMcuI2c::Write {
+            addr: address,
+            mode,
+            output: output(bytes)?,
+        };
+        self.command(cmd, |msg| match msg {
+            ToPcI2c::WriteComplete { addr } if addr == address => Some(()),
+            _ => None,
+        })
+    }
+
+    fn i2c_read(
+        &mut self,
+        address: u16,
+        mode: AddressMode,
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let cmd = ToMcuI2c::Read {
+            addr: address,
+            mode,
+            to_read: len_to_u32(buffer.len())?,
+        };
+        let data_read = self.command(cmd, |msg| match msg {
+            ToPcI2c::Read { addr, data_read } if addr == address => Some(data_read),
+            _ => None,
+        })?;
+        copy_response(buffer, &data_read)
+    }
+
+    fn i2c_write_read(
+        &mut self,
+        address: u16,
+        mode: AddressMode,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Error> {
+        let cmd = ToMcuI2c::WriteThenRead {
+            addr: address,
+            mode,
+            output: output(bytes)?,
+            to_read: len_to_u32(buffer.len())?,
+        };
+        let data_read = self.command(cmd, |msg| match msg {
+            ToPcI2c::WriteThenRead { addr, data_read } if addr == address => Some(data_read),
+            _ => None,
+        })?;
+        copy_response(buffer, &data_read)
+    }
+}
+
+impl Write for I2cBus<'_> {
+    type Error = Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c_write(address.into(), AddressMode::SevenBit, bytes)
+    }
+}
+
+impl Read for I2cBus<'_> {
+    type Error = Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c_read(address.into(), AddressMode::SevenBit, buffer)
+    }
+}
+
+impl WriteRead for I2cBus<'_> {
+    type Error = Error;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c_write_read(address.into(), AddressMode::SevenBit, bytes, buffer)
+    }
+}
+
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl Write<TenBitAddress> for I2cBus<'_> {
+    type Error = Error;
+
+    fn write(&mut self, address: u16, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c_write(address, AddressMode::TenBit, bytes)
+    }
+}
+
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl Read<TenBitAddress> for I2cBus<'_> {
+    type Error = Error;
+
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c_read(address, AddressMode::TenBit, buffer)
+    }
+}
+
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl WriteRead<TenBitAddress> for I2cBus<'_> {
+    type Error = Error;
+
+    fn write_read(&mut self, address: u16, bytes: &[u8], buffer: &mut [u8]) -> Result<(), Error> {
+        self.i2c_write_read(address, AddressMode::TenBit, bytes, buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::blocking::i2c::{Write, WriteRead};
+    use phm_icd::{ToMcu, ToMcuI2c, ToPc, ToPcI2c};
+
+    use crate::{mock, Error};
+
+    fn machine() -> crate::Machine {
+        mock::machine(|cmd| match cmd {
+            ToMcu::I2c {
+                cmd: ToMcuI2c::Write { addr, .. },
+                ..
+            } => Ok(ToPc::I2c(ToPcI2c::WriteComplete { addr })),
+            ToMcu::I2c {
+                cmd: ToMcuI2c::WriteThenRead { addr, to_read, .. },
+                ..
+            } => Ok(ToPc::I2c(ToPcI2c::WriteThenRead {
+                addr,
+                data_read: (0..to_read as u8).collect(),
+            })),
+            _ => Err(phm_icd::Error::Unsupported),
+        })
+    }
+
+    #[test]
+    fn writes_up_to_64_bytes() {
+        let mut machine = machine();
+        let mut bus = machine.i2c(0);
+        assert!(bus.write(0x50u8, &[0xAA; 64]).is_ok());
+        let mut buf = [0; 2];
+        assert!(bus.write_read(0x50u8, &[0xAA; 64], &mut buf).is_ok());
+        assert_eq!(buf, [0, 1]);
+    }
+
+    #[test]
+    fn longer_writes_are_rejected() {
+        let mut machine = machine();
+        let mut bus = machine.i2c(0);
+        assert!(matches!(
+            bus.write(0x50u8, &[0xAA; 65]),
+            Err(Error::InvalidParameter)
+        ));
+        assert!(matches!(
+            bus.write_read(0x50u8, &[0xAA; 65], &mut [0; 1]),
+            Err(Error::InvalidParameter)
+        ));
+    }
+}
@@ -1,8 +1,26 @@
-use phm_icd::{ToMcu, ToMcuI2c, ToMcuSpi, ToMcuUart, ToPc, ToPcI2c, ToPcSpi, ToPcUart};
+pub mod adc;
+pub mod custom;
+pub mod delay;
+pub mod i2c;
+#[cfg(test)]
+mod mock;
+pub mod pulse;
+pub mod pwm;
+pub mod register;
+pub mod smbus;
+pub mod spi;
+pub mod uart;
+
+use embedded_hal::blocking::i2c::TenBitAddress;
+use embedded_hal_1::spi::Operation;
+pub use phm_icd::{
+    AdcChannel, Capabilities, I2cBusStatus, PulseStats, PulseTimes, PwmState, Stats,
+};
+use phm_icd::{Request, Response, ToMcu, ToPc};
 use postcard::{to_stdvec_cobs, CobsAccumulator, FeedResult};
 use serialport::SerialPort;
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
     fmt::Display,
     io::{self, ErrorKind},
     time::{Duration, Instant},
@@ -16,7 +34,12 @@ pub struct Machine {
     port: Box<dyn SerialPort>,
     cobs_buf: CobsAccumulator<512>,
     command_timeout: Duration,
-    uart_rx_buf: VecDeque<u8>,
+    /// The bytes received by each UART, but not read yet.
+    uart_rx_bufs: HashMap<u8, VecDeque<u8>>,
+    /// The free slots in the command queue of the worker, as of its last response.
+    credits: usize,
+    /// The `seq` of the next command sent to the worker.
+    next_seq: u16,
 }
 
 /// The main Error type
@@ -25,6 +48,15 @@ pub enum Error {
     PhmSerial(io::Error),
     Postcard(postcard::Error),
     Timeout(Duration),
+    /// The worker gave up on the command at its own deadline, see
+    /// [Machine::set_command_timeout].
+    WorkerTimeout,
+    /// The worker doesn't have the interface, bus or feature used by the command,
+    /// see [Machine::capabilities].
+    Unsupported,
+    /// The command queue of the worker was full, and the command was dropped without
+    /// being executed. It can be sent again.
+    Busy,
 
     // TODO: This probably needs some more context/nuance...
     ResponseError,
@@ -56,6 +88,15 @@ impl Display for Error {
             Error::Timeout(d) => {
                 write!(f, "Timeout({:?})", d)
             }
+            Error::WorkerTimeout => {
+                write!(f, "WorkerTimeout")
+            }
+            Error::Unsupported => {
+                write!(f, "Unsupported")
+            }
+            Error::Busy => {
+                write!(f, "Busy")
+            }
             Error::ResponseError => {
                 write!(f, "ResponseError")
             }
@@ -69,8 +110,31 @@ impl Display for Error {
     }
 }
 
+impl From<phm_icd::Error> for Error {
+    fn from(err: phm_icd::Error) -> Self {
+        match err {
+            phm_icd::Error::Failed => Error::ResponseError,
+            phm_icd::Error::Timeout => Error::WorkerTimeout,
+            phm_icd::Error::Unsupported => Error::Unsupported,
+            phm_icd::Error::Busy => Error::Busy,
+        }
+    }
+}
+
 impl std::error::Error for Error {}
 
+impl embedded_hal_1::spi::Error for Error {
+    fn kind(&self) -> embedded_hal_1::spi::ErrorKind {
+        embedded_hal_1::spi::ErrorKind::Other
+    }
+}
+
+impl embedded_hal_1::pwm::Error for Error {
+    fn kind(&self) -> embedded_hal_1::pwm::ErrorKind {
+        embedded_hal_1::pwm::ErrorKind::Other
+    }
+}
+
 impl Machine {
     pub fn from_port(port: Box<dyn SerialPort>) -> Result<Self, Error> {
         // TODO: some kind of sanity checking? Check version, protocol,
@@ -79,42 +143,224 @@ impl Machine {
             port,
             cobs_buf: CobsAccumulator::new(),
             command_timeout: Duration::from_secs(3),
-            uart_rx_buf: Default::default(),
+            uart_rx_bufs: Default::default(),
+            // Until the worker advertises its queue, send one command at a time
+            credits: 1,
+            next_seq: 0,
         })
     }
 
     /// Set the timeout for a full command to complete.
     ///
     /// This is not a single message timeout, but rather the timeout
-    /// for a whole command (e.g. an I2C write) to execute. This is currently
-    /// only checked/set host side, so endless loops on the embedded side are
-    /// still possible.
+    /// for a whole command (e.g. an I2C write) to execute.
+    ///
+    /// The worker enforces its own deadline on every command, one second by
+    /// default, after which it aborts the command and reports
+    /// [Error::WorkerTimeout]. Commands that take longer on the worker need a
+    /// worker built with a longer deadline, as well as a longer timeout here.
+    /// Some workers can't abort a driver call that is stuck, e.g. on an I2C
+    /// device stretching the clock forever, see [Capabilities::deadline_abort].
     pub fn set_command_timeout(&mut self, timeout: Duration) {
         self.command_timeout = timeout;
     }
 
-    fn poll(&mut self) -> Result<Vec<ToPc>, Error> {
+    /// Ask the worker which features it supports, such as the SPI word sizes.
+    pub fn capabilities(&mut self) -> Result<Capabilities, Error> {
+        self.command(&ToMcu::GetCapabilities, |msg| match msg {
+            ToPc::Capabilities(capabilities) => Some(capabilities),
+            _ => None,
+        })
+    }
+
+    /// Read the counters of the worker, such as dropped messages and bus errors.
+    ///
+    /// The counters start at zero when the worker boots, and wrap around.
+    pub fn stats(&mut self) -> Result<Stats, Error> {
+        self.command(&ToMcu::GetStats, |msg| match msg {
+            ToPc::Stats(stats) => Some(stats),
+            _ => None,
+        })
+    }
+
+    /// Reset the counters of the worker, returning their values before the reset.
+    pub fn reset_stats(&mut self) -> Result<Stats, Error> {
+        self.command(&ToMcu::ResetStats, |msg| match msg {
+            ToPc::Stats(stats) => Some(stats),
+            _ => None,
+        })
+    }
+
+    /// The I2C bus with index `bus`, see [Capabilities::i2c_buses].
+    pub fn i2c(&mut self, bus: u8) -> i2c::I2cBus<'_> {
+        i2c::I2cBus::new(self, bus)
+    }
+
+    /// The SPI bus with index `bus`, see [Capabilities::spi_buses].
+    pub fn spi(&mut self, bus: u8) -> spi::SpiBus<'_> {
+        spi::SpiBus::new(self, bus)
+    }
+
+    /// The UART with index `bus`, see [Capabilities::uart_buses].
+    pub fn uart(&mut self, bus: u8) -> uart::UartBus<'_> {
+        uart::UartBus::new(self, bus)
+    }
+
+    /// Read the levels of the lines of the first I2C bus.
+    pub fn i2c_bus_status(&mut self) -> Result<I2cBusStatus, Error> {
+        self.i2c(0).status()
+    }
+
+    /// Free the first I2C bus when held by a device, see [i2c::I2cBus::recover].
+    pub fn i2c_recover(&mut self) -> Result<I2cBusStatus, Error> {
+        self.i2c(0).recover()
+    }
+
+    /// Wait for at least `duration`, timed by the worker, rounded up to whole
+    /// microseconds.
+    ///
+    /// Each delay is a round trip to the worker of its own, so the gap between the
+    /// commands before and after is stretched by the time it takes to exchange a
+    /// message, and is at least as long as `duration`. Only delays within an SPI
+    /// transaction are sent along with its other operations. Long delays are split
+    /// into several commands, to stay within the deadline of the worker.
+    pub fn delay(&mut self, duration: Duration) -> Result<(), Error> {
+        let mut remaining = duration.as_nanos().div_ceil(1000);
+        while remaining > 0 {
+            let us = remaining.min(delay::MAX_DELAY_US.into());
+            self.command(&ToMcu::Delay { us: us as u32 }, |msg| match msg {
+                ToPc::DelayComplete => Some(()),
+                _ => None,
+            })?;
+            remaining -= us;
+        }
+        Ok(())
+    }
+
+    /// Assert the SPI chip select line `cs` of the worker, until [Machine::spi_release]
+    /// is called.
+    ///
+    /// See [SpiDevice](crate::spi::SpiDevice) for a handle that does this around each transaction.
+    pub fn spi_select(&mut self, cs: u8) -> Result<(), Error> {
+        self.spi(0).select(cs)
+    }
+
+    /// Deassert all SPI chip select lines of the worker.
+    pub fn spi_release(&mut self) -> Result<(), Error> {
+        self.spi(0).release()
+    }
+
+    /// Execute SPI operations on the first SPI bus as a single transaction, see
+    /// [spi::SpiBus::transaction].
+    pub fn spi_transaction(
+        &mut self,
+        cs: Option<u8>,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Error> {
+        self.spi(0).transaction(cs, operations)
+    }
+
+    /// Send a command to the worker, and wait for the response picked out by `response`.
+    fn command<T>(
+        &mut self,
+        msg: &ToMcu,
+        response: impl FnMut(ToPc) -> Option<T>,
+    ) -> Result<T, Error> {
+        let mut values = self.commands(std::slice::from_ref(msg), response)?;
+        values.pop().ok_or(Error::ResponseError)
+    }
+
+    /// Send several commands to the worker, and collect the response to each, picked
+    /// out by `response`.
+    ///
+    /// Commands are sent while the worker has room for them in its queue, so several
+    /// can be in flight at once. Responses are matched to their command by `seq`, so
+    /// late responses to commands that timed out before are dropped. After an error
+    /// no more commands are sent, and the error of the first failed command is
+    /// returned once the commands in flight are answered.
+    fn commands<T>(
+        &mut self,
+        msgs: &[ToMcu],
+        mut response: impl FnMut(ToPc) -> Option<T>,
+    ) -> Result<Vec<T>, Error> {
+        let first_seq = self.next_seq;
+        let mut results: Vec<Option<Result<T, Error>>> = Vec::with_capacity(msgs.len());
+        let mut failed = false;
+        let mut answered = 0;
+        let mut start = Instant::now();
+
+        loop {
+            while !failed && results.len() < msgs.len() && self.has_credit(results.len() - answered)
+            {
+                let request = Request {
+                    seq: self.next_seq,
+                    cmd: &msgs[results.len()],
+                };
+                let ser_msg = to_stdvec_cobs(&request)?;
+                self.port.write_all(&ser_msg)?;
+                self.next_seq = self.next_seq.wrapping_add(1);
+                results.push(None);
+            }
+
+            if answered == results.len() && (failed || results.len() == msgs.len()) {
+                break;
+            }
+
+            if start.elapsed() >= self.command_timeout {
+                return Err(Error::Timeout(self.command_timeout));
+            }
+
+            let responses = self.poll()?;
+            if responses.is_empty() {
+                // TODO: We should probably just use the `timeout` value of the serial
+                // port, (e.g. don't delay at all), but I guess this is fine for now.
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            for Response { seq, result, .. } in responses {
+                let slot = match results.get_mut(usize::from(seq.wrapping_sub(first_seq))) {
+                    Some(slot @ None) => slot,
+                    // Not for us, e.g. the late response to a command that timed out
+                    _ => continue,
+                };
+                let result = match result {
+                    Ok(msg) => response(msg).ok_or(Error::ResponseError),
+                    Err(err) => Err(err.into()),
+                };
+                failed |= result.is_err();
+                *slot = Some(result);
+                answered += 1;
+                start = Instant::now();
+            }
+        }
+
+        results.into_iter().flatten().collect()
+    }
+
+    /// Whether another command may be sent with `in_flight` commands unanswered,
+    /// without overflowing the queue of the worker. A single command is always
+    /// allowed, the worker rejects it with [Error::Busy] if its queue is still full.
+    fn has_credit(&self, in_flight: usize) -> bool {
+        in_flight == 0 || in_flight < self.credits
+    }
+
+    fn poll(&mut self) -> Result<Vec<Response>, Error> {
         let mut responses = vec![];
         let mut buf = [0u8; 1024];
 
         // read from stdin and push it to the decoder
         match self.port.read(&mut buf) {
             Ok(n) if n > 0 => {
-                let buf = &buf[..n];
-                let mut window = &buf[..];
+                let mut window = &buf[..n];
 
                 'cobs: while !window.is_empty() {
-                    window = match self.cobs_buf.feed::<Result<phm_icd::ToPc, ()>>(&window) {
+                    window = match self.cobs_buf.feed::<Response>(window) {
                         FeedResult::Consumed => break 'cobs,
                         FeedResult::OverFull(new_wind) => new_wind,
                         FeedResult::DeserError(new_wind) => new_wind,
                         FeedResult::Success { data, remaining } => {
-                            // Do something with `data: MyData` here.
-                            if let Ok(data) = data {
-                                responses.push(data);
-                            } else {
-                                return Err(Error::ResponseError);
-                            }
+                            self.credits = data.credits.into();
+                            responses.push(data);
 
                             remaining
                         }
@@ -135,31 +381,7 @@ impl embedded_hal::blocking::i2c::Write for Machine {
     type Error = Error;
 
     fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
-        let msg = ToMcu::I2c(ToMcuI2c::Write {
-            addr: address,
-            output: bytes.iter().cloned().collect(),
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::I2c(ToPcI2c::WriteComplete { addr }) = msg {
-                    if address != addr {
-                        continue;
-                    }
-                    return Ok(());
-                }
-            }
-
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
-
-        Err(Error::Timeout(self.command_timeout))
+        self.i2c(0).write(address, bytes)
     }
 }
 
@@ -167,37 +389,7 @@ impl embedded_hal::blocking::i2c::Read for Machine {
     type Error = Error;
 
     fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
-        let msg = ToMcu::I2c(ToMcuI2c::Read {
-            addr: address,
-            to_read: len_to_u32(buffer.len())?,
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::I2c(ToPcI2c::Read { addr, data_read }) = msg {
-                    if address != addr {
-                        continue;
-                    }
-
-                    if data_read.len() != buffer.len() {
-                        return Err(Error::ResponseError);
-                    } else {
-                        buffer.copy_from_slice(&data_read);
-                        return Ok(());
-                    }
-                }
-            }
-
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
-
-        Err(Error::Timeout(self.command_timeout))
+        self.i2c(0).read(address, buffer)
     }
 }
 
@@ -210,66 +402,47 @@ impl embedded_hal::blocking::i2c::WriteRead for Machine {
         bytes: &[u8],
         buffer: &mut [u8],
     ) -> Result<(), Self::Error> {
-        let msg = ToMcu::I2c(ToMcuI2c::WriteThenRead {
-            addr: address,
-            output: bytes.iter().cloned().collect(),
-            to_read: len_to_u32(buffer.len())?,
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::I2c(ToPcI2c::WriteThenRead { addr, data_read }) = msg {
-                    if address != addr {
-                        continue;
-                    }
-
-                    if data_read.len() != buffer.len() {
-                        return Err(Error::ResponseError);
-                    } else {
-                        buffer.copy_from_slice(&data_read);
-                        return Ok(());
-                    }
-                }
-            }
+        self.i2c(0).write_read(address, bytes, buffer)
+    }
+}
 
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl embedded_hal::blocking::i2c::Write<TenBitAddress> for Machine {
+    type Error = Error;
 
-        Err(Error::Timeout(self.command_timeout))
+    fn write(&mut self, address: u16, bytes: &[u8]) -> Result<(), Error> {
+        self.i2c(0).write(address, bytes)
     }
 }
 
-impl embedded_hal::blocking::spi::Write<u8> for Machine {
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl embedded_hal::blocking::i2c::Read<TenBitAddress> for Machine {
     type Error = Error;
 
-    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
-        let msg = ToMcu::Spi(ToMcuSpi::Write {
-            output: bytes.iter().cloned().collect(),
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::Spi(ToPcSpi::WriteComplete) = msg {
-                    return Ok(());
-                }
-            }
+    fn read(&mut self, address: u16, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c(0).read(address, buffer)
+    }
+}
 
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
+/// 10-bit addresses are only supported by some workers, see [Machine::capabilities].
+impl embedded_hal::blocking::i2c::WriteRead<TenBitAddress> for Machine {
+    type Error = Error;
+
+    fn write_read(
+        &mut self,
+        address: u16,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.i2c(0).write_read(address, bytes, buffer)
+    }
+}
 
-        Err(Error::Timeout(self.command_timeout))
+impl embedded_hal::blocking::spi::Write<u8> for Machine {
+    type Error = Error;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.spi(0).write(bytes)
     }
 }
 
@@ -277,82 +450,37 @@ impl embedded_hal::blocking::spi::Transfer<u8> for Machine {
     type Error = Error;
 
     fn transfer<'a>(&mut self, buffer: &'a mut [u8]) -> Result<&'a [u8], Self::Error> {
-        let msg = ToMcu::Spi(ToMcuSpi::Transfer {
-            output: buffer.iter().cloned().collect(),
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::Spi(ToPcSpi::Transfer { data_read }) = msg {
-                    if data_read.len() != buffer.len() {
-                        return Err(Error::ResponseError);
-                    } else {
-                        buffer.copy_from_slice(&data_read);
-                        return Ok(buffer);
-                    }
-                }
-            }
+        self.spi(0).transfer(buffer)
+    }
+}
 
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
+/// 16-bit words are only supported by some workers, see [Machine::capabilities].
+impl embedded_hal::blocking::spi::Write<u16> for Machine {
+    type Error = Error;
 
-        Err(Error::Timeout(self.command_timeout))
+    fn write(&mut self, words: &[u16]) -> Result<(), Error> {
+        self.spi(0).write(words)
     }
 }
 
-impl embedded_hal::blocking::serial::Write<u8> for Machine {
+/// 16-bit words are only supported by some workers, see [Machine::capabilities].
+impl embedded_hal::blocking::spi::Transfer<u16> for Machine {
     type Error = Error;
 
-    fn bwrite_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
-        let msg = ToMcu::Uart(ToMcuUart::Write {
-            output: bytes.iter().cloned().collect(),
-        });
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::Uart(ToPcUart::WriteComplete) = msg {
-                    return Ok(());
-                }
-            }
+    fn transfer<'a>(&mut self, words: &'a mut [u16]) -> Result<&'a [u16], Self::Error> {
+        self.spi(0).transfer(words)
+    }
+}
 
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
+impl embedded_hal::blocking::serial::Write<u8> for Machine {
+    type Error = Error;
 
-        Err(Error::Timeout(self.command_timeout))
+    fn bwrite_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.uart(0).bwrite_all(bytes)
     }
 
     fn bflush(&mut self) -> Result<(), Self::Error> {
-        let msg = ToMcu::Uart(ToMcuUart::Flush);
-        let ser_msg = to_stdvec_cobs(&msg)?;
-        self.port.write_all(&ser_msg)?;
-
-        let start = Instant::now();
-
-        while start.elapsed() < self.command_timeout {
-            for msg in self.poll()? {
-                if let ToPc::Uart(ToPcUart::WriteComplete) = msg {
-                    return Ok(());
-                }
-            }
-
-            // TODO: We should probably just use the `timeout` value of the serial
-            // port, (e.g. don't delay at all), but I guess this is fine for now.
-            std::thread::sleep(Duration::from_millis(10));
-        }
-
-        Err(Error::Timeout(self.command_timeout))
+        self.uart(0).bflush()
     }
 }
 
@@ -360,12 +488,11 @@ impl embedded_hal::serial::Write<u8> for Machine {
     type Error = Error;
 
     fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
-        embedded_hal::blocking::serial::Write::<u8>::bwrite_all(self, &[byte])
-            .map_err(|_| nb::Error::WouldBlock)
+        embedded_hal::serial::Write::write(&mut self.uart(0), byte)
     }
 
     fn flush(&mut self) -> nb::Result<(), Self::Error> {
-        embedded_hal::blocking::serial::Write::<u8>::bflush(self).map_err(|_| nb::Error::WouldBlock)
+        embedded_hal::serial::Write::flush(&mut self.uart(0))
     }
 }
 
@@ -373,36 +500,7 @@ impl embedded_hal::serial::Read<u8> for Machine {
     type Error = Error;
 
     fn read(&mut self) -> nb::Result<u8, Self::Error> {
-        if !self.uart_rx_buf.is_empty() {
-            Ok(self.uart_rx_buf.pop_front().unwrap())
-        } else {
-            let msg = ToMcu::Uart(ToMcuUart::Read);
-            let ser_msg = to_stdvec_cobs(&msg).unwrap();
-            self.port.write_all(&ser_msg).unwrap();
-
-            let start = Instant::now();
-
-            while start.elapsed() < self.command_timeout {
-                if let Ok(vec) = self.poll() {
-                    for msg in vec {
-                        if let ToPc::Uart(ToPcUart::Read { data_read }) = msg {
-                            self.uart_rx_buf.extend(data_read);
-                            // break 'timeout;
-                            if !self.uart_rx_buf.is_empty() {
-                                return Ok(self.uart_rx_buf.pop_front().unwrap());
-                            } else {
-                                return Err(nb::Error::WouldBlock);
-                            }
-                        }
-                    }
-                }
-
-                // TODO: We should probably just use the `timeout` value of the serial
-                // port, (e.g. don't delay at all), but I guess this is fine for now.
-                std::thread::sleep(Duration::from_millis(10));
-            }
-            Err(nb::Error::Other(Error::Timeout(self.command_timeout)))
-        }
+        self.uart(0).read()
     }
 }
 
@@ -410,3 +508,17 @@ impl embedded_hal::serial::Read<u8> for Machine {
 fn len_to_u32(len: usize) -> Result<u32, Error> {
     len.try_into().map_err(|_| Error::InvalidParameter)
 }
+
+/// The bytes written by a single command, which are at most 64 bytes.
+fn output(bytes: &[u8]) -> Result<heapless::Vec<u8, 64>, Error> {
+    bytes.try_into().map_err(|_| Error::InvalidParameter)
+}
+
+/// Copy the data read by the worker into `buffer`, which must be the same length.
+fn copy_response(buffer: &mut [u8], data_read: &[u8]) -> Result<(), Error> {
+    if data_read.len() != buffer.len() {
+        return Err(Error::ResponseError);
+    }
+    buffer.copy_from_slice(data_read);
+    Ok(())
+}
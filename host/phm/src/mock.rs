@@ -0,0 +1,149 @@
+//! A stand-in for a worker, answering each command from a test
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use phm_icd::{Error as IcdError, Request, Response, ToMcu, ToPc};
+use postcard::{to_stdvec_cobs, CobsAccumulator, FeedResult};
+use serialport::{ClearBuffer, DataBits, FlowControl, Parity, SerialPort, StopBits};
+
+use crate::Machine;
+
+type Answer = Box<dyn FnMut(ToMcu) -> Result<ToPc, IcdError> + Send>;
+
+/// A serial port that answers each command with `answer`, as soon as it is sent.
+pub struct MockPort {
+    cobs: CobsAccumulator<512>,
+    out: VecDeque<u8>,
+    answer: Answer,
+}
+
+/// A machine connected to a [MockPort].
+pub fn machine(answer: impl FnMut(ToMcu) -> Result<ToPc, IcdError> + Send + 'static) -> Machine {
+    let port = MockPort {
+        cobs: CobsAccumulator::new(),
+        out: VecDeque::new(),
+        answer: Box::new(answer),
+    };
+    Machine::from_port(Box::new(port)).unwrap()
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.out.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let n = buf.len().min(self.out.len());
+        for (b, out) in buf.iter_mut().zip(self.out.drain(..n)) {
+            *b = out;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut window = buf;
+        while !window.is_empty() {
+            window = match self.cobs.feed::<Request>(window) {
+                FeedResult::Consumed => break,
+                FeedResult::OverFull(rest) | FeedResult::DeserError(rest) => rest,
+                FeedResult::Success { data, remaining } => {
+                    let response = Response {
+                        seq: data.seq,
+                        credits: 8,
+                        result: (self.answer)(data.cmd),
+                    };
+                    self.out.extend(to_stdvec_cobs(&response).unwrap());
+                    remaining
+                }
+            };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SerialPort for MockPort {
+    fn name(&self) -> Option<String> {
+        None
+    }
+    fn baud_rate(&self) -> serialport::Result<u32> {
+        Ok(115200)
+    }
+    fn data_bits(&self) -> serialport::Result<DataBits> {
+        Ok(DataBits::Eight)
+    }
+    fn flow_control(&self) -> serialport::Result<FlowControl> {
+        Ok(FlowControl::None)
+    }
+    fn parity(&self) -> serialport::Result<Parity> {
+        Ok(Parity::None)
+    }
+    fn stop_bits(&self) -> serialport::Result<StopBits> {
+        Ok(StopBits::One)
+    }
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+    fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn write_request_to_send(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn write_data_terminal_ready(&mut self, _: bool) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
+        Ok(true)
+    }
+    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
+        Ok(false)
+    }
+    fn bytes_to_read(&self) -> serialport::Result<u32> {
+        Ok(self.out.len() as u32)
+    }
+    fn bytes_to_write(&self) -> serialport::Result<u32> {
+        Ok(0)
+    }
+    fn clear(&self, _: ClearBuffer) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
+        unimplemented!("a mock port can't be cloned")
+    }
+    fn set_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+    fn clear_break(&self) -> serialport::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+//! Pulse and frequency measurement on the inputs of the worker
+
+use std::time::Duration;
+
+use phm_icd::{PulseStats, PulseTimes, ToMcu, ToPc};
+
+use crate::{Error, Machine};
+
+/// The result of [Machine::measure_pulse].
+///
+/// The worker polls the input and times its edges to about a microsecond, so the
+/// measurements are only accurate for signals up to a few kHz.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulseMeasurement {
+    /// The pulses as counted and timed by the worker.
+    pub stats: PulseStats,
+}
+
+impl PulseMeasurement {
+    /// The mean frequency, from the periods seen during the gate time.
+    pub fn frequency_hz(&self) -> Option<f64> {
+        mean_us(&self.stats.period).map(|us| 1_000_000.0 / us)
+    }
+
+    /// The frequency from the number of rising edges during the gate time, which
+    /// doesn't depend on the timing of the edges.
+    pub fn edge_rate_hz(&self) -> f64 {
+        f64::from(self.stats.rising_edges) * 1_000_000.0 / f64::from(self.stats.gate_us.max(1))
+    }
+
+    /// The mean duty cycle, from 0.0 to 1.0.
+    pub fn duty_cycle(&self) -> Option<f64> {
+        let high = mean_us(&self.stats.high)?;
+        let period = mean_us(&self.stats.period)?;
+        Some((high / period).clamp(0.0, 1.0))
+    }
+
+    /// The mean period.
+    pub fn period(&self) -> Option<Duration> {
+        mean_us(&self.stats.period).map(|us| Duration::from_secs_f64(us / 1_000_000.0))
+    }
+
+    /// The mean width of the high pulses.
+    pub fn pulse_width(&self) -> Option<Duration> {
+        mean_us(&self.stats.high).map(|us| Duration::from_secs_f64(us / 1_000_000.0))
+    }
+}
+
+fn mean_us(times: &PulseTimes) -> Option<f64> {
+    match times.count {
+        0 => None,
+        n => Some(f64::from(times.total_us) / f64::from(n)),
+    }
+}
+
+impl Machine {
+    /// Count and time the pulses on a pulse input of the worker for `gate`.
+    ///
+    /// The gate time must be shorter than the worker's command deadline, see
+    /// [Machine::set_command_timeout].
+    pub fn measure_pulse(&mut self, input: u8, gate: Duration) -> Result<PulseMeasurement, Error> {
+        let gate_us = gate
+            .as_micros()
+            .try_into()
+            .map_err(|_| Error::InvalidParameter)?;
+        let stats = self.command(&ToMcu::MeasurePulse { input, gate_us }, |msg| match msg {
+            ToPc::Pulse(stats) if stats.input == input => Some(stats),
+            _ => None,
+        })?;
+        Ok(PulseMeasurement { stats })
+    }
+}
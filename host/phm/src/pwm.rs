@@ -0,0 +1,125 @@
+//! PWM outputs of the worker
+
+use std::cell::RefCell;
+
+use embedded_hal::PwmPin;
+use embedded_hal_1::pwm::{ErrorType, SetDutyCycle};
+use phm_icd::{PwmState, ToMcu, ToMcuPwm, ToPc, ToPcPwm};
+
+use crate::{Error, Machine};
+
+impl Machine {
+    /// Set the frequency of a PWM channel, as closely as the worker can. Channels
+    /// sharing a timer with `channel` change their frequency too.
+    ///
+    /// Returns the state of the channel, with the frequency actually generated.
+    pub fn pwm_set_frequency(&mut self, channel: u8, hz: u32) -> Result<PwmState, Error> {
+        self.pwm_command(ToMcuPwm::SetFrequency { channel, hz })
+    }
+
+    /// Set the duty cycle of a PWM channel, as a fraction of `u16::MAX`.
+    pub fn pwm_set_duty(&mut self, channel: u8, duty: u16) -> Result<PwmState, Error> {
+        self.pwm_command(ToMcuPwm::SetDuty { channel, duty })
+    }
+
+    /// Start or stop the output of a PWM channel.
+    pub fn pwm_set_enabled(&mut self, channel: u8, enabled: bool) -> Result<PwmState, Error> {
+        self.pwm_command(ToMcuPwm::SetEnabled { channel, enabled })
+    }
+
+    /// The state of a PWM channel.
+    pub fn pwm_state(&mut self, channel: u8) -> Result<PwmState, Error> {
+        self.pwm_command(ToMcuPwm::GetState { channel })
+    }
+
+    fn pwm_command(&mut self, cmd: ToMcuPwm) -> Result<PwmState, Error> {
+        let channel = match cmd {
+            ToMcuPwm::SetFrequency { channel, .. }
+            | ToMcuPwm::SetDuty { channel, .. }
+            | ToMcuPwm::SetEnabled { channel, .. }
+            | ToMcuPwm::GetState { channel } => channel,
+        };
+        self.command(&ToMcu::Pwm(cmd), |msg| match msg {
+            ToPc::Pwm(ToPcPwm::State { channel: ch, state }) if ch == channel => Some(state),
+            _ => None,
+        })
+    }
+}
+
+/// A PWM output of the worker of a [Machine], for drivers.
+///
+/// Duty cycles are fractions of `u16::MAX`. The embedded-hal 0.2 `PwmPin` trait
+/// can't report errors, so its methods panic if the worker doesn't answer.
+pub struct PwmChannel<'a> {
+    machine: &'a RefCell<Machine>,
+    channel: u8,
+}
+
+impl<'a> PwmChannel<'a> {
+    pub fn new(machine: &'a RefCell<Machine>, channel: u8) -> Self {
+        Self { machine, channel }
+    }
+
+    /// Set the frequency of the channel, see [Machine::pwm_set_frequency].
+    pub fn set_frequency(&mut self, hz: u32) -> Result<PwmState, Error> {
+        self.machine
+            .borrow_mut()
+            .pwm_set_frequency(self.channel, hz)
+    }
+
+    fn expect(&self, result: Result<PwmState, Error>) -> PwmState {
+        match result {
+            Ok(state) => state,
+            Err(e) => panic!("PWM channel {} failed: {}", self.channel, e),
+        }
+    }
+}
+
+impl PwmPin for PwmChannel<'_> {
+    type Duty = u16;
+
+    fn disable(&mut self) {
+        let result = self
+            .machine
+            .borrow_mut()
+            .pwm_set_enabled(self.channel, false);
+        self.expect(result);
+    }
+
+    fn enable(&mut self) {
+        let result = self
+            .machine
+            .borrow_mut()
+            .pwm_set_enabled(self.channel, true);
+        self.expect(result);
+    }
+
+    fn get_duty(&self) -> u16 {
+        let result = self.machine.borrow_mut().pwm_state(self.channel);
+        self.expect(result).duty
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        let result = self.machine.borrow_mut().pwm_set_duty(self.channel, duty);
+        self.expect(result);
+    }
+}
+
+impl ErrorType for PwmChannel<'_> {
+    type Error = Error;
+}
+
+impl SetDutyCycle for PwmChannel<'_> {
+    fn max_duty_cycle(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Error> {
+        self.machine.borrow_mut().pwm_set_duty(self.channel, duty)?;
+        Ok(())
+    }
+}
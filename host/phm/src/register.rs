@@ -0,0 +1,726 @@
+//! Register access helpers
+//!
+//! Most I2C and SPI devices are controlled through registers. These helpers take
+//! care of encoding register addresses and values, so driver prototypes don't need
+//! to reimplement `read_reg`/`write_reg`/`update_bits` each time.
+//!
+//! [`I2cRegisters`] and [`SpiRegisters`] only describe *how* to talk to a device.
+//! The bus is passed to each call, so they work with a [`Machine`](crate::Machine),
+//! or any other implementation of the `embedded-hal` bus traits.
+//!
+//! ```no_run
+//! use phm::register::{Field, I2cRegisters};
+//!
+//! const CTRL_MEAS: u16 = 0xF4;
+//! const OSRS_T: Field = Field::new(5, 3);
+//!
+//! # fn demo(machine: &mut phm::Machine) -> Result<(), phm::Error> {
+//! let bme280 = I2cRegisters::new(0x76);
+//! let chip_id: u8 = bme280.read(machine, 0xD0)?;
+//! bme280.write_field(machine, CTRL_MEAS, OSRS_T, 0b010u8)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use embedded_hal::blocking::{i2c, spi};
+
+/// The width of a register address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressWidth {
+    Bits8,
+    Bits16,
+}
+
+/// The byte order of multi-byte register addresses and values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// A register value: `u8`, `u16` or `u32`.
+pub trait Value: Copy {
+    /// The size of the value on the bus, in bytes.
+    const SIZE: usize;
+
+    /// Encode the value into `out`, which is exactly [`Self::SIZE`] bytes long.
+    fn to_bytes(self, endian: Endian, out: &mut [u8]);
+    /// Decode the value from `bytes`, which is exactly [`Self::SIZE`] bytes long.
+    fn from_bytes(bytes: &[u8], endian: Endian) -> Self;
+    fn to_u32(self) -> u32;
+    /// Convert from a `u32`, discarding any bits that don't fit.
+    fn from_u32(value: u32) -> Self;
+}
+
+macro_rules! impl_value {
+    ($($ty:ty),*) => {
+        $(
+            impl Value for $ty {
+                const SIZE: usize = core::mem::size_of::<$ty>();
+
+                fn to_bytes(self, endian: Endian, out: &mut [u8]) {
+                    match endian {
+                        Endian::Big => out.copy_from_slice(&self.to_be_bytes()),
+                        Endian::Little => out.copy_from_slice(&self.to_le_bytes()),
+                    }
+                }
+
+                fn from_bytes(bytes: &[u8], endian: Endian) -> Self {
+                    let mut raw = [0u8; core::mem::size_of::<$ty>()];
+                    raw.copy_from_slice(bytes);
+                    match endian {
+                        Endian::Big => <$ty>::from_be_bytes(raw),
+                        Endian::Little => <$ty>::from_le_bytes(raw),
+                    }
+                }
+
+                fn to_u32(self) -> u32 {
+                    self.into()
+                }
+
+                fn from_u32(value: u32) -> Self {
+                    value as $ty
+                }
+            }
+        )*
+    };
+}
+
+impl_value!(u8, u16, u32);
+
+/// A bitfield within a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    /// The position of the least significant bit of the field.
+    pub offset: u8,
+    /// The number of bits in the field.
+    pub width: u8,
+}
+
+impl Field {
+    pub const fn new(offset: u8, width: u8) -> Self {
+        Self { offset, width }
+    }
+
+    /// The bits of the register covered by this field. Any bits of the field
+    /// above bit 31 are left out, as registers are at most 32 bits wide.
+    pub const fn mask(&self) -> u32 {
+        let bits = match self.width {
+            0 => 0,
+            64.. => u64::MAX,
+            width => u64::MAX >> (64 - width),
+        };
+        match self.offset {
+            32.. => 0,
+            offset => (bits << offset) as u32,
+        }
+    }
+
+    /// Get the value of this field from a register value.
+    pub const fn extract(&self, register: u32) -> u32 {
+        match (register & self.mask()).checked_shr(self.offset as u32) {
+            Some(value) => value,
+            None => 0,
+        }
+    }
+
+    /// Replace the value of this field in a register value.
+    pub const fn insert(&self, register: u32, value: u32) -> u32 {
+        let shifted = match value.checked_shl(self.offset as u32) {
+            Some(shifted) => shifted,
+            None => 0,
+        };
+        (register & !self.mask()) | (shifted & self.mask())
+    }
+}
+
+/// Registers of a device on an I2C bus.
+///
+/// Defaults to 8-bit register addresses and big-endian values.
+#[derive(Debug, Clone, Copy)]
+pub struct I2cRegisters {
+    address: u8,
+    address_width: AddressWidth,
+    endian: Endian,
+}
+
+impl I2cRegisters {
+    /// Registers of the device at the given I2C address.
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            address_width: AddressWidth::Bits8,
+            endian: Endian::Big,
+        }
+    }
+
+    pub fn address_width(mut self, width: AddressWidth) -> Self {
+        self.address_width = width;
+        self
+    }
+
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Read consecutive bytes, starting at the given register.
+    ///
+    /// This relies on the device auto-incrementing the register address.
+    pub fn read_bytes<BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        buffer: &mut [u8],
+    ) -> Result<(), BUS::Error>
+    where
+        BUS: i2c::WriteRead,
+    {
+        let header = encode_address(self.address_width, self.endian, register);
+        bus.write_read(self.address, &header, buffer)
+    }
+
+    /// Write consecutive bytes, starting at the given register.
+    pub fn write_bytes<BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        bytes: &[u8],
+    ) -> Result<(), BUS::Error>
+    where
+        BUS: i2c::Write,
+    {
+        let mut out = encode_address(self.address_width, self.endian, register);
+        out.extend_from_slice(bytes);
+        bus.write(self.address, &out)
+    }
+
+    pub fn read<V, BUS>(&self, bus: &mut BUS, register: u16) -> Result<V, BUS::Error>
+    where
+        V: Value,
+        BUS: i2c::WriteRead,
+    {
+        let mut buf = [0u8; 4];
+        self.read_bytes(bus, register, &mut buf[..V::SIZE])?;
+        Ok(V::from_bytes(&buf[..V::SIZE], self.endian))
+    }
+
+    pub fn write<V, BUS>(&self, bus: &mut BUS, register: u16, value: V) -> Result<(), BUS::Error>
+    where
+        V: Value,
+        BUS: i2c::Write,
+    {
+        let mut buf = [0u8; 4];
+        value.to_bytes(self.endian, &mut buf[..V::SIZE]);
+        self.write_bytes(bus, register, &buf[..V::SIZE])
+    }
+
+    /// Replace the bits of a register selected by `mask` with those of `value`,
+    /// returning the new register value.
+    pub fn update_bits<V, BUS, E>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        mask: V,
+        value: V,
+    ) -> Result<V, E>
+    where
+        V: Value,
+        BUS: i2c::WriteRead<Error = E> + i2c::Write<Error = E>,
+    {
+        let old: V = self.read(bus, register)?;
+        let new = apply_mask(old, mask, value);
+        self.write(bus, register, new)?;
+        Ok(new)
+    }
+
+    pub fn read_field<V, BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        field: Field,
+    ) -> Result<V, BUS::Error>
+    where
+        V: Value,
+        BUS: i2c::WriteRead,
+    {
+        let value: V = self.read(bus, register)?;
+        Ok(V::from_u32(field.extract(value.to_u32())))
+    }
+
+    /// Set a single field of a register, keeping the other bits. Returns the
+    /// new register value.
+    pub fn write_field<V, BUS, E>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        field: Field,
+        value: V,
+    ) -> Result<V, E>
+    where
+        V: Value,
+        BUS: i2c::WriteRead<Error = E> + i2c::Write<Error = E>,
+    {
+        let mask = V::from_u32(field.mask());
+        let value = V::from_u32(field.insert(0, value.to_u32()));
+        self.update_bits(bus, register, mask, value)
+    }
+}
+
+/// How a register address is marked as a read or a write on an SPI bus.
+///
+/// The register address is masked with `address_mask`, then `read` or `write` is
+/// set, and `auto_increment` is set for accesses of more than one byte. For
+/// 16-bit register addresses, these are 16-bit masks, and the header is sent most
+/// significant byte first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpiConvention {
+    pub address_mask: u16,
+    pub read: u16,
+    pub write: u16,
+    pub auto_increment: u16,
+}
+
+impl SpiConvention {
+    /// The MSB is set for reads and cleared for writes, e.g. the BME280.
+    pub const MSB_READ: Self = Self {
+        address_mask: 0x7F,
+        read: 0x80,
+        write: 0x00,
+        auto_increment: 0x00,
+    };
+
+    /// The MSB is set for reads, and bit 6 for multi-byte accesses, e.g. the LIS3DH.
+    pub const MSB_READ_BIT6_INCREMENT: Self = Self {
+        address_mask: 0x3F,
+        read: 0x80,
+        write: 0x00,
+        auto_increment: 0x40,
+    };
+
+    /// The MSB is set for writes and cleared for reads, e.g. the RFM69.
+    pub const MSB_WRITE: Self = Self {
+        address_mask: 0x7F,
+        read: 0x00,
+        write: 0x80,
+        auto_increment: 0x00,
+    };
+
+    fn header(&self, register: u16, read: bool, len: usize) -> u16 {
+        let mut header = register & self.address_mask;
+        header |= if read { self.read } else { self.write };
+        if len > 1 {
+            header |= self.auto_increment;
+        }
+        header
+    }
+}
+
+/// Registers of a device on an SPI bus.
+///
+/// Chip select is not handled here, and must be asserted around each call.
+/// Defaults to 8-bit register addresses and big-endian values. 16-bit register
+/// addresses are always sent big-endian, see [SpiConvention].
+#[derive(Debug, Clone, Copy)]
+pub struct SpiRegisters {
+    convention: SpiConvention,
+    address_width: AddressWidth,
+    endian: Endian,
+}
+
+impl SpiRegisters {
+    pub fn new(convention: SpiConvention) -> Self {
+        Self {
+            convention,
+            address_width: AddressWidth::Bits8,
+            endian: Endian::Big,
+        }
+    }
+
+    pub fn address_width(mut self, width: AddressWidth) -> Self {
+        self.address_width = width;
+        self
+    }
+
+    /// The byte order of values. This doesn't change the order of the address header.
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    /// Read consecutive bytes, starting at the given register.
+    pub fn read_bytes<BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        buffer: &mut [u8],
+    ) -> Result<(), BUS::Error>
+    where
+        BUS: spi::Transfer<u8>,
+    {
+        let header = self.convention.header(register, true, buffer.len());
+        let mut frame = encode_address(self.address_width, Endian::Big, header);
+        let header_len = frame.len();
+        frame.resize(header_len + buffer.len(), 0);
+
+        let data = bus.transfer(&mut frame)?;
+        buffer.copy_from_slice(&data[header_len..]);
+        Ok(())
+    }
+
+    /// Write consecutive bytes, starting at the given register.
+    pub fn write_bytes<BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        bytes: &[u8],
+    ) -> Result<(), BUS::Error>
+    where
+        BUS: spi::Write<u8>,
+    {
+        let header = self.convention.header(register, false, bytes.len());
+        let mut frame = encode_address(self.address_width, Endian::Big, header);
+        frame.extend_from_slice(bytes);
+        bus.write(&frame)
+    }
+
+    pub fn read<V, BUS>(&self, bus: &mut BUS, register: u16) -> Result<V, BUS::Error>
+    where
+        V: Value,
+        BUS: spi::Transfer<u8>,
+    {
+        let mut buf = [0u8; 4];
+        self.read_bytes(bus, register, &mut buf[..V::SIZE])?;
+        Ok(V::from_bytes(&buf[..V::SIZE], self.endian))
+    }
+
+    pub fn write<V, BUS>(&self, bus: &mut BUS, register: u16, value: V) -> Result<(), BUS::Error>
+    where
+        V: Value,
+        BUS: spi::Write<u8>,
+    {
+        let mut buf = [0u8; 4];
+        value.to_bytes(self.endian, &mut buf[..V::SIZE]);
+        self.write_bytes(bus, register, &buf[..V::SIZE])
+    }
+
+    /// Replace the bits of a register selected by `mask` with those of `value`,
+    /// returning the new register value.
+    pub fn update_bits<V, BUS, E>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        mask: V,
+        value: V,
+    ) -> Result<V, E>
+    where
+        V: Value,
+        BUS: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    {
+        let old: V = self.read(bus, register)?;
+        let new = apply_mask(old, mask, value);
+        self.write(bus, register, new)?;
+        Ok(new)
+    }
+
+    pub fn read_field<V, BUS>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        field: Field,
+    ) -> Result<V, BUS::Error>
+    where
+        V: Value,
+        BUS: spi::Transfer<u8>,
+    {
+        let value: V = self.read(bus, register)?;
+        Ok(V::from_u32(field.extract(value.to_u32())))
+    }
+
+    /// Set a single field of a register, keeping the other bits. Returns the
+    /// new register value.
+    pub fn write_field<V, BUS, E>(
+        &self,
+        bus: &mut BUS,
+        register: u16,
+        field: Field,
+        value: V,
+    ) -> Result<V, E>
+    where
+        V: Value,
+        BUS: spi::Transfer<u8, Error = E> + spi::Write<u8, Error = E>,
+    {
+        let mask = V::from_u32(field.mask());
+        let value = V::from_u32(field.insert(0, value.to_u32()));
+        self.update_bits(bus, register, mask, value)
+    }
+}
+
+fn encode_address(width: AddressWidth, endian: Endian, register: u16) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 + 4);
+    match width {
+        AddressWidth::Bits8 => out.push(register as u8),
+        AddressWidth::Bits16 => {
+            let mut raw = [0u8; 2];
+            register.to_bytes(endian, &mut raw);
+            out.extend_from_slice(&raw);
+        }
+    }
+    out
+}
+
+fn apply_mask<V: Value>(old: V, mask: V, value: V) -> V {
+    let mask = mask.to_u32();
+    V::from_u32((old.to_u32() & !mask) | (value.to_u32() & mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    /// A bus that records each transaction, and answers reads with `reply`, which
+    /// must be as long as the data read.
+    #[derive(Default)]
+    struct Recorder {
+        frames: Vec<(Option<u8>, Vec<u8>)>,
+        reply: Vec<u8>,
+    }
+
+    impl i2c::Write for Recorder {
+        type Error = Infallible;
+
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Infallible> {
+            self.frames.push((Some(address), bytes.to_vec()));
+            Ok(())
+        }
+    }
+
+    impl i2c::WriteRead for Recorder {
+        type Error = Infallible;
+
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Infallible> {
+            self.frames.push((Some(address), bytes.to_vec()));
+            buffer.copy_from_slice(&self.reply[..buffer.len()]);
+            Ok(())
+        }
+    }
+
+    impl spi::Write<u8> for Recorder {
+        type Error = Infallible;
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Infallible> {
+            self.frames.push((None, words.to_vec()));
+            Ok(())
+        }
+    }
+
+    impl spi::Transfer<u8> for Recorder {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Infallible> {
+            self.frames.push((None, words.to_vec()));
+            let header = words.len() - self.reply.len();
+            words[header..].copy_from_slice(&self.reply);
+            Ok(words)
+        }
+    }
+
+    fn sent(bus: &Recorder) -> Vec<Vec<u8>> {
+        bus.frames.iter().map(|(_, bytes)| bytes.clone()).collect()
+    }
+
+    #[test]
+    fn field_mask() {
+        assert_eq!(Field::new(0, 1).mask(), 0x0000_0001);
+        assert_eq!(Field::new(5, 3).mask(), 0x0000_00E0);
+        assert_eq!(Field::new(0, 8).mask(), 0x0000_00FF);
+        assert_eq!(Field::new(0, 32).mask(), 0xFFFF_FFFF);
+        assert_eq!(Field::new(31, 1).mask(), 0x8000_0000);
+        assert_eq!(Field::new(0, 0).mask(), 0);
+        // Bits above bit 31 are left out, rather than overflowing
+        assert_eq!(Field::new(0, 64).mask(), 0xFFFF_FFFF);
+        assert_eq!(Field::new(0, 255).mask(), 0xFFFF_FFFF);
+        assert_eq!(Field::new(28, 8).mask(), 0xF000_0000);
+        assert_eq!(Field::new(32, 1).mask(), 0);
+    }
+
+    #[test]
+    fn field_extract_insert() {
+        let osrs_t = Field::new(5, 3);
+        assert_eq!(osrs_t.extract(0b0100_0111), 0b010);
+        assert_eq!(osrs_t.insert(0b0000_0111, 0b101), 0b1010_0111);
+        assert_eq!(osrs_t.insert(0b1111_1111, 0), 0b0001_1111);
+        // Values too wide for the field are cut off
+        assert_eq!(osrs_t.insert(0, 0xFF), 0b1110_0000);
+
+        let all = Field::new(0, 32);
+        assert_eq!(all.extract(0x1234_5678), 0x1234_5678);
+        assert_eq!(all.insert(0x1234_5678, 0xCAFE_F00D), 0xCAFE_F00D);
+
+        let outside = Field::new(40, 4);
+        assert_eq!(outside.extract(0xFFFF_FFFF), 0);
+        assert_eq!(outside.insert(0x1234_5678, 0xF), 0x1234_5678);
+    }
+
+    #[test]
+    fn encode_addresses() {
+        let encode = |width, endian| encode_address(width, endian, 0x1234);
+        assert_eq!(encode(AddressWidth::Bits8, Endian::Big), [0x34]);
+        assert_eq!(encode(AddressWidth::Bits8, Endian::Little), [0x34]);
+        assert_eq!(encode(AddressWidth::Bits16, Endian::Big), [0x12, 0x34]);
+        assert_eq!(encode(AddressWidth::Bits16, Endian::Little), [0x34, 0x12]);
+    }
+
+    #[test]
+    fn i2c_registers() {
+        let mut bus = Recorder {
+            reply: vec![0xAB, 0xCD],
+            ..Default::default()
+        };
+        let regs = I2cRegisters::new(0x76);
+        assert_eq!(regs.read::<u8, _>(&mut bus, 0xD0).unwrap(), 0xAB);
+        assert_eq!(regs.read::<u16, _>(&mut bus, 0xD0).unwrap(), 0xABCD);
+        regs.write(&mut bus, 0xF4, 0x1234u16).unwrap();
+        assert_eq!(bus.frames[0], (Some(0x76), vec![0xD0]));
+        assert_eq!(bus.frames[2], (Some(0x76), vec![0xF4, 0x12, 0x34]));
+
+        let mut bus = Recorder {
+            reply: vec![0xAB, 0xCD],
+            ..Default::default()
+        };
+        let regs = I2cRegisters::new(0x50)
+            .address_width(AddressWidth::Bits16)
+            .endian(Endian::Little);
+        assert_eq!(regs.read::<u16, _>(&mut bus, 0x0102).unwrap(), 0xCDAB);
+        regs.write(&mut bus, 0x0102, 0x1234u16).unwrap();
+        assert_eq!(sent(&bus), [vec![0x02, 0x01], vec![0x02, 0x01, 0x34, 0x12]]);
+    }
+
+    #[test]
+    fn i2c_fields() {
+        let mut bus = Recorder {
+            reply: vec![0b0100_0111],
+            ..Default::default()
+        };
+        let regs = I2cRegisters::new(0x76);
+        let osrs_t = Field::new(5, 3);
+        assert_eq!(
+            regs.read_field::<u8, _>(&mut bus, 0xF4, osrs_t).unwrap(),
+            0b010
+        );
+        let new = regs.write_field(&mut bus, 0xF4, osrs_t, 0b101u8).unwrap();
+        assert_eq!(new, 0b1010_0111);
+        assert_eq!(bus.frames.last().unwrap().1, [0xF4, 0b1010_0111]);
+    }
+
+    #[test]
+    fn spi_msb_read() {
+        let mut bus = Recorder {
+            reply: vec![0x60],
+            ..Default::default()
+        };
+        let regs = SpiRegisters::new(SpiConvention::MSB_READ);
+        assert_eq!(regs.read::<u8, _>(&mut bus, 0xD0).unwrap(), 0x60);
+        regs.write(&mut bus, 0xF4, 0x27u8).unwrap();
+        bus.reply = vec![0x60, 0x61];
+        let mut buf = [0; 2];
+        regs.read_bytes(&mut bus, 0x88, &mut buf).unwrap();
+        assert_eq!(buf, [0x60, 0x61]);
+        assert_eq!(
+            sent(&bus),
+            [vec![0xD0, 0x00], vec![0x74, 0x27], vec![0x88, 0x00, 0x00]]
+        );
+    }
+
+    #[test]
+    fn spi_msb_read_bit6_increment() {
+        let mut bus = Recorder {
+            reply: vec![0x33],
+            ..Default::default()
+        };
+        let regs = SpiRegisters::new(SpiConvention::MSB_READ_BIT6_INCREMENT);
+        assert_eq!(regs.read::<u8, _>(&mut bus, 0x0F).unwrap(), 0x33);
+        regs.write(&mut bus, 0x20, 0x57u8).unwrap();
+        regs.write(&mut bus, 0x20, 0x5701u16).unwrap();
+        bus.reply = vec![0x00, 0x01];
+        regs.read::<u16, _>(&mut bus, 0x28).unwrap();
+        // Addresses are masked to 6 bits
+        regs.write(&mut bus, 0xFF, 0x00u8).unwrap();
+        assert_eq!(
+            sent(&bus),
+            [
+                vec![0x8F, 0x00],
+                vec![0x20, 0x57],
+                vec![0x60, 0x57, 0x01],
+                vec![0xE8, 0x00, 0x00],
+                vec![0x3F, 0x00],
+            ]
+        );
+    }
+
+    #[test]
+    fn spi_msb_write() {
+        let mut bus = Recorder {
+            reply: vec![0x24],
+            ..Default::default()
+        };
+        let regs = SpiRegisters::new(SpiConvention::MSB_WRITE);
+        assert_eq!(regs.read::<u8, _>(&mut bus, 0x10).unwrap(), 0x24);
+        regs.write(&mut bus, 0x01, 0x04u8).unwrap();
+        regs.write(&mut bus, 0x81, 0x04u8).unwrap();
+        assert_eq!(
+            sent(&bus),
+            [vec![0x10, 0x00], vec![0x81, 0x04], vec![0x81, 0x04]]
+        );
+    }
+
+    #[test]
+    fn spi_16_bit_addresses() {
+        let convention = SpiConvention {
+            address_mask: 0x7FFF,
+            read: 0x8000,
+            write: 0x0000,
+            auto_increment: 0x0000,
+        };
+        let mut bus = Recorder {
+            reply: vec![0x12, 0x34],
+            ..Default::default()
+        };
+        let regs = SpiRegisters::new(convention).address_width(AddressWidth::Bits16);
+        assert_eq!(regs.read::<u16, _>(&mut bus, 0x0123).unwrap(), 0x1234);
+        regs.write(&mut bus, 0x0123, 0xBEEFu16).unwrap();
+        // Only the value is little-endian, the read bit still goes out first
+        let regs = regs.endian(Endian::Little);
+        assert_eq!(regs.read::<u16, _>(&mut bus, 0x0123).unwrap(), 0x3412);
+        regs.write(&mut bus, 0x0123, 0xBEEFu16).unwrap();
+        assert_eq!(
+            sent(&bus),
+            [
+                vec![0x81, 0x23, 0x00, 0x00],
+                vec![0x01, 0x23, 0xBE, 0xEF],
+                vec![0x81, 0x23, 0x00, 0x00],
+                vec![0x01, 0x23, 0xEF, 0xBE],
+            ]
+        );
+    }
+
+    #[test]
+    fn spi_update_bits() {
+        let mut bus = Recorder {
+            reply: vec![0b1111_0000],
+            ..Default::default()
+        };
+        let regs = SpiRegisters::new(SpiConvention::MSB_READ);
+        let new = regs
+            .update_bits(&mut bus, 0x20, 0b0011_1100u8, 0b0000_0100)
+            .unwrap();
+        assert_eq!(new, 0b1100_0100);
+        assert_eq!(sent(&bus), [vec![0xA0, 0x00], vec![0x20, 0b1100_0100]]);
+    }
+}
@@ -0,0 +1,184 @@
+//! SMBus devices on the I2C buses of a [Machine]
+
+use phm_icd::{ToMcu, ToMcuSmbus, ToPc, ToPcSmbus};
+
+use crate::{Error, Machine};
+
+/// The most bytes in an SMBus block.
+pub const MAX_BLOCK: usize = 32;
+
+/// An SMBus device on an I2C bus of a [Machine].
+///
+/// The SMBus transactions are executed by the worker, which also calculates and
+/// checks the packet error codes when enabled:
+///
+/// ```no_run
+/// # fn demo(machine: &mut phm::Machine) -> Result<(), phm::Error> {
+/// use phm::smbus::Smbus;
+///
+/// let mut gauge = Smbus::new(machine, 0x0B).pec(true);
+/// let voltage_mv = gauge.read_word(0x09)?;
+/// let name = gauge.block_read(0x21)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Smbus<'a> {
+    machine: &'a mut Machine,
+    bus: u8,
+    address: u8,
+    pec: bool,
+}
+
+impl<'a> Smbus<'a> {
+    /// A device on the first I2C bus.
+    pub fn new(machine: &'a mut Machine, address: u8) -> Self {
+        Self {
+            machine,
+            bus: 0,
+            address,
+            pec: false,
+        }
+    }
+
+    /// Use the I2C bus with index `bus` instead of the first one.
+    pub fn bus(mut self, bus: u8) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    /// Use packet error checking, defaults to off.
+    pub fn pec(mut self, pec: bool) -> Self {
+        self.pec = pec;
+        self
+    }
+
+    /// Send only the address, with the R/W bit set for a read if `read` is true.
+    ///
+    /// Fails with [Error::Unsupported] on workers whose I2C driver can't send the
+    /// address alone. The blackpill worker only supports writes, the others neither.
+    pub fn quick(&mut self, read: bool) -> Result<(), Error> {
+        self.complete(ToMcuSmbus::Quick {
+            addr: self.address,
+            read,
+        })
+    }
+
+    pub fn send_byte(&mut self, data: u8) -> Result<(), Error> {
+        self.complete(ToMcuSmbus::SendByte {
+            addr: self.address,
+            data,
+            pec: self.pec,
+        })
+    }
+
+    pub fn receive_byte(&mut self) -> Result<u8, Error> {
+        self.byte(ToMcuSmbus::ReceiveByte {
+            addr: self.address,
+            pec: self.pec,
+        })
+    }
+
+    pub fn write_byte(&mut self, command: u8, data: u8) -> Result<(), Error> {
+        self.complete(ToMcuSmbus::WriteByte {
+            addr: self.address,
+            command,
+            data,
+            pec: self.pec,
+        })
+    }
+
+    pub fn read_byte(&mut self, command: u8) -> Result<u8, Error> {
+        self.byte(ToMcuSmbus::ReadByte {
+            addr: self.address,
+            command,
+            pec: self.pec,
+        })
+    }
+
+    pub fn write_word(&mut self, command: u8, data: u16) -> Result<(), Error> {
+        self.complete(ToMcuSmbus::WriteWord {
+            addr: self.address,
+            command,
+            data,
+            pec: self.pec,
+        })
+    }
+
+    pub fn read_word(&mut self, command: u8) -> Result<u16, Error> {
+        self.word(ToMcuSmbus::ReadWord {
+            addr: self.address,
+            command,
+            pec: self.pec,
+        })
+    }
+
+    /// Write a word, and read the word sent back by the device.
+    pub fn process_call(&mut self, command: u8, data: u16) -> Result<u16, Error> {
+        self.word(ToMcuSmbus::ProcessCall {
+            addr: self.address,
+            command,
+            data,
+            pec: self.pec,
+        })
+    }
+
+    /// Write a block of up to [MAX_BLOCK] bytes.
+    pub fn block_write(&mut self, command: u8, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_BLOCK {
+            return Err(Error::InvalidParameter);
+        }
+        self.complete(ToMcuSmbus::BlockWrite {
+            addr: self.address,
+            command,
+            data: data.iter().cloned().collect(),
+            pec: self.pec,
+        })
+    }
+
+    /// Read a block, with the length given by the device.
+    ///
+    /// Only supported by workers whose I2C driver can read the length first, see
+    /// `SmbusBlockRead` in `phm-worker`.
+    pub fn block_read(&mut self, command: u8) -> Result<Vec<u8>, Error> {
+        let addr = self.address;
+        let msg = ToMcu::Smbus {
+            bus: self.bus,
+            cmd: ToMcuSmbus::BlockRead {
+                addr,
+                command,
+                pec: self.pec,
+            },
+        };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Smbus(ToPcSmbus::Block { addr: a, data }) if a == addr => Some(data.to_vec()),
+            _ => None,
+        })
+    }
+
+    fn complete(&mut self, cmd: ToMcuSmbus) -> Result<(), Error> {
+        let addr = self.address;
+        let msg = ToMcu::Smbus { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Smbus(ToPcSmbus::Complete { addr: a }) if a == addr => Some(()),
+            _ => None,
+        })
+    }
+
+    fn byte(&mut self, cmd: ToMcuSmbus) -> Result<u8, Error> {
+        let addr = self.address;
+        let msg = ToMcu::Smbus { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Smbus(ToPcSmbus::Byte { addr: a, data }) if a == addr => Some(data),
+            _ => None,
+        })
+    }
+
+    fn word(&mut self, cmd: ToMcuSmbus) -> Result<u16, Error> {
+        let addr = self.address;
+        let msg = ToMcu::Smbus { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Smbus(ToPcSmbus::Word { addr: a, data }) if a == addr => Some(data),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,436 @@
+//! The SPI buses of a [Machine], and the devices sharing them
+
+use std::cell::RefCell;
+
+use embedded_hal::blocking::spi::{Operation as OperationV02, Transactional, Transfer, Write};
+use embedded_hal_1::spi::{ErrorType, Operation, SpiDevice as SpiDeviceV1};
+use heapless::Vec as HVec;
+use phm_icd::{SpiOp, ToMcu, ToMcuSpi, ToPc, ToPcSpi};
+
+use crate::{Error, Machine};
+
+/// The most bytes written or read by a single transaction message.
+const MAX_DATA: usize = 64;
+/// The most operations in a single transaction message.
+const MAX_OPS: usize = 16;
+
+/// One of the SPI buses of a [Machine], see [Machine::spi].
+///
+/// The `embedded-hal` SPI traits of [Machine] itself use the first bus.
+pub struct SpiBus<'a> {
+    machine: &'a mut Machine,
+    bus: u8,
+}
+
+impl<'a> SpiBus<'a> {
+    pub(crate) fn new(machine: &'a mut Machine, bus: u8) -> Self {
+        Self { machine, bus }
+    }
+
+    /// Assert the chip select line `cs` of the worker, until [SpiBus::release]
+    /// is called.
+    ///
+    /// See [SpiDevice] for a handle that does this around each transaction.
+    pub fn select(&mut self, cs: u8) -> Result<(), Error> {
+        self.command(ToMcuSpi::Select { cs }, |msg| match msg {
+            ToPcSpi::Selected { cs: selected } if selected == cs => Some(()),
+            _ => None,
+        })
+    }
+
+    /// Deassert all chip select lines of the worker.
+    pub fn release(&mut self) -> Result<(), Error> {
+        self.command(ToMcuSpi::Release, |msg| match msg {
+            ToPcSpi::Released => Some(()),
+            _ => None,
+        })
+    }
+
+    /// Execute SPI operations as a single transaction, with the chip select line `cs`
+    /// (if any) asserted throughout.
+    ///
+    /// The operations are executed by the worker with as few messages as possible,
+    /// usually one. Several messages are sent without waiting for each response, as far
+    /// as the queue of the worker allows.
+    pub fn transaction(
+        &mut self,
+        cs: Option<u8>,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Error> {
+        let batches = batches(operations);
+
+        let data_read = match (cs, batches.len()) {
+            (_, 1) => self.batch(cs, &batches[0])?,
+            (None, _) => self.batches(&batches)?,
+            (Some(cs), _) => {
+                // Hold the chip select across all of the messages
+                self.select(cs)?;
+                let result = self.batches(&batches);
+                let released = self.release();
+                let data_read = result?;
+                released?;
+                data_read
+            }
+        };
+
+        distribute(operations, &data_read);
+        Ok(())
+    }
+
+    /// Write words of `bits` bits, from 9 to 16, in the low bits of each `u16`.
+    ///
+    /// Only supported by workers with this word size in
+    /// [Capabilities::spi_word_sizes](phm_icd::Capabilities::spi_word_sizes).
+    pub fn write_words(&mut self, bits: u8, words: &[u16]) -> Result<(), Error> {
+        let cmds = words
+            .chunks(32)
+            .map(|chunk| ToMcuSpi::WriteU16 {
+                bits,
+                output: chunk.iter().cloned().collect(),
+            })
+            .collect();
+        self.commands(cmds, |msg| match msg {
+            ToPcSpi::WriteComplete => Some(()),
+            _ => None,
+        })?;
+        Ok(())
+    }
+
+    /// Transfer words of `bits` bits, from 9 to 16, in the low bits of each `u16`,
+    /// replacing them with the words read.
+    ///
+    /// Only supported by workers with this word size in
+    /// [Capabilities::spi_word_sizes](phm_icd::Capabilities::spi_word_sizes).
+    pub fn transfer_words(&mut self, bits: u8, words: &mut [u16]) -> Result<(), Error> {
+        let cmds = words
+            .chunks(32)
+            .map(|chunk| ToMcuSpi::TransferU16 {
+                bits,
+                output: chunk.iter().cloned().collect(),
+            })
+            .collect();
+        let reads = self.commands(cmds, |msg| match msg {
+            ToPcSpi::TransferU16 { data_read } => Some(data_read),
+            _ => None,
+        })?;
+        for (chunk, data_read) in words.chunks_mut(32).zip(reads) {
+            if data_read.len() != chunk.len() {
+                return Err(Error::ResponseError);
+            }
+            chunk.copy_from_slice(&data_read);
+        }
+        Ok(())
+    }
+
+    fn batches(&mut self, batches: &[Batch]) -> Result<Vec<u8>, Error> {
+        let cmds = batches.iter().map(|batch| batch.command(None)).collect();
+        let reads = self.commands(cmds, |msg| match msg {
+            ToPcSpi::Transaction { data_read } => Some(data_read),
+            _ => None,
+        })?;
+
+        let mut data_read = Vec::new();
+        for (batch, read) in batches.iter().zip(reads) {
+            if read.len() != batch.read_len {
+                return Err(Error::ResponseError);
+            }
+            data_read.extend(read);
+        }
+        Ok(data_read)
+    }
+
+    fn batch(&mut self, cs: Option<u8>, batch: &Batch) -> Result<Vec<u8>, Error> {
+        let data_read = self.command(batch.command(cs), |msg| match msg {
+            ToPcSpi::Transaction { data_read } => Some(data_read),
+            _ => None,
+        })?;
+
+        match data_read.len() == batch.read_len {
+            true => Ok(data_read.to_vec()),
+            false => Err(Error::ResponseError),
+        }
+    }
+
+    fn command<T>(
+        &mut self,
+        cmd: ToMcuSpi,
+        mut response: impl FnMut(ToPcSpi) -> Option<T>,
+    ) -> Result<T, Error> {
+        let msg = ToMcu::Spi { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Spi(msg) => response(msg),
+            _ => None,
+        })
+    }
+
+    /// Send several commands, see [Machine::commands].
+    fn commands<T>(
+        &mut self,
+        cmds: Vec<ToMcuSpi>,
+        mut response: impl FnMut(ToPcSpi) -> Option<T>,
+    ) -> Result<Vec<T>, Error> {
+        let msgs: Vec<ToMcu> = cmds
+            .into_iter()
+            .map(|cmd| ToMcu::Spi { bus: self.bus, cmd })
+            .collect();
+        self.machine.commands(&msgs, |msg| match msg {
+            ToPc::Spi(msg) => response(msg),
+            _ => None,
+        })
+    }
+}
+
+impl Write<u8> for SpiBus<'_> {
+    type Error = Error;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let cmds = bytes
+            .chunks(MAX_DATA)
+            .map(|chunk| ToMcuSpi::Write {
+                output: chunk.iter().cloned().collect(),
+            })
+            .collect();
+        self.commands(cmds, |msg| match msg {
+            ToPcSpi::WriteComplete => Some(()),
+            _ => None,
+        })?;
+        Ok(())
+    }
+}
+
+impl Transfer<u8> for SpiBus<'_> {
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, buffer: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        let cmds = buffer
+            .chunks(MAX_DATA)
+            .map(|chunk| ToMcuSpi::Transfer {
+                output: chunk.iter().cloned().collect(),
+            })
+            .collect();
+        let reads = self.commands(cmds, |msg| match msg {
+            ToPcSpi::Transfer { data_read } => Some(data_read),
+            _ => None,
+        })?;
+        for (chunk, data_read) in buffer.chunks_mut(MAX_DATA).zip(reads) {
+            if data_read.len() != chunk.len() {
+                return Err(Error::ResponseError);
+            }
+            chunk.copy_from_slice(&data_read);
+        }
+        Ok(buffer)
+    }
+}
+
+/// 16-bit words are only supported by some workers, see [Machine::capabilities].
+impl Write<u16> for SpiBus<'_> {
+    type Error = Error;
+
+    fn write(&mut self, words: &[u16]) -> Result<(), Error> {
+        self.write_words(16, words)
+    }
+}
+
+/// 16-bit words are only supported by some workers, see [Machine::capabilities].
+impl Transfer<u16> for SpiBus<'_> {
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Error> {
+        self.transfer_words(16, words)?;
+        Ok(words)
+    }
+}
+
+/// A device on an SPI bus of a [Machine], selected by one of the chip select
+/// lines of the worker.
+///
+/// The chip select line is asserted for the duration of each transaction. Several
+/// devices can share one machine:
+///
+/// ```no_run
+/// # fn demo(machine: phm::Machine) {
+/// use std::cell::RefCell;
+/// use phm::spi::SpiDevice;
+///
+/// let machine = RefCell::new(machine);
+/// let flash = SpiDevice::new(&machine, 0);
+/// let radio = SpiDevice::new(&machine, 1).bus(1);
+/// # }
+/// ```
+pub struct SpiDevice<'a> {
+    machine: &'a RefCell<Machine>,
+    bus: u8,
+    cs: u8,
+}
+
+impl<'a> SpiDevice<'a> {
+    /// A device on the first SPI bus.
+    pub fn new(machine: &'a RefCell<Machine>, cs: u8) -> Self {
+        Self {
+            machine,
+            bus: 0,
+            cs,
+        }
+    }
+
+    /// Use the SPI bus with index `bus` instead of the first one.
+    pub fn bus(mut self, bus: u8) -> Self {
+        self.bus = bus;
+        self
+    }
+}
+
+impl ErrorType for SpiDevice<'_> {
+    type Error = Error;
+}
+
+impl SpiDeviceV1 for SpiDevice<'_> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Error> {
+        self.machine
+            .borrow_mut()
+            .spi(self.bus)
+            .transaction(Some(self.cs), operations)
+    }
+}
+
+impl Transactional<u8> for SpiDevice<'_> {
+    type Error = Error;
+
+    fn exec(&mut self, operations: &mut [OperationV02<'_, u8>]) -> Result<(), Error> {
+        let mut operations: Vec<Operation<'_, u8>> = operations
+            .iter_mut()
+            .map(|op| match op {
+                OperationV02::Write(bytes) => Operation::Write(bytes),
+                OperationV02::Transfer(buffer) => Operation::TransferInPlace(buffer),
+            })
+            .collect();
+        SpiDeviceV1::transaction(self, &mut operations)
+    }
+}
+
+impl Write<u8> for SpiDevice<'_> {
+    type Error = Error;
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        SpiDeviceV1::write(self, bytes)
+    }
+}
+
+impl Transfer<u8> for SpiDevice<'_> {
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, buffer: &'w mut [u8]) -> Result<&'w [u8], Error> {
+        SpiDeviceV1::transfer_in_place(self, buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// A transaction message, as sent to the worker.
+#[derive(Default)]
+struct Batch {
+    pub ops: HVec<SpiOp, MAX_OPS>,
+    pub output: HVec<u8, MAX_DATA>,
+    pub read_len: usize,
+}
+
+impl Batch {
+    fn push(&mut self, op: SpiOp, output: &[u8], read_len: usize) -> bool {
+        let fits = !self.ops.is_full()
+            && self.output.len() + output.len() <= MAX_DATA
+            && self.read_len + read_len <= MAX_DATA;
+        if fits {
+            self.ops.push(op).ok();
+            self.output.extend_from_slice(output).ok();
+            self.read_len += read_len;
+        }
+        fits
+    }
+
+    fn command(&self, cs: Option<u8>) -> ToMcuSpi {
+        ToMcuSpi::Transaction {
+            cs,
+            ops: self.ops.clone(),
+            output: self.output.clone(),
+        }
+    }
+}
+
+/// Split operations into as few transaction messages as possible.
+fn batches(operations: &[Operation<'_, u8>]) -> Vec<Batch> {
+    let mut batches = vec![Batch::default()];
+    let mut push = |op: SpiOp, output: &[u8], read_len: usize| {
+        let batch = batches.last_mut().unwrap();
+        if !batch.push(op, output, read_len) {
+            let mut batch = Batch::default();
+            batch.push(op, output, read_len);
+            batches.push(batch);
+        }
+    };
+
+    for op in operations {
+        match op {
+            Operation::Write(bytes) => {
+                for chunk in bytes.chunks(MAX_DATA) {
+                    push(
+                        SpiOp::Write {
+                            len: chunk.len() as u8,
+                        },
+                        chunk,
+                        0,
+                    );
+                }
+            }
+            Operation::Read(buffer) => {
+                for chunk in buffer.chunks(MAX_DATA) {
+                    push(
+                        SpiOp::Read {
+                            len: chunk.len() as u8,
+                        },
+                        &[],
+                        chunk.len(),
+                    );
+                }
+            }
+            Operation::Transfer(read, write) => {
+                let len = read.len().max(write.len());
+                for start in (0..len).step_by(MAX_DATA) {
+                    let end = (start + MAX_DATA).min(len);
+                    let read_len = read.len().clamp(start, end) - start;
+                    let output = &write[write.len().min(start)..write.len().min(end)];
+                    let op = SpiOp::Transfer {
+                        read_len: read_len as u8,
+                        write_len: output.len() as u8,
+                    };
+                    push(op, output, read_len);
+                }
+            }
+            Operation::TransferInPlace(buffer) => {
+                for chunk in buffer.chunks(MAX_DATA) {
+                    let op = SpiOp::TransferInPlace {
+                        len: chunk.len() as u8,
+                    };
+                    push(op, chunk, chunk.len());
+                }
+            }
+            Operation::DelayNs(ns) => push(SpiOp::DelayNs { ns: *ns }, &[], 0),
+        }
+    }
+
+    batches
+}
+
+/// Copy the bytes read by a transaction back into its operations.
+fn distribute(operations: &mut [Operation<'_, u8>], data_read: &[u8]) {
+    let mut data_read = data_read;
+    for op in operations.iter_mut() {
+        let buffer: &mut [u8] = match op {
+            Operation::Read(buffer) => buffer,
+            Operation::Transfer(read, _) => read,
+            Operation::TransferInPlace(buffer) => buffer,
+            Operation::Write(_) | Operation::DelayNs(_) => continue,
+        };
+        let (bytes, rest) = data_read.split_at(buffer.len());
+        buffer.copy_from_slice(bytes);
+        data_read = rest;
+    }
+}
@@ -0,0 +1,133 @@
+//! The UARTs of a [Machine]
+
+use std::collections::VecDeque;
+
+use phm_icd::{ToMcu, ToMcuUart, ToPc, ToPcUart};
+
+use crate::{Error, Machine};
+
+/// One of the UARTs of a [Machine], see [Machine::uart].
+///
+/// The `embedded-hal` serial traits of [Machine] itself use the first UART.
+pub struct UartBus<'a> {
+    machine: &'a mut Machine,
+    bus: u8,
+}
+
+impl<'a> UartBus<'a> {
+    pub(crate) fn new(machine: &'a mut Machine, bus: u8) -> Self {
+        Self { machine, bus }
+    }
+
+    fn command<T>(
+        &mut self,
+        cmd: ToMcuUart,
+        mut response: impl FnMut(ToPcUart) -> Option<T>,
+    ) -> Result<T, Error> {
+        let msg = ToMcu::Uart { bus: self.bus, cmd };
+        self.machine.command(&msg, |msg| match msg {
+            ToPc::Uart(msg) => response(msg),
+            _ => None,
+        })
+    }
+
+    /// The bytes received from the worker, but not read yet.
+    fn rx_buf(&mut self) -> &mut VecDeque<u8> {
+        self.machine.uart_rx_bufs.entry(self.bus).or_default()
+    }
+}
+
+impl embedded_hal::blocking::serial::Write<u8> for UartBus<'_> {
+    type Error = Error;
+
+    /// Write the bytes in commands of up to 64 bytes, sent as far as the queue of
+    /// the worker allows.
+    fn bwrite_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        let msgs: Vec<ToMcu> = bytes
+            .chunks(64)
+            .map(|chunk| ToMcu::Uart {
+                bus: self.bus,
+                cmd: ToMcuUart::Write {
+                    output: chunk.iter().cloned().collect(),
+                },
+            })
+            .collect();
+        self.machine.commands(&msgs, |msg| match msg {
+            ToPc::Uart(ToPcUart::WriteComplete) => Some(()),
+            _ => None,
+        })?;
+        Ok(())
+    }
+
+    fn bflush(&mut self) -> Result<(), Self::Error> {
+        self.command(ToMcuUart::Flush, |msg| match msg {
+            ToPcUart::WriteComplete => Some(()),
+            _ => None,
+        })
+    }
+}
+
+impl embedded_hal::serial::Write<u8> for UartBus<'_> {
+    type Error = Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        embedded_hal::blocking::serial::Write::<u8>::bwrite_all(self, &[byte])
+            .map_err(|_| nb::Error::WouldBlock)
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        embedded_hal::blocking::serial::Write::<u8>::bflush(self).map_err(|_| nb::Error::WouldBlock)
+    }
+}
+
+impl embedded_hal::serial::Read<u8> for UartBus<'_> {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        if let Some(byte) = self.rx_buf().pop_front() {
+            return Ok(byte);
+        }
+
+        let data_read = self.command(ToMcuUart::Read, |msg| match msg {
+            ToPcUart::Read { data_read } => Some(data_read),
+            _ => None,
+        })?;
+        let rx_buf = self.rx_buf();
+        rx_buf.extend(data_read);
+        rx_buf.pop_front().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use embedded_hal::blocking::serial::Write;
+    use phm_icd::{ToMcu, ToMcuUart, ToPc, ToPcUart};
+
+    use crate::mock;
+
+    #[test]
+    fn long_writes_are_split() {
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let log = written.clone();
+        let mut machine = mock::machine(move |cmd| match cmd {
+            ToMcu::Uart {
+                cmd: ToMcuUart::Write { output },
+                ..
+            } => {
+                log.lock().unwrap().push(output.to_vec());
+                Ok(ToPc::Uart(ToPcUart::WriteComplete))
+            }
+            _ => Err(phm_icd::Error::Unsupported),
+        });
+
+        let bytes: Vec<u8> = (0..=255).collect();
+        machine.uart(0).bwrite_all(&bytes).unwrap();
+
+        let written = written.lock().unwrap();
+        let sizes: Vec<usize> = written.iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 64, 64, 64]);
+        assert_eq!(written.concat(), bytes);
+    }
+}